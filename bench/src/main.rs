@@ -0,0 +1,49 @@
+use std::time::Instant;
+
+use machine::machine::{config::MachineConfig, machine::Machine};
+
+const ITERATIONS: u64 = 1_000_000;
+// DUP DUP XOR NOT ADD INC JNZ
+const LOOP_BODY: u64 = 7;
+
+/// counts the top of the stack down to zero, bumping R0 on every iteration:
+///
+///     PUSH n
+/// loop:
+///     DUP, DUP, XOR, NOT, ADD   ; n + (-1), sets the flags
+///     INC R0
+///     JNZ loop
+///     HLT
+fn tight_loop(n: u64) -> Vec<u64> {
+    vec![
+        1, 0x01, 3, n,
+        1, 0x07,
+        1, 0x07,
+        1, 0x15,
+        1, 0x14,
+        1, 0x04,
+        1, 0x09, 0, 0,
+        1, 0x0d, 3, 10,
+        1, 0x1a,
+    ]
+}
+
+fn run(name: &str, config: MachineConfig) {
+    let mut machine = Machine::with_config(config).unwrap();
+    let fid = machine.spawn().unwrap();
+    machine.write_bytecodes(fid, &tight_loop(ITERATIONS)).unwrap();
+
+    let start = Instant::now();
+    machine.execute().unwrap();
+    let elapsed = start.elapsed();
+
+    let instructions = ITERATIONS * LOOP_BODY + 2;
+    println!("{}: {} instructions in {:.3?} ({:.2} M instr/s)",
+        name, instructions, elapsed, instructions as f64 / elapsed.as_secs_f64() / 1e6);
+}
+
+fn main() {
+    run("tight_loop, decoding every step", MachineConfig { decode_cache: false, tier_up_threshold: None, ..Default::default() });
+    run("tight_loop, decode cache", MachineConfig { decode_cache: true, tier_up_threshold: None, ..Default::default() });
+    run("tight_loop, closure tier", MachineConfig { tier_up_threshold: Some(1000), ..Default::default() });
+}
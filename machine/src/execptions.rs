@@ -1,3 +1,7 @@
+use std::{error::Error, fmt};
+
+use crate::{fiber::{capability::Capability, fiber::{Flag, Reg, RegisterSnapshot}}, opcode::instruction::Instruction};
+
 #[derive(Debug)]
 pub enum MachineError {
     InvalidAddress(Option<String>),
@@ -10,4 +14,177 @@ pub enum MachineError {
     InvalidFiberState,
     InvalidBytecodeDataType,
     InvalidFiber,
-}
\ No newline at end of file
+    TypeMismatch(Option<String>),
+    FloatingPoint(Option<String>),
+    /// ENDTRY without a matching TRY
+    InvalidHandler,
+    /// raised by THROW with a code chosen by the program
+    Thrown(u64),
+    /// rejected by the verifier
+    InvalidBytecode(Option<String>),
+    /// the fiber used up its instruction budget
+    OutOfFuel,
+    /// SYSCALL of a native that isn't registered or that broke its signature
+    InvalidSyscall(Option<String>),
+    /// raised by a native with a code chosen by the host
+    Native(u64, Option<String>),
+    /// OUT or IN on a port that doesn't exist or goes the other way
+    InvalidDevice(Option<String>),
+    /// the fiber lacks the capability for what it tried
+    CapabilityDenied(Capability),
+    /// no reply to a CALLR before its timeout
+    CallTimeout,
+    /// the actor a CALLR went to faulted before replying, or couldn't be reached
+    RemoteFault(Option<String>),
+    /// REPLY without a request to answer
+    InvalidReply,
+    /// operands that don't fit the opcode
+    InvalidInstruction(Option<String>),
+}
+
+/// set in the code a TRY handler gets for a THROW
+pub const THROWN: u64 = 1 << 63;
+/// set in the code a TRY handler gets for an error raised by a native
+pub const NATIVE: u64 = 1 << 62;
+/// bits left to the code chosen by THROW or the native
+pub const CODE_MASK: u64 = NATIVE - 1;
+
+impl MachineError {
+    /// Code pushed for a TRY handler. Machine faults use small fixed codes
+    /// with the top two bits clear. THROW and native codes keep their low 62
+    /// bits and get `THROWN` or `NATIVE` on top, so `THROW 3` can't pass for
+    /// a machine fault or a native error.
+    pub fn code(&self) -> u64 {
+        match self {
+            Self::InvalidAddress(_) => 0x01,
+            Self::InsufficientMemory(_) => 0x02,
+            Self::InvalidPointer(_) => 0x03,
+            Self::StackOverflow => 0x04,
+            Self::StackUnderflow => 0x05,
+            Self::InvalidRegister => 0x06,
+            Self::InvalidOpcode(_) => 0x07,
+            Self::InvalidFiberState => 0x08,
+            Self::InvalidBytecodeDataType => 0x09,
+            Self::InvalidFiber => 0x0a,
+            Self::TypeMismatch(_) => 0x0b,
+            Self::FloatingPoint(_) => 0x0c,
+            Self::InvalidHandler => 0x0d,
+            Self::InvalidBytecode(_) => 0x0e,
+            Self::OutOfFuel => 0x0f,
+            Self::InvalidSyscall(_) => 0x10,
+            Self::Native(code, _) => code & CODE_MASK | NATIVE,
+            Self::InvalidDevice(_) => 0x11,
+            Self::CapabilityDenied(_) => 0x12,
+            Self::CallTimeout => 0x13,
+            Self::RemoteFault(_) => 0x14,
+            Self::InvalidReply => 0x15,
+            Self::InvalidInstruction(_) => 0x16,
+            Self::Thrown(code) => code & CODE_MASK | THROWN,
+        }
+    }
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, detail) = match self {
+            Self::InvalidAddress(detail) => ("invalid address", detail.as_deref()),
+            Self::InsufficientMemory(detail) => ("insufficient memory", detail.as_deref()),
+            Self::InvalidPointer(detail) => ("invalid pointer", detail.as_deref()),
+            Self::StackOverflow => ("stack overflow", None),
+            Self::StackUnderflow => ("stack underflow", None),
+            Self::InvalidRegister => ("invalid register", None),
+            Self::InvalidOpcode(detail) => ("invalid opcode", detail.as_deref()),
+            Self::InvalidFiberState => ("invalid fiber state", None),
+            Self::InvalidBytecodeDataType => ("invalid bytecode data type", None),
+            Self::InvalidFiber => ("invalid fiber", None),
+            Self::TypeMismatch(detail) => ("type mismatch", detail.as_deref()),
+            Self::FloatingPoint(detail) => ("floating point exception", detail.as_deref()),
+            Self::InvalidHandler => ("ENDTRY without TRY", None),
+            Self::InvalidBytecode(detail) => ("invalid bytecode", detail.as_deref()),
+            Self::OutOfFuel => ("out of fuel", None),
+            Self::InvalidSyscall(detail) => ("invalid syscall", detail.as_deref()),
+            Self::InvalidDevice(detail) => ("invalid device", detail.as_deref()),
+            Self::CallTimeout => ("call timed out", None),
+            Self::RemoteFault(detail) => ("remote fault", detail.as_deref()),
+            Self::InvalidReply => ("REPLY without a request", None),
+            Self::InvalidInstruction(detail) => ("invalid instruction", detail.as_deref()),
+            Self::CapabilityDenied(capability) => return write!(f, "capability denied: {}", capability),
+            Self::Native(code, detail) => return match detail {
+                Some(detail) => write!(f, "native error {:#x}: {}", code, detail),
+                None => write!(f, "native error {:#x}", code),
+            },
+            Self::Thrown(code) => return write!(f, "uncaught exception {:#x}", code),
+        };
+        match detail {
+            Some(detail) => write!(f, "{}: {}", name, detail),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Error for MachineError {}
+
+/// A `MachineError` together with where it happened. Errors raised outside
+/// of a fiber (allocation, loading) carry no context.
+#[derive(Debug)]
+pub struct Fault {
+    pub error: MachineError,
+    pub fiber: Option<u64>,
+    /// address of the faulting instruction in the text section
+    pub pc: Option<u64>,
+    /// `None` when the instruction itself could not be decoded
+    pub instruction: Option<Instruction>,
+    /// registers and flags at the time of the fault
+    pub snapshot: Option<Box<RegisterSnapshot>>,
+    /// return addresses of the active call frames, innermost first. Empty
+    /// until the ISA has calls.
+    pub backtrace: Box<[u64]>,
+}
+
+impl From<MachineError> for Fault {
+    fn from(error: MachineError) -> Self {
+        Self {
+            error,
+            fiber: None,
+            pc: None,
+            instruction: None,
+            snapshot: None,
+            backtrace: Box::new([]),
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(fiber) = self.fiber {
+            write!(f, "\n  fiber: {:#018x}", fiber)?;
+        }
+        if let Some(pc) = self.pc {
+            write!(f, "\n  at: #{:x}", pc)?;
+            match &self.instruction {
+                Some(instr) => write!(f, " {}", instr)?,
+                None => write!(f, " <undecodable>")?,
+            }
+        }
+        if let Some(snapshot) = &self.snapshot {
+            write!(f, "\n  registers: PC={:#x} SP={:#x}", snapshot.get(Reg::PC), snapshot.get(Reg::SP))?;
+            for (idx, val) in snapshot.registers[Reg::R0.index()..].iter().enumerate() {
+                write!(f, " R{}={:#x}", idx, val)?;
+            }
+            write!(f, "\n  flags: Z={} O={} N={} C={}",
+                snapshot.flag(Flag::Zero) as u8, snapshot.flag(Flag::Overflow) as u8,
+                snapshot.flag(Flag::Negative) as u8, snapshot.flag(Flag::Carry) as u8)?;
+        }
+        for (depth, address) in self.backtrace.iter().enumerate() {
+            write!(f, "\n  #{} #{:x}", depth, address)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Fault {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
@@ -1,3 +1,6 @@
 pub mod fiber;
 pub mod stack;
-pub mod section;
\ No newline at end of file
+pub mod section;
+pub mod value;
+pub mod capability;
+pub mod actor;
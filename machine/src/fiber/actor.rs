@@ -0,0 +1,67 @@
+use std::fmt;
+
+/// Names a machine in a cluster. A machine on its own is node 0.
+pub type NodeId = u16;
+
+/// bits of an actor slot holding the fiber id, the node goes above them
+pub const LOCAL_BITS: u32 = 48;
+/// the largest fiber id, ids are drawn from `1..=MAX_LOCAL_ID`
+pub const MAX_LOCAL_ID: u64 = (1 << LOCAL_BITS) - 1;
+
+/// A fiber anywhere in the cluster. Fits a single stack slot, so bytecode
+/// holds one like any other value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ActorId {
+    pub node: NodeId,
+    /// the fiber's id on its node, 0 is the host
+    pub local: u64,
+}
+
+impl ActorId {
+    pub fn new(node: NodeId, local: u64) -> Self {
+        Self { node, local: local & MAX_LOCAL_ID }
+    }
+
+    pub fn to_bits(self) -> u64 {
+        (self.node as u64) << LOCAL_BITS | self.local
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Self { node: (bits >> LOCAL_BITS) as NodeId, local: bits & MAX_LOCAL_ID }
+    }
+}
+
+impl fmt::Display for ActorId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}@{}", self.local, self.node)
+    }
+}
+
+/// Why a message didn't arrive. RECV hands the sender the code and the
+/// actor it couldn't reach, with the zero flag set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// the node is up but the fiber isn't there, or halted
+    DeadActor = 0x01,
+    /// no connection to the node the actor lives on
+    UnknownNode = 0x02,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeadActor => write!(f, "dead actor"),
+            Self::UnknownNode => write!(f, "unknown node"),
+        }
+    }
+}
+
+impl Failure {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0x01 => Some(Self::DeadActor),
+            0x02 => Some(Self::UnknownNode),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::{collections::BTreeSet, fmt};
+
+use crate::fiber::actor::ActorId;
+
+/// Everything of a kind, or only the listed ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Allow {
+    All,
+    Only(BTreeSet<u64>),
+}
+
+impl Allow {
+    pub fn none() -> Self {
+        Self::Only(BTreeSet::new())
+    }
+
+    pub fn only(ids: &[u64]) -> Self {
+        Self::Only(ids.iter().copied().collect())
+    }
+
+    pub fn allows(&self, id: u64) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(ids) => ids.contains(&id),
+        }
+    }
+}
+
+/// Resources a fiber may use, `None` leaves it to the machine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// instructions, see `Fiber::set_fuel`
+    pub fuel: Option<u64>,
+    /// stack size in bytes
+    pub stack: Option<usize>,
+    /// fibers it may SPAWN over its lifetime, together with everything it
+    /// spawns, their children included
+    pub children: Option<usize>,
+}
+
+/// What a fiber may do, fixed when it is spawned. A child spawned by a
+/// fiber gets its parent's and shares its budget of children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// natives reachable with SYSCALL
+    pub syscalls: Allow,
+    /// ports reachable with OUT and IN
+    pub devices: Allow,
+    /// actors it may SEND to besides its parent and its own children, by
+    /// `ActorId::to_bits`, which is the plain fiber id on node 0
+    pub peers: Allow,
+    pub spawn: bool,
+    pub limits: Limits,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl Capabilities {
+    /// no restrictions at all, what fibers get unless configured otherwise
+    pub fn all() -> Self {
+        Self {
+            syscalls: Allow::All,
+            devices: Allow::All,
+            peers: Allow::All,
+            spawn: true,
+            limits: Limits::default(),
+        }
+    }
+
+    /// pure computation, nothing outside the fiber is reachable
+    pub fn none() -> Self {
+        Self {
+            syscalls: Allow::none(),
+            devices: Allow::none(),
+            peers: Allow::none(),
+            spawn: false,
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// The capability a denied operation was missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Syscall(u64),
+    Device(u64),
+    Peer(ActorId),
+    Spawn,
+    /// the limit on children was reached
+    Children(usize),
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syscall(id) => write!(f, "syscall {:#x}", id),
+            Self::Device(port) => write!(f, "device {}", port),
+            Self::Peer(actor) => write!(f, "peer {}", actor),
+            Self::Spawn => write!(f, "spawn"),
+            Self::Children(limit) => write!(f, "more than {} children", limit),
+        }
+    }
+}
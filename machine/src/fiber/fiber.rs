@@ -1,6 +1,8 @@
-use crate::{execptions::MachineError, fiber::section::Section, memory::{allocation::Pointer, memory::Memory}, opcode::{commands, opcodes::Opcodes}, utils};
+use std::{cell::Cell, collections::VecDeque, fmt, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Instant};
 
-#[derive(Debug, Clone)]
+use crate::{execptions::{Fault, MachineError}, fiber::{actor::{ActorId, Failure, NodeId}, capability::{Capabilities, Capability}, section::Section, stack::MAX_STACK_SIZE, value::{Tag, Value}}, machine::device::STDIN, memory::{allocation::Pointer, memory::Memory}, opcode::{cache::{DecodeCache, Op}, commands, instruction::{decode, Instruction}, opcodes::Opcodes, tier::Tier, verifier::{self, Verification}}, trace::{event::{Event, EventKind, TraceEvent}, tracer::Tracer}, utils};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Reg {
     PC, SP,
     R0, R1, R2, R3, R4, R5, R6, R7,
@@ -22,18 +24,69 @@ impl Reg {
             _ => Err(MachineError::InvalidRegister),
         }
     }
+
+    /// operand byte the register is encoded with, see `from_u8`
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Self::R0 => 0,
+            Self::R1 => 1,
+            Self::R2 => 2,
+            Self::R3 => 3,
+            Self::R4 => 4,
+            Self::R5 => 5,
+            Self::R6 => 6,
+            Self::R7 => 7,
+            Self::PC => 100,
+            Self::SP => 101,
+        }
+    }
+
+    /// slot of the register inside the register file
+    pub fn index(self) -> usize {
+        match self {
+            Self::PC => 0,
+            Self::SP => 1,
+            Self::R0 => 2,
+            Self::R1 => 3,
+            Self::R2 => 4,
+            Self::R3 => 5,
+            Self::R4 => 6,
+            Self::R5 => 7,
+            Self::R6 => 8,
+            Self::R7 => 9,
+        }
+    }
 }
 
-#[derive(Debug)]
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum Flag {
     Zero, Overflow, Negative, Carry,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+impl Flag {
+    pub fn bit(self) -> u8 {
+        match self {
+            Flag::Zero => 0,
+            Flag::Overflow => 1,
+            Flag::Negative => 2,
+            Flag::Carry => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum FiberState {
     RUNNING = 0x00,
     HALTED = 0x01,
     BLOCKED = 0x02,
+    /// parked on a native call that completes later
+    WAITING = 0x03,
 }
 
 impl FiberState {
@@ -42,297 +95,760 @@ impl FiberState {
             0x00 => Ok(Self::RUNNING),
             0x01 => Ok(Self::HALTED),
             0x02 => Ok(Self::BLOCKED),
+            0x03 => Ok(Self::WAITING),
             _ => Err(MachineError::InvalidFiberState)
         }
     }
 }
 
-#[derive(Debug)]
-pub struct Registers {
-    pc: Pointer,
-    sp: Pointer,
-    r0: Pointer,
-    r1: Pointer,
-    r2: Pointer,
-    r3: Pointer,
-    r4: Pointer,
-    r5: Pointer,
-    r6: Pointer,
-    r7: Pointer,
+pub const REGISTER_COUNT: usize = 10;
+/// ten 8 byte registers followed by the flag byte
+pub const REGISTER_FILE_SIZE: usize = REGISTER_COUNT * 8 + 1;
+
+/// Hot CPU state of a fiber, kept natively instead of in `Memory`.
+/// Cells let the `&self` register accessors keep their signatures.
+#[derive(Debug, Default)]
+pub struct RegisterFile {
+    regs: [Cell<u64>; REGISTER_COUNT],
+    flags: Cell<u8>,
+}
+
+impl RegisterFile {
+    #[inline(always)]
+    pub fn get(&self, reg: Reg) -> u64 {
+        self.regs[reg.index()].get()
+    }
+
+    #[inline(always)]
+    pub fn set(&self, reg: Reg, val: u64) {
+        self.regs[reg.index()].set(val)
+    }
+
+    #[inline(always)]
+    pub fn flag(&self, flag: Flag) -> bool {
+        (self.flags.get() >> flag.bit()) & 1 == 1
+    }
+
+    #[inline(always)]
+    pub fn set_flag(&self, flag: Flag, value: bool) {
+        let flags = self.flags.get();
+        if value {
+            self.flags.set(flags | (1 << flag.bit()));
+        } else {
+            self.flags.set(flags & !(1 << flag.bit()));
+        }
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.flags.get()
+    }
+
+    pub fn snapshot(&self) -> RegisterSnapshot {
+        RegisterSnapshot {
+            registers: std::array::from_fn(|i| self.regs[i].get()),
+            flags: self.flags.get(),
+        }
+    }
+}
+
+/// Copy of a register file, registers indexed by `Reg::index`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterSnapshot {
+    pub registers: [u64; REGISTER_COUNT],
+    pub flags: u8,
+}
+
+impl RegisterSnapshot {
+    pub fn get(&self, reg: Reg) -> u64 {
+        self.registers[reg.index()]
+    }
+
+    pub fn flag(&self, flag: Flag) -> bool {
+        (self.flags >> flag.bit()) & 1 == 1
+    }
 }
 
 #[derive(Debug)]
 pub struct Fiber {
-    pub(crate) id: Pointer,
-    pub(crate) registers: Registers,
-    pub(crate) flag: Pointer,
+    pub(crate) id: u64,
+    pub(crate) registers: RegisterFile,
     pub(crate) stack: Pointer,
     pub(crate) text_section: Section,
     pub(crate) data_section: Section,
-    pub(crate) state: Pointer,
+    pub(crate) state: Cell<FiberState>,
+    /// memory copy of the register file, see `snapshot_registers`
+    pub(crate) register_snapshot: Option<Pointer>,
+    /// one tag per stack slot when running with a typed stack
+    pub(crate) tags: Option<Vec<Tag>>,
+    /// blocks backing `Str`/`Bytes` values, see `store_bytes`
+    pub(crate) heap: Vec<Pointer>,
+    /// blocks left after the last sweep of `heap`
+    pub(crate) heap_swept: usize,
+    /// fault on invalid float operations and division by zero instead of
+    /// producing NaN or infinity
+    pub(crate) float_traps: bool,
+    /// handlers registered by TRY, innermost last
+    pub(crate) handlers: Vec<Handler>,
+    /// text length the last successful verification covered
+    pub(crate) verified: Option<usize>,
+    /// decoded text, `None` decodes every instruction as it executes
+    pub(crate) cache: Option<DecodeCache>,
+    /// compiled hot blocks, `None` only interprets
+    pub(crate) tier: Option<Tier>,
+    /// instructions left before the fiber faults with `OutOfFuel`, `None` is unlimited
+    pub(crate) fuel: Option<u64>,
+    /// what the fiber stopped its slice for, handled by the machine
+    pub(crate) trap: Option<Trap>,
+    /// bytes written by OUT during the current slice, per port
+    pub(crate) output: Vec<(u64, Vec<u8>)>,
+    pub(crate) capabilities: Capabilities,
+    /// most the stack may grow to, in bytes
+    pub(crate) stack_limit: usize,
+    pub(crate) parent: Option<u64>,
+    /// every fiber it spawned, dead or alive
+    pub(crate) children: Vec<u64>,
+    /// SPAWNs left for the fiber and everything it spawned, shared by all of them
+    pub(crate) spawns_left: Option<Arc<AtomicUsize>>,
+    /// messages sent during the current slice, delivered by the machine
+    pub(crate) outbox: Vec<(ActorId, Value, Option<Rpc>)>,
+    pub(crate) mailbox: VecDeque<Message>,
+    /// parked on an empty mailbox
+    pub(crate) receiving: bool,
+    /// the CALLR the fiber waits on
+    pub(crate) calling: Option<Calling>,
+    /// id of the last request made with CALLR
+    pub(crate) last_request: u64,
+    /// requests taken with RECV and not replied to yet, newest last
+    pub(crate) requests: Vec<(ActorId, u64)>,
+    /// memory partition of the machine the fiber lives in
+    pub(crate) partition: usize,
+    /// node of the machine the fiber lives in
+    pub(crate) node: NodeId,
+}
+
+/// A SYSCALL waiting for the machine: the native's id and where it was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Syscall {
+    pub id: u64,
+    pub address: u64,
+}
+
+/// Why a fiber ended its slice early, each carries the address of the
+/// instruction that did it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Trap {
+    Syscall(Syscall),
+    Read(u64),
+    Spawn { entry: u64, address: u64 },
+    /// RECV on an empty mailbox, it runs again once a message is there
+    Receive(u64),
+    /// CALLR, the request is in the outbox already
+    Call { request: u64, timeout: u64, address: u64 },
+}
+
+/// A CALLR waiting for its reply.
+#[derive(Debug)]
+pub(crate) struct Calling {
+    pub(crate) request: u64,
+    /// the CALLR, timeouts and remote faults are raised there
+    pub(crate) address: u64,
+    pub(crate) deadline: Option<Instant>,
+    /// what came back, handed to the fiber at the start of the next round
+    pub(crate) answer: Option<Result<Value, MachineError>>,
+}
+
+/// How a message takes part in a CALLR, by the id the caller gave the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rpc {
+    Request(u64),
+    /// sent with REPLY, goes to the caller's stack instead of its mailbox
+    Reply(u64),
+    /// the callee halted before replying, the value says why
+    Fault(u64),
+}
+
+/// A message waiting in a mailbox. The host sends as fiber 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub from: ActorId,
+    pub value: Value,
+    /// set on the notice a sender gets back when its message to `from`
+    /// couldn't be delivered, `value` is what it sent
+    pub failure: Option<Failure>,
+    pub rpc: Option<Rpc>,
+}
+
+/// A TRY block waiting for a fault: where to continue and how deep the
+/// stack was when it was entered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handler {
+    pub address: u64,
+    pub sp: u64,
 }
 
 impl Fiber {
     pub fn new(mem: &mut Memory, rng: &mut Box<rand::prelude::ThreadRng>) -> Result<Self, MachineError> {
-        let res = Self {
-            flag: mem.allocate(1)?,
-            registers: Registers { 
-                pc: mem.allocate(8)?,
-                sp: mem.allocate(8)?,
-                r0: mem.allocate(8)?,
-                r1: mem.allocate(8)?,
-                r2: mem.allocate(8)?,
-                r3: mem.allocate(8)?,
-                r4: mem.allocate(8)?,
-                r5: mem.allocate(8)?,
-                r6: mem.allocate(8)?,
-                r7: mem.allocate(8)?,
-            },
+        Ok(Self {
+            id: utils::random::random_fiber_id(rng),
+            registers: RegisterFile::default(),
             stack: mem.allocate(4 * 1024)?,
-            id: mem.allocate(8)?,
             text_section: Section::new(mem)?,
             data_section: Section::new(mem)?,
-            state: mem.allocate(1)?,
-        };
-        res.set_register(mem, Reg::PC, 0)?;
-        res.set_register(mem, Reg::SP, 0)?;
-        mem.write_u64(res.id.address, utils::random::random_fiber_id(rng))?;
-        mem.write_u8(res.flag.address, 0)?;
-        Ok(res)
+            state: Cell::new(FiberState::RUNNING),
+            register_snapshot: None,
+            tags: None,
+            heap: Vec::new(),
+            heap_swept: 0,
+            float_traps: false,
+            handlers: Vec::new(),
+            verified: None,
+            cache: Some(DecodeCache::default()),
+            tier: None,
+            fuel: None,
+            trap: None,
+            output: Vec::new(),
+            capabilities: Capabilities::all(),
+            stack_limit: MAX_STACK_SIZE,
+            parent: None,
+            children: Vec::new(),
+            spawns_left: None,
+            outbox: Vec::new(),
+            mailbox: VecDeque::new(),
+            receiving: false,
+            calling: None,
+            last_request: 0,
+            requests: Vec::new(),
+            partition: 0,
+            node: 0,
+        })
+    }
+
+    /// Tags every stack slot and makes opcodes check their operand types.
+    /// Slots already on the stack are treated as integers.
+    pub fn enable_typed_stack(&mut self) {
+        if self.tags.is_none() {
+            let depth = self.registers.get(Reg::SP) as usize / 8;
+            self.tags = Some(vec![Tag::Int; depth]);
+        }
+    }
+
+    pub fn is_typed(&self) -> bool {
+        self.tags.is_some()
+    }
+
+    pub fn set_float_traps(&mut self, enabled: bool) {
+        self.float_traps = enabled;
+    }
+
+    pub fn float_traps(&self) -> bool {
+        self.float_traps
+    }
+
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        let optimize = self.cache.as_ref().is_some_and(|cache| cache.optimize());
+        self.cache = enabled.then(|| DecodeCache::new(optimize));
+    }
+
+    /// Fuses common instruction runs when the text is decoded, see
+    /// `DecodeCache`. Turns the decode cache on if it was off.
+    pub fn set_optimize(&mut self, enabled: bool) {
+        self.cache = Some(DecodeCache::new(enabled));
+    }
+
+    pub fn decode_cache(&self) -> Option<&DecodeCache> {
+        self.cache.as_ref()
+    }
+
+    /// Compiles a block into closures once execution entered it more than
+    /// `threshold` times, `None` keeps the fiber in the interpreter.
+    pub fn set_tier_up_threshold(&mut self, threshold: Option<u32>) {
+        self.tier = threshold.map(Tier::new);
     }
 
-    pub fn get_id(&self, mem: &Memory) -> Result<u64, MachineError> {
-        mem.read_u64(self.id.address)
+    pub fn tier(&self) -> Option<&Tier> {
+        self.tier.as_ref()
+    }
+
+    /// Limits how many more instructions the fiber may execute. A metered
+    /// fiber runs one instruction at a time, without fusion or compiled blocks.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Restricts what the fiber may do from now on. Limits on fuel and stack
+    /// take effect right away, a limit on children starts a new budget for
+    /// the fiber and the fibers it goes on to spawn.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        if let Some(fuel) = capabilities.limits.fuel {
+            self.fuel = Some(self.fuel.map_or(fuel, |left| left.min(fuel)));
+        }
+        self.spawns_left = capabilities.limits.children.map(|limit| Arc::new(AtomicUsize::new(limit)));
+        self.stack_limit = capabilities.limits.stack.map_or(MAX_STACK_SIZE, |limit| limit.min(MAX_STACK_SIZE));
+        self.capabilities = capabilities;
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn parent(&self) -> Option<u64> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[u64] {
+        &self.children
+    }
+
+    pub fn mailbox(&self) -> &VecDeque<Message> {
+        &self.mailbox
+    }
+
+    /// whether SEND may reach `fiber`
+    pub fn may_address(&self, actor: ActorId) -> bool {
+        let related = actor.node == self.node && (self.parent == Some(actor.local) || self.children.contains(&actor.local));
+        related || self.capabilities.peers.allows(actor.to_bits())
+    }
+
+    /// the fiber as seen from anywhere in the cluster
+    pub fn actor(&self) -> ActorId {
+        ActorId::new(self.node, self.id)
+    }
+
+    pub fn text_section(&self) -> &Section {
+        &self.text_section
+    }
+
+    pub fn data_section(&self) -> &Section {
+        &self.data_section
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn change_state(&self, state: FiberState) {
+        self.state.set(state)
+    }
+
+    pub fn state(&self) -> FiberState {
+        self.state.get()
+    }
+
+    #[deprecated(note = "the id no longer lives in memory, use `id`")]
+    pub fn get_id(&self, _mem: &Memory) -> Result<u64, MachineError> {
+        Ok(self.id)
+    }
+
+    #[deprecated(note = "the state no longer lives in memory, use `change_state`")]
+    pub fn set_state(&self, _mem: &mut Memory, state: FiberState) -> Result<(), MachineError> {
+        self.change_state(state);
+        Ok(())
     }
 
-    pub fn set_state(&self, mem: &mut Memory, state: FiberState) -> Result<(), MachineError> {
-        mem.write_u8(self.state.address, state as u8)
+    #[deprecated(note = "the state no longer lives in memory, use `state`")]
+    pub fn get_state(&self, _mem: &Memory) -> Result<FiberState, MachineError> {
+        Ok(self.state())
     }
 
-    pub fn get_state(&self, mem: &Memory) -> Result<FiberState, MachineError> {
-        let val = mem.read_u8(self.state.address)?;
-        FiberState::from_u8(val)
+    pub fn registers(&self) -> &RegisterFile {
+        &self.registers
+    }
+
+    pub fn handlers(&self) -> &[Handler] {
+        &self.handlers
+    }
+
+    /// Runs the verifier over the text section. The fiber counts as verified
+    /// until more bytecode is appended.
+    pub fn verify(&mut self, mem: &Memory) -> Result<Verification, MachineError> {
+        let verification = verifier::verify(mem, &self.text_section)?;
+        self.verified = Some(verification.len);
+        Ok(verification)
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified == Some(self.text_section.len())
     }
 
     pub fn kill(&self, mem: &mut Memory) -> Result<(), MachineError> {
-        // deallocate registers
-        mem.deallocate(&self.registers.pc)?;
-        mem.deallocate(&self.registers.sp)?;
-        mem.deallocate(&self.registers.r0)?;
-        mem.deallocate(&self.registers.r1)?;
-        mem.deallocate(&self.registers.r2)?;
-        mem.deallocate(&self.registers.r3)?;
-        mem.deallocate(&self.registers.r4)?;
-        mem.deallocate(&self.registers.r5)?;
-        mem.deallocate(&self.registers.r6)?;
-        mem.deallocate(&self.registers.r7)?;
         // deallocate sections
         self.text_section.free(mem)?;
         self.data_section.free(mem)?;
         // dellocate other
         mem.deallocate(&self.stack)?;
-        mem.deallocate(&self.flag)?;
-        mem.deallocate(&self.id)?;
+        if let Some(snapshot) = &self.register_snapshot {
+            mem.deallocate(snapshot)?;
+        }
+        for block in &self.heap {
+            mem.deallocate(block)?;
+        }
 
         Ok(())
     }
 
-    pub fn set_register(&self, mem: &mut Memory, reg: Reg, val: u64) -> Result<(), MachineError> {
-        match reg {
-            Reg::R0 => mem.write_u64(self.registers.r0.address, val),
-            Reg::R1 => mem.write_u64(self.registers.r1.address, val),
-            Reg::R2 => mem.write_u64(self.registers.r2.address, val),
-            Reg::R3 => mem.write_u64(self.registers.r3.address, val),
-            Reg::R4 => mem.write_u64(self.registers.r4.address, val),
-            Reg::R5 => mem.write_u64(self.registers.r5.address, val),
-            Reg::R6 => mem.write_u64(self.registers.r6.address, val),
-            Reg::R7 => mem.write_u64(self.registers.r7.address, val),
-            Reg::PC => mem.write_u64(self.registers.pc.address, val),
-            Reg::SP => mem.write_u64(self.registers.sp.address, val),
+    /// Reserves a block in `mem` and copies the register file into it so it
+    /// can be inspected with `hexdump`. This is a snapshot, not a mapping: the
+    /// registers stay in the fiber, writes to the block are never read back,
+    /// and it goes stale while the fiber runs. It is refreshed whenever the
+    /// fiber stops executing, or explicitly through `refresh_snapshot`.
+    pub fn snapshot_registers(&mut self, mem: &mut Memory) -> Result<Pointer, MachineError> {
+        if self.register_snapshot.is_none() {
+            self.register_snapshot = Some(mem.allocate(REGISTER_FILE_SIZE)?);
         }
+        self.refresh_snapshot(mem)?;
+        Ok(self.register_snapshot.clone().unwrap())
+    }
+
+    pub fn register_snapshot(&self) -> Option<&Pointer> {
+        self.register_snapshot.as_ref()
     }
 
-    pub fn get_register(&self, mem: &Memory, reg: Reg) -> Result<u64, MachineError> {
-        match reg {
-            Reg::R0 => mem.read_u64(self.registers.r0.address),
-            Reg::R1 => mem.read_u64(self.registers.r1.address),
-            Reg::R2 => mem.read_u64(self.registers.r2.address),
-            Reg::R3 => mem.read_u64(self.registers.r3.address),
-            Reg::R4 => mem.read_u64(self.registers.r4.address),
-            Reg::R5 => mem.read_u64(self.registers.r5.address),
-            Reg::R6 => mem.read_u64(self.registers.r6.address),
-            Reg::R7 => mem.read_u64(self.registers.r7.address),
-            Reg::PC => mem.read_u64(self.registers.pc.address),
-            Reg::SP => mem.read_u64(self.registers.sp.address),
+    /// copies the current registers and flags into the snapshot, if any
+    pub fn refresh_snapshot(&self, mem: &mut Memory) -> Result<(), MachineError> {
+        if let Some(snapshot) = &self.register_snapshot {
+            for (idx, val) in self.registers.snapshot().registers.iter().enumerate() {
+                mem.write_u64(snapshot.address + idx * 8, *val)?;
+            }
+            mem.write_u8(snapshot.address + REGISTER_COUNT * 8, self.registers.flags())?;
         }
+        Ok(())
     }
 
-    pub fn get_flag(&self, mem: &Memory, flag: Flag) -> Result<bool, MachineError> {
-        let flags = mem.read_u8(self.flag.address)?;
+    pub fn set_register(&self, _mem: &mut Memory, reg: Reg, val: u64) -> Result<(), MachineError> {
+        self.registers.set(reg, val);
+        Ok(())
+    }
 
-        let bit = match flag {
-            Flag::Zero => 0,
-            Flag::Overflow => 1,
-            Flag::Negative => 2,
-            Flag::Carry => 3,
-        };
+    pub fn get_register(&self, _mem: &Memory, reg: Reg) -> Result<u64, MachineError> {
+        Ok(self.registers.get(reg))
+    }
 
-        Ok((flags >> bit) & 1 == 1)
+    pub fn get_flag(&self, _mem: &Memory, flag: Flag) -> Result<bool, MachineError> {
+        Ok(self.registers.flag(flag))
     }
 
-    pub fn set_flag(&self, mem: &mut Memory, flag: Flag, value: bool) -> Result<(), MachineError> {
-        let mut flags = mem.read_u8(self.flag.address)?;
+    pub fn set_flag(&self, _mem: &mut Memory, flag: Flag, value: bool) -> Result<(), MachineError> {
+        self.registers.set_flag(flag, value);
+        Ok(())
+    }
 
-        let bit = match flag {
-            Flag::Zero => 0,
-            Flag::Overflow => 1,
-            Flag::Negative => 2,
-            Flag::Carry => 3,
-        };
+    #[inline(always)]
+    fn get_pc(&self) -> usize {
+        self.registers.get(Reg::PC) as usize
+    }
 
-        if value {
-            flags |= 1 << bit;
-        } else {
-            flags &= !(1 << bit);
+    /// Runs the fiber until it halts, yields or faults. Faults halt the
+    /// fiber and carry the context it was in when things went wrong.
+    pub fn execute(&mut self, mem: &mut Memory) -> Result<(), Fault> {
+        self.execute_traced(mem, None)
+    }
+
+    /// Same as `execute`, handing every instruction to `tracer` before it
+    /// runs if it asks for them. Fused runs and compiled blocks are skipped
+    /// while tracing so each instruction shows up on its own.
+    pub fn execute_traced(&mut self, mem: &mut Memory, tracer: Option<&mut dyn Tracer>) -> Result<(), Fault> {
+        let tracer = tracer.filter(|tracer| tracer.wants(EventKind::Execute));
+        let res = self.run(mem, tracer);
+        if res.is_err() {
+            self.change_state(FiberState::HALTED);
         }
+        self.refresh_snapshot(mem)?;
+        res
+    }
 
-        mem.write_u8(self.flag.address, flags)?;
+    fn run(&mut self, mem: &mut Memory, tracer: Option<&mut dyn Tracer>) -> Result<(), Fault> {
+        self.change_state(FiberState::RUNNING);
+        if tracer.is_none() && self.fuel.is_none() {
+            return self.run_fast(mem);
+        }
+        self.run_stepped(mem, tracer)
+    }
 
-        Ok(())
+    /// one instruction at a time, each one reported before it runs and paid
+    /// for with fuel
+    fn run_stepped(&mut self, mem: &mut Memory, mut tracer: Option<&mut dyn Tracer>) -> Result<(), Fault> {
+        loop {
+            match self.step_metered(mem, tracer.as_deref_mut().map(|tracer| tracer as &mut dyn Tracer))? {
+                Flow::Continue | Flow::Jump => {},
+                Flow::Halt => {
+                    self.change_state(FiberState::HALTED);
+                    return Ok(());
+                },
+                Flow::Yield => {
+                    self.change_state(FiberState::BLOCKED);
+                    return Ok(());
+                },
+            }
+        }
     }
 
-    fn advance_pc(&self, mem: &mut Memory, step: u64) -> Result<(), MachineError> {
-        let cur = self.get_register(&mem, Reg::PC)?;
-        self.set_register(mem, Reg::PC, cur + step)
+    /// Executes the instruction at PC and nothing else. A fault nothing
+    /// catches is returned but leaves the fiber alive with PC past the
+    /// faulting instruction, so a debugger can carry on.
+    pub fn single_step(&mut self, mem: &mut Memory, tracer: Option<&mut dyn Tracer>) -> Result<(), Fault> {
+        if matches!(self.state(), FiberState::HALTED | FiberState::WAITING) {
+            return Err(self.fault(MachineError::InvalidFiberState, self.get_pc() as u64, None));
+        }
+        self.change_state(FiberState::RUNNING);
+        let tracer = tracer.filter(|tracer| tracer.wants(EventKind::Execute));
+        let res = self.step_metered(mem, tracer);
+        match res {
+            Ok(Flow::Halt) => self.change_state(FiberState::HALTED),
+            Ok(Flow::Yield) => self.change_state(FiberState::BLOCKED),
+            _ => {},
+        }
+        self.refresh_snapshot(mem)?;
+        res.map(|_| ())
     }
 
-    fn get_pc(&self, mem: &Memory) -> Result<u64, MachineError> {
-        self.get_register(&mem, Reg::PC)
+    fn step_metered(&mut self, mem: &mut Memory, tracer: Option<&mut dyn Tracer>) -> Result<Flow, Fault> {
+        let pc = self.get_pc();
+        if let Some(fuel) = &mut self.fuel {
+            // running dry can't be caught, a handler would only burn more
+            if *fuel == 0 {
+                let instr = decode(mem, &self.text_section, pc).ok();
+                return Err(self.fault(MachineError::OutOfFuel, pc as u64, instr));
+            }
+            *fuel -= 1;
+        }
+        match self.dispatch_traced(mem, pc, tracer) {
+            Ok(flow) => Ok(flow),
+            Err((instr, err)) => match self.catch(mem, err) {
+                Ok(()) => Ok(Flow::Jump),
+                Err(err) => Err(self.fault(err, pc as u64, instr)),
+            },
+        }
     }
 
-    pub fn execute(&mut self, mem: &mut Memory) -> Result<(), MachineError> {
-        self.set_state(mem, FiberState::RUNNING)?;
+    fn run_fast(&mut self, mem: &mut Memory) -> Result<(), Fault> {
+        // execution got here other than by falling through, blocks start at such places
+        let mut jumped = true;
         loop {
-            let opcode_read = self.text_section.read_u16(mem, self.get_pc(mem)? as usize)?;
-            self.advance_pc(mem, 2)?;
-            let instr = Opcodes::try_from(opcode_read);
-            if let Ok(opcode) = instr {
-                match opcode {
-                    Opcodes::PUSH => {
-                        let val = self.text_section.read_u64(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 8)?;
-                        println!("------------------------------------ {:b}", val);
-                        commands::push(mem, self, val)?;
-                    },
-                    Opcodes::POP => {
-                        let reg = self.text_section.read_u8(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 1)?;
-                        commands::pop(mem, self, Reg::from_u8(reg)?)?;
-                    },
-                    Opcodes::MOV => {
-                        let reg = self.text_section.read_u8(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 1)?;
-                        let val = self.text_section.read_u64(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 8)?;
-                        commands::mov(mem, self, Reg::from_u8(reg)?, val)?;
-                    },
-                    Opcodes::ADD => {
-                        commands::add(mem, self)?;
-                    },
-                    Opcodes::SUB => {
-                        commands::sub(mem, self)?;
-                    },
-                    Opcodes::DROP => {
-                        commands::drop(mem, self)?;
-                    },
-                    Opcodes::DUP => {
-                        commands::dup(mem, self)?;
-                    },
-                    Opcodes::SWP => {
-                        commands::swap(mem, self)?;
-                    },
-                    Opcodes::INC => {
-                        let reg = self.text_section.read_u8(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 1)?;
-                        commands::inc(mem, self, Reg::from_u8(reg)?)?;
-                    },
-                    Opcodes::DEC => {
-                        let reg = self.text_section.read_u8(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 1)?;
-                        commands::dec(mem, self, Reg::from_u8(reg)?)?;
-                    },
-                    Opcodes::JMP => {
-                        let val = self.text_section.read_u64(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 8)?;
-                        commands::jmp(mem, self, val as usize)?;
+            let pc = self.get_pc();
+            let block = match &mut self.tier {
+                Some(tier) if jumped => tier.enter(mem, &self.text_section, pc as u64),
+                _ => None,
+            };
+            let flow = match block {
+                Some(block) => match block.run(self, mem) {
+                    Ok(flow) => Ok(flow),
+                    Err((instr, err)) => {
+                        if let Err(err) = self.catch(mem, err) {
+                            return Err(self.fault(err, instr.address, Some(instr)));
+                        }
+                        jumped = true;
+                        continue;
                     },
-                    Opcodes::JZ => {
-                        let val = self.text_section.read_u64(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 8)?;
-                        commands::jz(mem, self, val as usize)?;
-                    },
-                    Opcodes::JNZ => {
-                        let val = self.text_section.read_u64(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 8)?;
-                        commands::jnz(mem, self, val as usize)?;
-                    },
-                    Opcodes::JG => {
-                        let val = self.text_section.read_u64(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 8)?;
-                        commands::jg(mem, self, val as usize)?;
-                    },
-                    Opcodes::JGE => {
-                        let val = self.text_section.read_u64(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 8)?;
-                        commands::jge(mem, self, val as usize)?;
-                    },
-                    Opcodes::JL => {
-                        let val = self.text_section.read_u64(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 8)?;
-                        commands::jl(mem, self, val as usize)?;
-                    },
-                    Opcodes::JLE => {
-                        let val = self.text_section.read_u64(mem, self.get_pc(mem)? as usize)?;
-                        self.advance_pc(mem, 8)?;
-                        commands::jle(mem, self, val as usize)?;
-                    },
-                    Opcodes::AND => {
-                        commands::and(mem, self)?;
-                    },
-                    Opcodes::OR => {
-                        commands::or(mem, self)?;
-                    },
-                    Opcodes::NOT => {
-                        commands::not(mem, self)?;
-                    },
-                    Opcodes::XOR => {
-                        commands::xor(mem, self)?;
-                    },
-                    Opcodes::SHR => {
-                        commands::shr(mem, self)?;
-                    },
-                    Opcodes::SHL => {
-                        commands::shl(mem, self)?;
-                    },
-                    Opcodes::ROL => {
-                        commands::rol(mem, self)?;
-                    },
-                    Opcodes::ROR => {
-                        commands::ror(mem, self)?;
-                    },
-                    Opcodes::HLT => {
-                        self.set_state(mem, FiberState::HALTED)?;
-                        return Ok(());
-                    },
-                    Opcodes::YLD => {
-                        self.set_state(mem, FiberState::BLOCKED)?;
-                        return Ok(());
-                    },
-                }
-            } else {
-                self.set_state(mem, FiberState::HALTED)?;
-                return Err(MachineError::InvalidOpcode(Some(format!("opcode: {} at #{:x}", opcode_read, self.get_pc(mem)?).to_string())));
+                },
+                None => self.dispatch(mem, pc),
+            };
+            match flow {
+                Ok(Flow::Continue) => jumped = false,
+                Ok(Flow::Jump) => jumped = true,
+                Ok(Flow::Halt) => {
+                    self.change_state(FiberState::HALTED);
+                    return Ok(());
+                },
+                Ok(Flow::Yield) => {
+                    self.change_state(FiberState::BLOCKED);
+                    return Ok(());
+                },
+                Err(err) => {
+                    if let Err(err) = self.catch(mem, err) {
+                        let instr = decode(mem, &self.text_section, pc).ok();
+                        return Err(self.fault(err, pc as u64, instr));
+                    }
+                    jumped = true;
+                },
             }
         }
     }
+
+    /// decodes and executes one instruction, telling the tracer first
+    fn dispatch_traced(&mut self, mem: &mut Memory, pc: usize, tracer: Option<&mut dyn Tracer>) -> Result<Flow, (Option<Instruction>, MachineError)> {
+        let instr = decode(mem, &self.text_section, pc).map_err(|err| (None, err))?;
+        if let Some(tracer) = tracer {
+            tracer.record(&TraceEvent {
+                fiber: Some(self.id),
+                pc: Some(pc as u64),
+                instruction: Some(instr),
+                event: Event::Execute,
+            });
+        }
+        self.step(mem, &instr).map_err(|err| (Some(instr), err))
+    }
+
+    /// Executes whatever the interpreter finds at `pc`.
+    #[inline(always)]
+    fn dispatch(&mut self, mem: &mut Memory, pc: usize) -> Result<Flow, MachineError> {
+        let decoded = match &mut self.cache {
+            Some(cache) => cache.get(mem, &self.text_section, pc)?,
+            None => Op::Single(decode(mem, &self.text_section, pc)?),
+        };
+        let instr = match decoded {
+            Op::Single(instr) => instr,
+            Op::Fused(fused) => {
+                if self.run_fused(mem, &fused)? {
+                    return Ok(if self.get_pc() as u64 == fused.next() { Flow::Continue } else { Flow::Jump });
+                }
+                // no shortcut this time, run the first instruction on its own
+                decode(mem, &self.text_section, pc)?
+            },
+        };
+        match self.step(mem, &instr)? {
+            Flow::Continue if self.get_pc() as u64 != instr.next() => Ok(Flow::Jump),
+            flow => Ok(flow),
+        }
+    }
+
+    /// Hands `err` to the innermost handler: the stack is cut back to where
+    /// the TRY was entered, the fault code is pushed and execution continues
+    /// at the handler. Gives the error back when nothing catches it.
+    fn catch(&mut self, mem: &mut Memory, err: MachineError) -> Result<(), MachineError> {
+        let Some(handler) = self.handlers.pop() else {
+            return Err(err);
+        };
+        self.registers.set(Reg::SP, handler.sp);
+        if let Some(tags) = &mut self.tags {
+            tags.truncate(handler.sp as usize / 8);
+        }
+        self.push(mem, err.code())?;
+        self.registers.set(Reg::PC, handler.address);
+        Ok(())
+    }
+
+    /// Executes one decoded instruction, PC ends up on the next one unless it jumped.
+    #[inline(always)]
+    pub(crate) fn step(&mut self, mem: &mut Memory, instr: &Instruction) -> Result<Flow, MachineError> {
+        self.registers.set(Reg::PC, instr.next());
+        match instr.opcode {
+            Opcodes::PUSH => commands::push(mem, self, instr.imm()?)?,
+            Opcodes::PUSHT => commands::pusht(mem, self, instr.tag()?, instr.imm()?)?,
+            Opcodes::POP => commands::pop(mem, self, instr.reg()?)?,
+            Opcodes::MOV => commands::mov(mem, self, instr.reg()?, instr.imm()?)?,
+            Opcodes::ADD => commands::add(mem, self)?,
+            Opcodes::SUB => commands::sub(mem, self)?,
+            Opcodes::DROP => commands::drop(mem, self)?,
+            Opcodes::DUP => commands::dup(mem, self)?,
+            Opcodes::SWP => commands::swap(mem, self)?,
+            Opcodes::INC => commands::inc(mem, self, instr.reg()?)?,
+            Opcodes::DEC => commands::dec(mem, self, instr.reg()?)?,
+            Opcodes::JMP => commands::jmp(mem, self, instr.imm()? as usize)?,
+            Opcodes::JZ => commands::jz(mem, self, instr.imm()? as usize)?,
+            Opcodes::JNZ => commands::jnz(mem, self, instr.imm()? as usize)?,
+            Opcodes::JG => commands::jg(mem, self, instr.imm()? as usize)?,
+            Opcodes::JGE => commands::jge(mem, self, instr.imm()? as usize)?,
+            Opcodes::JL => commands::jl(mem, self, instr.imm()? as usize)?,
+            Opcodes::JLE => commands::jle(mem, self, instr.imm()? as usize)?,
+            Opcodes::AND => commands::and(mem, self)?,
+            Opcodes::OR => commands::or(mem, self)?,
+            Opcodes::NOT => commands::not(mem, self)?,
+            Opcodes::XOR => commands::xor(mem, self)?,
+            Opcodes::SHR => commands::shr(mem, self)?,
+            Opcodes::SHL => commands::shl(mem, self)?,
+            Opcodes::ROL => commands::rol(mem, self)?,
+            Opcodes::ROR => commands::ror(mem, self)?,
+            Opcodes::FADD => commands::fadd(mem, self)?,
+            Opcodes::FSUB => commands::fsub(mem, self)?,
+            Opcodes::FMUL => commands::fmul(mem, self)?,
+            Opcodes::FDIV => commands::fdiv(mem, self)?,
+            Opcodes::FSQRT => commands::fsqrt(mem, self)?,
+            Opcodes::FNEG => commands::fneg(mem, self)?,
+            Opcodes::FABS => commands::fabs(mem, self)?,
+            Opcodes::ITOF => commands::itof(mem, self)?,
+            Opcodes::UTOF => commands::utof(mem, self)?,
+            Opcodes::FTOI => commands::ftoi(mem, self)?,
+            Opcodes::FTOU => commands::ftou(mem, self)?,
+            Opcodes::FCMP => commands::fcmp(mem, self)?,
+            Opcodes::TRY => commands::try_handler(mem, self, instr.imm()?)?,
+            Opcodes::ENDTRY => commands::endtry(mem, self)?,
+            Opcodes::THROW => return Err(MachineError::Thrown(instr.imm()?)),
+            Opcodes::HLT => return Ok(Flow::Halt),
+            Opcodes::YLD => return Ok(Flow::Yield),
+            // the slice ends here, the machine runs the native before the fiber goes on
+            Opcodes::SYSCALL => {
+                if !self.capabilities.syscalls.allows(instr.imm()?) {
+                    return Err(MachineError::CapabilityDenied(Capability::Syscall(instr.imm()?)));
+                }
+                self.trap = Some(Trap::Syscall(Syscall { id: instr.imm()?, address: instr.address }));
+                return Ok(Flow::Yield);
+            },
+            Opcodes::OUT => commands::out(mem, self, instr.imm()?)?,
+            Opcodes::IN => {
+                if instr.imm()? != STDIN {
+                    return Err(MachineError::InvalidDevice(Some(format!("port {} can't be read", instr.imm()?))));
+                }
+                if !self.capabilities.devices.allows(STDIN) {
+                    return Err(MachineError::CapabilityDenied(Capability::Device(STDIN)));
+                }
+                self.trap = Some(Trap::Read(instr.address));
+                return Ok(Flow::Yield);
+            },
+            Opcodes::SPAWN => {
+                if !self.capabilities.spawn {
+                    return Err(MachineError::CapabilityDenied(Capability::Spawn));
+                }
+                if let (Some(limit), Some(left)) = (self.capabilities.limits.children, &self.spawns_left)
+                    && left.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1)).is_err() {
+                    return Err(MachineError::CapabilityDenied(Capability::Children(limit)));
+                }
+                self.trap = Some(Trap::Spawn { entry: instr.imm()?, address: instr.address });
+                return Ok(Flow::Yield);
+            },
+            Opcodes::SEND => commands::send(mem, self)?,
+            // the fiber sleeps until the reply is there or the call times out
+            Opcodes::CALLR => {
+                let request = commands::callr(mem, self)?;
+                self.trap = Some(Trap::Call { request, timeout: instr.imm()?, address: instr.address });
+                return Ok(Flow::Yield);
+            },
+            Opcodes::REPLY => commands::reply(mem, self)?,
+            Opcodes::RECV => {
+                if !commands::recv(mem, self)? {
+                    // runs again once something arrived
+                    self.registers.set(Reg::PC, instr.address);
+                    self.trap = Some(Trap::Receive(instr.address));
+                    return Ok(Flow::Yield);
+                }
+            },
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Raises `err` as if the instruction at `address` had faulted. A fault
+    /// nothing catches halts the fiber and comes back.
+    pub(crate) fn raise(&mut self, mem: &mut Memory, err: MachineError, address: u64) -> Result<(), Fault> {
+        if let Err(err) = self.catch(mem, err) {
+            self.change_state(FiberState::HALTED);
+            let instr = decode(mem, &self.text_section, address as usize).ok();
+            return Err(self.fault(err, address, instr));
+        }
+        Ok(())
+    }
+
+    /// wraps an error with the state of this fiber
+    pub(crate) fn fault(&self, error: MachineError, pc: u64, instruction: Option<Instruction>) -> Fault {
+        Fault {
+            error,
+            fiber: Some(self.id),
+            pc: Some(pc),
+            instruction,
+            snapshot: Some(Box::new(self.registers.snapshot())),
+            backtrace: Box::new([]),
+        }
+    }
+}
+
+/// What the fiber does after an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Flow {
+    Continue,
+    /// continued somewhere other than the next instruction
+    Jump,
+    Halt,
+    Yield,
 }
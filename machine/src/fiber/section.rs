@@ -1,9 +1,13 @@
+use std::cell::Cell;
+
 use crate::{execptions::MachineError, memory::{allocation::Pointer, memory::Memory}};
 
 #[derive(Debug)]
 pub struct Section {
-    pub(crate) dp: Pointer, // data pointer
+    pub(crate) dp: Cell<usize>, // data pointer
     pub(crate) data: Pointer,
+    /// bumped on every write so decoded copies of the section can notice
+    pub(crate) generation: Cell<u64>,
 }
 
 pub trait MemoryMan {
@@ -71,22 +75,38 @@ impl MemoryMan for u64 {
 impl Section {
     pub fn new(mem: &mut Memory) -> Result<Self, MachineError> {
         Ok(Self {
-            dp: mem.allocate(8)?,
+            dp: Cell::new(0),
             data: mem.allocate(8 * 1024)?,
+            generation: Cell::new(0),
         })
     }
 
     pub fn free(&self, mem: &mut Memory) -> Result<(), MachineError> {
-        mem.deallocate(&self.data)?;
-        mem.deallocate(&self.dp)?;
-        Ok(())
+        mem.deallocate(&self.data)
+    }
+
+    /// number of bytes appended so far
+    pub fn len(&self) -> usize {
+        self.dp.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dp.get() == 0
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.get()
     }
 
     /// appends data to the section and increase DP
     pub fn append_data<T: MemoryMan>(&self, mem: &mut Memory, data: T) -> Result<(), MachineError> {
-        let dp = mem.read_u64(self.dp.address)?;
-        T::append_data(data, mem, self.data.address + dp as usize)?;
-        mem.write_u64(self.dp.address, dp + T::size_in_bytes() as u64)?;
+        let dp = self.dp.get();
+        if dp + T::size_in_bytes() > self.data.size {
+            return Err(MachineError::InsufficientMemory(Some("section is full".to_string())));
+        }
+        T::append_data(data, mem, self.data.address + dp)?;
+        self.dp.set(dp + T::size_in_bytes());
+        self.generation.set(self.generation.get() + 1);
         Ok(())
     }
 
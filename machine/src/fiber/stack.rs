@@ -1,45 +1,171 @@
-use crate::{execptions::MachineError, fiber::fiber::{Fiber, Reg}, memory::memory::Memory};
+use std::collections::HashSet;
+
+use crate::{execptions::MachineError, fiber::{actor::ActorId, fiber::{Fiber, Reg}, value::{mismatch, Tag, Value}}, memory::memory::Memory};
+
+pub(crate) const MAX_STACK_SIZE: usize = 1024 * 1024;
+/// blocks a fiber keeps for values before it first sweeps them
+const MIN_SWEEP: usize = 16;
 
 impl Fiber {
     pub fn push(&mut self, mem: &mut Memory, data: u64) -> Result<(), MachineError> {
-        if self.get_register(mem, Reg::SP)? as usize > self.stack.size {
-            if self.stack.size > 1 * 1024 * 1024 {
-                return Err(MachineError::StackOverflow);
-            }
-            let prev_stack = self.stack.clone();
-            self.stack = mem.reallocate(&prev_stack, prev_stack.size + 64)?;
-            mem.deallocate(&prev_stack)?;
-        }
-        mem.write_u64(self.stack.address + self.get_register(mem, Reg::SP)? as usize, data)?;
-        let r = self.get_register(mem, Reg::SP)?;
-        self.set_register(mem, Reg::SP, r + 8)?;
-        Ok(())
+        self.push_tagged(mem, Tag::Int, data)
     }
 
     // TODO shrink stack if needed
     pub fn pop(&mut self, mem: &mut Memory) -> Result<u64, MachineError> {
-        if self.get_register(mem, Reg::SP)? as usize == 0 {
-            return Err(MachineError::StackUnderflow);
-        }
-        let val = mem.read_u64(self.stack.address + (self.get_register(mem, Reg::SP)? as usize - 8))?;
-        let r = self.get_register(mem, Reg::SP)?;
-        self.set_register(mem, Reg::SP, r - 8)?;
-        Ok(val)
+        Ok(self.pop_tagged(mem)?.1)
     }
 
     pub fn swap(&mut self, mem: &mut Memory) -> Result<(), MachineError> {
-        let v1 = self.pop(mem)?;
-        let v2 = self.pop(mem)?;
-        self.push(mem, v1)?;
-        self.push(mem, v2)?;
+        let v1 = self.pop_tagged(mem)?;
+        let v2 = self.pop_tagged(mem)?;
+        self.push_tagged(mem, v1.0, v1.1)?;
+        self.push_tagged(mem, v2.0, v2.1)?;
         Ok(())
     }
 
     pub fn peek(&self, mem: &Memory) -> Result<u64, MachineError> {
-        if self.get_register(mem, Reg::SP)? as usize == 0 {
+        Ok(self.peek_tagged(mem)?.1)
+    }
+
+    /// pushes a slot, the tag is dropped when the stack is untyped
+    pub fn push_tagged(&mut self, mem: &mut Memory, tag: Tag, data: u64) -> Result<(), MachineError> {
+        let sp = self.registers.get(Reg::SP) as usize;
+        if sp + 8 > self.stack_limit {
+            return Err(MachineError::StackOverflow);
+        }
+        if sp + 8 > self.stack.size {
+            // reallocate releases the old block itself
+            self.stack = mem.reallocate(&self.stack, (self.stack.size * 2).min(self.stack_limit))?;
+        }
+        mem.write_u64(self.stack.address + sp, data)?;
+        self.registers.set(Reg::SP, (sp + 8) as u64);
+        if let Some(tags) = &mut self.tags {
+            tags.push(tag);
+        }
+        Ok(())
+    }
+
+    /// pops a slot with its tag, untyped stacks report every slot as `Int`
+    pub fn pop_tagged(&mut self, mem: &mut Memory) -> Result<(Tag, u64), MachineError> {
+        let sp = self.registers.get(Reg::SP) as usize;
+        if sp == 0 {
             return Err(MachineError::StackUnderflow);
         }
-        let val = mem.read_u64(self.stack.address + (self.get_register(mem, Reg::SP)? as usize - 8))?;
+        let val = mem.read_u64(self.stack.address + (sp - 8))?;
+        self.registers.set(Reg::SP, (sp - 8) as u64);
+        let tag = match &mut self.tags {
+            Some(tags) => tags.pop().unwrap_or(Tag::Int),
+            None => Tag::Int,
+        };
+        Ok((tag, val))
+    }
+
+    pub fn peek_tagged(&self, mem: &Memory) -> Result<(Tag, u64), MachineError> {
+        let sp = self.registers.get(Reg::SP) as usize;
+        if sp == 0 {
+            return Err(MachineError::StackUnderflow);
+        }
+        let val = mem.read_u64(self.stack.address + (sp - 8))?;
+        let tag = self.tags.as_ref().and_then(|tags| tags.last().copied()).unwrap_or(Tag::Int);
+        Ok((tag, val))
+    }
+
+    /// every slot with its tag, bottom of the stack first
+    pub fn stack_slots(&self, mem: &Memory) -> Result<Vec<(Tag, u64)>, MachineError> {
+        let depth = self.registers.get(Reg::SP) as usize / 8;
+        (0..depth).map(|idx| {
+            let tag = self.tags.as_ref().and_then(|tags| tags.get(idx).copied()).unwrap_or(Tag::Int);
+            Ok((tag, mem.read_u64(self.stack.address + idx * 8)?))
+        }).collect()
+    }
+
+    /// pops a slot that has to carry `expected`, only checked on typed stacks
+    pub fn pop_typed(&mut self, mem: &mut Memory, expected: Tag) -> Result<u64, MachineError> {
+        let (tag, val) = self.pop_tagged(mem)?;
+        if self.tags.is_some() && tag != expected {
+            return Err(mismatch(expected, tag));
+        }
         Ok(val)
     }
-}
\ No newline at end of file
+
+    /// Pushes a host value. `Str` and `Bytes` are copied into a block owned
+    /// by the fiber as a length followed by the raw bytes.
+    pub fn push_value(&mut self, mem: &mut Memory, value: Value) -> Result<(), MachineError> {
+        let tag = value.tag();
+        let bits = match value {
+            Value::Int(val) => val as u64,
+            Value::Float(val) => val.to_bits(),
+            Value::Bool(val) => val as u64,
+            Value::Str(val) => self.store_bytes(mem, val.as_bytes())?,
+            Value::Bytes(val) => self.store_bytes(mem, &val)?,
+            Value::Ref(val) => val,
+            Value::Actor(actor) => actor.to_bits(),
+            Value::Null => 0,
+        };
+        self.push_tagged(mem, tag, bits)
+    }
+
+    pub fn pop_value(&mut self, mem: &mut Memory) -> Result<Value, MachineError> {
+        let (tag, bits) = self.pop_tagged(mem)?;
+        Self::to_value(mem, tag, bits)
+    }
+
+    /// reads a slot as a value of type `tag`
+    pub(crate) fn to_value(mem: &Memory, tag: Tag, bits: u64) -> Result<Value, MachineError> {
+        Ok(match tag {
+            Tag::Int => Value::Int(bits as i64),
+            Tag::Float => Value::Float(f64::from_bits(bits)),
+            Tag::Bool => Value::Bool(bits != 0),
+            Tag::Str => Value::Str(String::from_utf8_lossy(&Self::load_bytes(mem, bits)?).into_owned()),
+            Tag::Bytes => Value::Bytes(Self::load_bytes(mem, bits)?),
+            Tag::Ref => Value::Ref(bits),
+            Tag::Actor => Value::Actor(ActorId::from_bits(bits)),
+            Tag::Null => Value::Null,
+        })
+    }
+
+    /// Copies `bytes` into a new block. Blocks no stack slot points at any
+    /// more are released first, once the heap has doubled since the last
+    /// sweep. A handle kept only in a register or in memory doesn't count.
+    fn store_bytes(&mut self, mem: &mut Memory, bytes: &[u8]) -> Result<u64, MachineError> {
+        if self.heap.len() >= (self.heap_swept * 2).max(MIN_SWEEP) {
+            self.sweep(mem)?;
+        }
+        let block = mem.allocate(8 + bytes.len())?;
+        mem.write_u64(block.address, bytes.len() as u64)?;
+        for (idx, byte) in bytes.iter().enumerate() {
+            mem.write_u8(block.address + 8 + idx, *byte)?;
+        }
+        let address = block.address as u64;
+        self.heap.push(block);
+        Ok(address)
+    }
+
+    /// releases the blocks no stack slot holds, on typed stacks only slots
+    /// tagged `Str` or `Bytes` count
+    fn sweep(&mut self, mem: &mut Memory) -> Result<(), MachineError> {
+        let typed = self.is_typed();
+        let held = self.stack_slots(mem)?.into_iter()
+            .filter(|(tag, _)| !typed || matches!(tag, Tag::Str | Tag::Bytes))
+            .map(|(_, bits)| bits)
+            .collect::<HashSet<_>>();
+        let mut kept = Vec::with_capacity(self.heap.len());
+        for block in self.heap.drain(..) {
+            if held.contains(&(block.address as u64)) {
+                kept.push(block);
+            } else {
+                mem.deallocate(&block)?;
+            }
+        }
+        self.heap = kept;
+        self.heap_swept = self.heap.len();
+        Ok(())
+    }
+
+    pub(crate) fn load_bytes(mem: &Memory, address: u64) -> Result<Vec<u8>, MachineError> {
+        let address = address as usize;
+        let len = mem.read_u64(address)? as usize;
+        (0..len).map(|idx| mem.read_u8(address + 8 + idx)).collect()
+    }
+}
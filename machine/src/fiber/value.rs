@@ -0,0 +1,77 @@
+use std::fmt;
+
+use crate::{execptions::MachineError, fiber::actor::ActorId};
+
+/// Type tag of a stack slot when a fiber runs with a typed stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Int = 0x00,
+    Float = 0x01,
+    Bool = 0x02,
+    Str = 0x03,
+    Bytes = 0x04,
+    Ref = 0x05,
+    Actor = 0x06,
+    Null = 0x07,
+}
+
+impl Tag {
+    pub fn from_u8(val: u8) -> Result<Self, MachineError> {
+        match val {
+            0x00 => Ok(Self::Int),
+            0x01 => Ok(Self::Float),
+            0x02 => Ok(Self::Bool),
+            0x03 => Ok(Self::Str),
+            0x04 => Ok(Self::Bytes),
+            0x05 => Ok(Self::Ref),
+            0x06 => Ok(Self::Actor),
+            0x07 => Ok(Self::Null),
+            _ => Err(MachineError::TypeMismatch(Some(format!("unknown tag {:#x}", val)))),
+        }
+    }
+
+    /// references point at things owned by the machine, bytecode can only
+    /// move them around and never make one up
+    pub fn is_reference(self) -> bool {
+        matches!(self, Self::Str | Self::Bytes | Self::Ref | Self::Actor)
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A stack value as seen by the host.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Bytes(Vec<u8>),
+    /// address of a block in the machine's memory
+    Ref(u64),
+    Actor(ActorId),
+    Null,
+}
+
+impl Value {
+    pub fn tag(&self) -> Tag {
+        match self {
+            Self::Int(_) => Tag::Int,
+            Self::Float(_) => Tag::Float,
+            Self::Bool(_) => Tag::Bool,
+            Self::Str(_) => Tag::Str,
+            Self::Bytes(_) => Tag::Bytes,
+            Self::Ref(_) => Tag::Ref,
+            Self::Actor(_) => Tag::Actor,
+            Self::Null => Tag::Null,
+        }
+    }
+}
+
+pub(crate) fn mismatch(expected: Tag, found: Tag) -> MachineError {
+    MachineError::TypeMismatch(Some(format!("expected {}, found {}", expected, found)))
+}
@@ -3,4 +3,5 @@ pub mod execptions;
 pub mod utils;
 pub mod fiber;
 pub mod opcode;
-pub mod machine;
\ No newline at end of file
+pub mod machine;
+pub mod trace;
@@ -1 +1,7 @@
 pub mod machine;
+pub mod config;
+pub mod native;
+pub mod device;
+pub(crate) mod pool;
+#[cfg(feature = "async")]
+pub mod driver;
@@ -0,0 +1,81 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{fiber::{actor::NodeId, capability::Capabilities}, memory::memory::Endianness};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backing {
+    Ram,
+    /// memory-mapped file, created (or truncated) when the machine starts
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub struct MachineConfig {
+    /// bytes reserved up front
+    pub memory_size: usize,
+    /// memory grows in pages up to this many bytes, equal to `memory_size` means fixed
+    pub max_memory_size: usize,
+    pub backing: Backing,
+    /// keep a snapshot of every fiber's register file in memory so it shows up
+    /// in `hexdump`, refreshed whenever the fiber stops
+    pub snapshot_registers: bool,
+    /// byte order of the image, big-endian for images built before it was configurable
+    pub endianness: Endianness,
+    /// tag every stack slot and check operand types, see `Fiber::enable_typed_stack`
+    pub typed_stack: bool,
+    /// fault on float division by zero and invalid operations instead of following IEEE-754
+    pub float_traps: bool,
+    /// run the verifier over a fiber's text before it executes and refuse
+    /// to run the fiber when it fails
+    pub verify: bool,
+    /// keep decoded instructions around instead of decoding on every step
+    pub decode_cache: bool,
+    /// fuse common instruction runs into superinstructions, implies `decode_cache`
+    pub optimize: bool,
+    /// compile blocks entered more often than this into closures, `None`
+    /// interprets everything
+    pub tier_up_threshold: Option<u32>,
+    /// instructions each fiber may execute before it faults with `OutOfFuel`,
+    /// `None` is unlimited
+    pub fuel: Option<u64>,
+    /// leave halted fibers in place until `kill` so their registers can
+    /// still be read, they are not scheduled again
+    pub keep_halted: bool,
+    /// what fibers spawned by the host may do, see `Machine::spawn_with`
+    pub capabilities: Capabilities,
+    /// threads running slices at once, 1 runs everything on the calling
+    /// thread in a fixed order. Above 1 memory is split into `4 * workers`
+    /// partitions that share `memory_size` and `max_memory_size` evenly, and
+    /// fibers are spread over them. A fiber only grows within its own
+    /// partition, so its stack and values get that share at most, not all
+    /// of `max_memory_size`
+    pub workers: usize,
+    /// the machine's node in a cluster, part of every `ActorId` it hands out
+    pub node: NodeId,
+    /// how long CALLR waits for its reply when it doesn't say, `None` waits forever
+    pub call_timeout: Option<Duration>,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            memory_size: 16 * 1024 * 1024,
+            max_memory_size: 16 * 1024 * 1024,
+            backing: Backing::Ram,
+            snapshot_registers: false,
+            endianness: Endianness::Little,
+            typed_stack: false,
+            float_traps: false,
+            verify: false,
+            decode_cache: true,
+            optimize: false,
+            tier_up_threshold: Some(1000),
+            fuel: None,
+            keep_halted: false,
+            capabilities: Capabilities::all(),
+            workers: 1,
+            node: 0,
+            call_timeout: None,
+        }
+    }
+}
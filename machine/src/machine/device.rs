@@ -0,0 +1,187 @@
+use std::{collections::VecDeque, io::{self, Read, Write}, sync::{Arc, Mutex}, thread};
+
+use crate::{fiber::value::Value, machine::native::Pending};
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+/// Where OUT ends up. Every chunk is what one fiber wrote to one port
+/// during one slice, in scheduling order.
+pub trait Sink: Send {
+    fn write_chunk(&mut self, fiber: u64, bytes: &[u8]) -> io::Result<()>;
+}
+
+/// plain writers get the bytes and lose the fiber id
+impl<W: Write + Send> Sink for W {
+    fn write_chunk(&mut self, _fiber: u64, bytes: &[u8]) -> io::Result<()> {
+        self.write_all(bytes)
+    }
+}
+
+/// One fiber's output from one slice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub fiber: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// Keeps output in memory, clones share the same chunks.
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer {
+    chunks: Arc<Mutex<Vec<Chunk>>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn chunks(&self) -> Vec<Chunk> {
+        self.chunks.lock().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    /// everything written, whatever fiber it came from
+    pub fn bytes(&self) -> Vec<u8> {
+        self.chunks().into_iter().flat_map(|chunk| chunk.bytes).collect()
+    }
+}
+
+impl Sink for OutputBuffer {
+    fn write_chunk(&mut self, fiber: u64, bytes: &[u8]) -> io::Result<()> {
+        self.chunks.lock().unwrap_or_else(|err| err.into_inner()).push(Chunk { fiber, bytes: bytes.to_vec() });
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct InputState {
+    bytes: VecDeque<u8>,
+    closed: bool,
+    /// fibers blocked on IN, first come first served
+    readers: VecDeque<Pending>,
+}
+
+/// Bytes IN reads from. The host writes into it, clones share the same
+/// buffer. Once closed and drained IN reads -1.
+#[derive(Clone, Default)]
+pub struct Input {
+    state: Arc<Mutex<InputState>>,
+}
+
+/// what IN gets right away
+pub(crate) enum Received {
+    Byte(u8),
+    Eof,
+    Empty,
+}
+
+impl Input {
+    /// an open buffer, empty until the host writes to it
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// input that is already at its end
+    pub fn closed() -> Self {
+        let input = Self::new();
+        input.close();
+        input
+    }
+
+    /// Feeds the input from `reader` on a thread of its own, so a reader
+    /// that blocks only blocks the fibers reading from it.
+    pub fn from_reader(mut reader: impl Read + Send + 'static) -> Self {
+        let input = Self::new();
+        let feed = input.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => feed.write(&buf[..len]),
+                }
+            }
+            feed.close();
+        });
+        input
+    }
+
+    pub fn write(&self, bytes: &[u8]) {
+        let mut state = self.lock();
+        state.bytes.extend(bytes);
+        state.wake();
+    }
+
+    pub fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        state.wake();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, InputState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn read(&self) -> Received {
+        let mut state = self.lock();
+        match state.bytes.pop_front() {
+            Some(byte) => Received::Byte(byte),
+            None if state.closed => Received::Eof,
+            None => Received::Empty,
+        }
+    }
+
+    /// `pending` completes with the next byte
+    pub(crate) fn wait(&self, pending: Pending) {
+        let mut state = self.lock();
+        state.readers.push_back(pending);
+        state.wake();
+    }
+}
+
+impl InputState {
+    fn wake(&mut self) {
+        while !self.readers.is_empty() {
+            let value = match self.bytes.pop_front() {
+                Some(byte) => byte as i64,
+                None if self.closed => -1,
+                None => break,
+            };
+            if let Some(reader) = self.readers.pop_front() {
+                reader.complete(Ok(vec![Value::Int(value)]));
+            }
+        }
+    }
+}
+
+/// The console of a machine.
+pub(crate) struct Devices {
+    pub(crate) stdin: Input,
+    pub(crate) stdout: Box<dyn Sink>,
+    pub(crate) stderr: Box<dyn Sink>,
+    pub(crate) errors: usize,
+}
+
+impl Default for Devices {
+    fn default() -> Self {
+        Self {
+            stdin: Input::closed(),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            errors: 0,
+        }
+    }
+}
+
+impl Devices {
+    /// writes what a fiber wrote during its slice
+    pub(crate) fn flush(&mut self, fiber: u64, output: &mut Vec<(u64, Vec<u8>)>) {
+        for (port, bytes) in output.drain(..) {
+            let sink = if port == STDERR { &mut self.stderr } else { &mut self.stdout };
+            if sink.write_chunk(fiber, &bytes).is_err() {
+                self.errors += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::{collections::VecDeque, future::Future, pin::Pin, sync::{Arc, Condvar, Mutex}, task::{Context, Poll, Waker}, thread, time::Instant};
+
+use crate::{execptions::Fault, fiber::{fiber::FiberState, value::Value}, machine::machine::Machine};
+
+/// How a driven machine stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// every fiber halted, or the ones left wait for something nobody can send anymore
+    Done,
+    /// stopped through `Handle::cancel`, the fibers are left as they were
+    Cancelled,
+}
+
+/// events handed to the driver by the host
+#[derive(Default)]
+struct Inbox {
+    messages: VecDeque<(u64, Value)>,
+    ticks: u64,
+    cancelled: bool,
+    waker: Option<Waker>,
+}
+
+impl Inbox {
+    fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.ticks == 0 && !self.cancelled
+    }
+}
+
+/// Reaches a machine while a `Drive` owns it, from any thread. Every event
+/// wakes the driver.
+#[derive(Clone)]
+pub struct Handle {
+    inbox: Arc<Mutex<Inbox>>,
+}
+
+impl Handle {
+    fn with(&self, event: impl FnOnce(&mut Inbox)) {
+        let mut inbox = self.inbox.lock().unwrap_or_else(|err| err.into_inner());
+        event(&mut inbox);
+        if let Some(waker) = inbox.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Posts `value` to the fiber as sent by the host, see `Machine::send`.
+    /// Messages to fibers that are gone are dropped.
+    pub fn send(&self, fiber: u64, value: Value) {
+        self.with(|inbox| inbox.messages.push_back((fiber, value)));
+    }
+
+    /// Runs the driver's tick callback once, ticks that pile up while the
+    /// machine runs are run back to back.
+    pub fn tick(&self) {
+        self.with(|inbox| inbox.ticks += 1);
+    }
+
+    /// Makes the driver return `Exit::Cancelled` at the end of the round it
+    /// is in. Dropping the future cancels too, without saying so.
+    pub fn cancel(&self) {
+        self.with(|inbox| inbox.cancelled = true);
+    }
+}
+
+/// what the timer thread waits for
+#[derive(Default)]
+struct Alarm {
+    deadline: Option<Instant>,
+    waker: Option<Waker>,
+    stopped: bool,
+}
+
+/// One thread per driver that wakes it at the earliest deadline it was
+/// given, started the first time a CALLR can time out.
+#[derive(Default)]
+struct Timer {
+    alarm: Arc<(Mutex<Alarm>, Condvar)>,
+    started: bool,
+}
+
+impl Timer {
+    fn set(&mut self, deadline: Instant, waker: &Waker) {
+        if !self.started {
+            self.started = true;
+            let alarm = self.alarm.clone();
+            thread::spawn(move || Self::run(&alarm));
+        }
+        let (alarm, wakeup) = &*self.alarm;
+        let mut alarm = alarm.lock().unwrap_or_else(|err| err.into_inner());
+        alarm.deadline = Some(alarm.deadline.map_or(deadline, |earlier| earlier.min(deadline)));
+        alarm.waker = Some(waker.clone());
+        wakeup.notify_one();
+    }
+
+    fn run(alarm: &(Mutex<Alarm>, Condvar)) {
+        let (alarm, wakeup) = alarm;
+        let lock = || alarm.lock().unwrap_or_else(|err| err.into_inner());
+        let mut state = lock();
+        while !state.stopped {
+            match state.deadline {
+                Some(deadline) if deadline <= Instant::now() => {
+                    state.deadline = None;
+                    let waker = state.waker.take();
+                    // outside the lock, the driver may set the next alarm right away
+                    drop(state);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                    state = lock();
+                },
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    state = wakeup.wait_timeout(state, timeout).unwrap_or_else(|err| err.into_inner()).0;
+                },
+                None => state = wakeup.wait(state).unwrap_or_else(|err| err.into_inner()),
+            }
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let (alarm, wakeup) = &*self.alarm;
+        alarm.lock().unwrap_or_else(|err| err.into_inner()).stopped = true;
+        wakeup.notify_one();
+    }
+}
+
+type Tick<'a> = Box<dyn FnMut(&mut Machine) + 'a>;
+
+/// A machine running as a Future, see `Machine::drive`. Doesn't depend on
+/// any runtime, it only needs its waker called.
+pub struct Drive<'a> {
+    machine: &'a mut Machine,
+    inbox: Arc<Mutex<Inbox>>,
+    rounds: usize,
+    on_tick: Option<Tick<'a>>,
+    timer: Timer,
+}
+
+impl<'a> Drive<'a> {
+    pub fn handle(&self) -> Handle {
+        Handle { inbox: self.inbox.clone() }
+    }
+
+    /// Called with the machine on every `Handle::tick`, between two rounds.
+    /// Timers of the host go here.
+    pub fn on_tick(mut self, tick: impl FnMut(&mut Machine) + 'a) -> Self {
+        self.on_tick = Some(Box::new(tick));
+        self
+    }
+
+    /// Hands the events sent so far to the machine. Returns whether the
+    /// driver was cancelled.
+    fn take_events(&mut self) -> bool {
+        let (messages, ticks, cancelled) = {
+            let mut inbox = self.inbox.lock().unwrap_or_else(|err| err.into_inner());
+            (std::mem::take(&mut inbox.messages), std::mem::take(&mut inbox.ticks), inbox.cancelled)
+        };
+        if cancelled {
+            return true;
+        }
+        for (fiber, value) in messages {
+            // the fiber may be gone by now
+            let _ = self.machine.send(fiber, value);
+        }
+        if let Some(tick) = &mut self.on_tick {
+            for _ in 0..ticks {
+                tick(self.machine);
+            }
+        }
+        false
+    }
+
+    /// Arms the waker on everything that can make a fiber runnable again.
+    /// Returns whether something did in the meantime.
+    fn sleep(&mut self, waker: &Waker) -> bool {
+        // there is no runtime to ask for a timer
+        if let Some(deadline) = self.machine.next_deadline() {
+            if deadline <= Instant::now() {
+                return true;
+            }
+            self.timer.set(deadline, waker);
+        }
+        let mut inbox = self.inbox.lock().unwrap_or_else(|err| err.into_inner());
+        inbox.waker = Some(waker.clone());
+        let completed = self.machine.natives().wake(waker);
+        completed || !inbox.is_empty()
+    }
+}
+
+impl Future for Drive<'_> {
+    type Output = Result<Exit, Fault>;
+
+    /// Runs up to `rounds` scheduling rounds per poll and gives the executor
+    /// its thread back in between.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut rounds = 0;
+        while rounds < this.rounds {
+            if this.take_events() {
+                return Poll::Ready(Ok(Exit::Cancelled));
+            }
+            if !this.machine.is_runnable() {
+                if !this.machine.fibers().any(|fiber| fiber.state() == FiberState::WAITING) {
+                    return Poll::Ready(this.machine.finish().map(|_| Exit::Done));
+                }
+                // otherwise the next round picks up what arrived
+                if !this.sleep(cx.waker()) {
+                    // with no handle left only a native or a timeout can still wake anything
+                    if Arc::strong_count(&this.inbox) > 1 || this.machine.natives().is_waiting() || this.machine.next_deadline().is_some() {
+                        return Poll::Pending;
+                    }
+                    return Poll::Ready(this.machine.finish().map(|_| Exit::Done));
+                }
+            }
+            if !this.machine.schedule()? {
+                return Poll::Ready(this.machine.finish().map(|_| Exit::Done));
+            }
+            rounds += 1;
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl Machine {
+    /// Runs the machine like `execute`, as a Future that gives the executor
+    /// its thread back every `rounds` rounds and waits for messages, ticks
+    /// and natives completing instead of blocking when no fiber can run.
+    pub fn drive(&mut self, rounds: usize) -> Drive<'_> {
+        Drive {
+            machine: self,
+            inbox: Arc::default(),
+            rounds: rounds.max(1),
+            on_tick: None,
+            timer: Timer::default(),
+        }
+    }
+}
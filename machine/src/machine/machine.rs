@@ -1,88 +1,729 @@
-use core::panic;
+use std::{sync::atomic::Ordering, thread, time::{Duration, Instant}};
 
-use crate::{execptions::MachineError, fiber::fiber::{Fiber, FiberState}, memory::{memory::Memory}};
+use crate::{execptions::{Fault, MachineError}, fiber::{actor::{ActorId, Failure, NodeId}, capability::Capabilities, fiber::{Calling, Fiber, FiberState, Message, Reg, Rpc, Trap}, value::{Tag, Value}}, machine::{config::{Backing, MachineConfig}, device::{Devices, Input, Received, Sink}, native::{Call, Natives, Outcome, Signature, Slot}, pool::Pool}, memory::{memory::Memory, store::{FileStore, RamStore}}, opcode::{instruction::Instruction, verifier::Verification}, trace::{event::{Event, EventKind, TraceEvent}, coverage::Coverage, profile::Profile, tracer::{Tee, Tracer}}};
+
+/// memory partitions per worker thread, more of them balance better
+const PARTITIONS_PER_WORKER: usize = 4;
+
+/// A message for a fiber on another node, left for the node layer to carry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outgoing {
+    pub from: ActorId,
+    pub to: ActorId,
+    pub value: Value,
+    pub rpc: Option<Rpc>,
+}
 
 pub struct Machine {
-    mem: Memory,
+    /// one partition unless `workers` is above 1, a fiber only ever touches its own
+    mems: Vec<Memory>,
     rng: Box<rand::prelude::ThreadRng>,
     fibers: Vec<Fiber>,
+    config: MachineConfig,
+    /// uncaught faults of fibers that were killed, oldest first
+    faults: Vec<Fault>,
+    tracer: Option<Box<dyn Tracer>>,
+    profile: Profile,
+    profiling: bool,
+    coverage: Coverage,
+    covering: bool,
+    natives: Natives,
+    devices: Devices,
+    /// whether anything picks up `outgoing`, remote sends fail right away otherwise
+    routing: bool,
+    outgoing: Vec<Outgoing>,
+    /// started by the first parallel round, lives as long as the machine
+    pool: Option<Pool<Slices, Slices>>,
+}
+
+/// a partition by index with the fibers to run in it, and once run what
+/// their slices ended with
+type Slices = (usize, Memory, Vec<(usize, Fiber, Result<(), Fault>)>);
+
+/// runs the slices of a partition's fibers one after another
+fn run_slices((partition, mut mem, fibers): Slices) -> Slices {
+    let fibers = fibers.into_iter().map(|(idx, mut fiber, _)| {
+        let res = fiber.execute(&mut mem);
+        (idx, fiber, res)
+    }).collect();
+    (partition, mem, fibers)
+}
+
+fn emit(tracer: &mut Option<Box<dyn Tracer>>, fiber: Option<u64>, pc: Option<u64>, instruction: Option<Instruction>, event: Event) {
+    if let Some(tracer) = tracer
+        && tracer.wants(event.kind()) {
+        tracer.record(&TraceEvent { fiber, pc, instruction, event });
+    }
 }
 
 impl Machine {
     pub fn new(size: usize) -> Result<Self, MachineError> {
+        Self::with_config(MachineConfig {
+            memory_size: size,
+            max_memory_size: size,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_config(config: MachineConfig) -> Result<Self, MachineError> {
+        let partitions = if config.workers > 1 { config.workers * PARTITIONS_PER_WORKER } else { 1 };
+        let mems = (0..partitions).map(|idx| Self::partition(&config, idx, partitions)).collect::<Result<_, _>>()?;
         Ok(Self{
             fibers: Vec::new(),
-            mem: Memory::new(size)?,
+            mems,
             rng: Box::new(rand::rng()),
+            config,
+            faults: Vec::new(),
+            tracer: None,
+            profile: Profile::default(),
+            profiling: false,
+            coverage: Coverage::default(),
+            covering: false,
+            natives: Natives::default(),
+            devices: Devices::default(),
+            routing: false,
+            outgoing: Vec::new(),
+            pool: None,
         })
     }
 
+    /// Memory partition `idx` of `count`, with an even share of the configured
+    /// sizes. Files of partitions past the first get the index appended to
+    /// their name.
+    fn partition(config: &MachineConfig, idx: usize, count: usize) -> Result<Memory, MachineError> {
+        let size = config.memory_size / count;
+        let max_size = (config.max_memory_size / count).max(size);
+        let mut mem = match &config.backing {
+            Backing::Ram => Memory::with_store(Box::new(RamStore::new(size)), max_size)?,
+            Backing::File(path) if idx == 0 => Memory::with_store(Box::new(FileStore::new(path, size)?), max_size)?,
+            Backing::File(path) => {
+                let mut name = path.clone().into_os_string();
+                name.push(format!(".{}", idx));
+                Memory::with_store(Box::new(FileStore::new(name, size)?), max_size)?
+            },
+        };
+        mem.set_endianness(config.endianness);
+        Ok(mem)
+    }
+
+    /// the first memory partition, the only one unless `workers` is above 1
+    pub fn memory(&self) -> &Memory {
+        &self.mems[0]
+    }
+
+    /// the memory partition the fiber lives in
+    pub fn memory_of(&self, fiber_id: u64) -> Option<&Memory> {
+        self.fiber(fiber_id).map(|fiber| &self.mems[fiber.partition])
+    }
+
+    pub fn fibers(&self) -> impl Iterator<Item = &Fiber> {
+        self.fibers.iter()
+    }
+
+    pub fn fiber(&self, fiber_id: u64) -> Option<&Fiber> {
+        self.fibers.iter().find(|x| x.id() == fiber_id)
+    }
+
+    /// Installs a tracer, or removes it with `None`. Memory only keeps a
+    /// journal of allocations while the tracer asks for them.
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        let journal = tracer.as_ref()
+            .is_some_and(|tracer| tracer.wants(EventKind::Allocate) || tracer.wants(EventKind::Deallocate));
+        for mem in &mut self.mems {
+            mem.set_journal(journal);
+        }
+        self.tracer = tracer;
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        for mem in &mut self.mems {
+            mem.set_journal(false);
+        }
+        self.tracer.take()
+    }
+
+    /// Starts or stops counting executed instructions and timing slices.
+    /// Takes effect with the next scheduling round, counts gathered so far
+    /// are kept until `take_profile`.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiling = enabled;
+    }
+
+    pub fn is_profiling(&self) -> bool {
+        self.profiling
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// returns the counts gathered so far and starts over
+    pub fn take_profile(&mut self) -> Profile {
+        std::mem::take(&mut self.profile)
+    }
+
+    /// Starts or stops recording executed addresses and branch directions,
+    /// from the next scheduling round on. Every fiber adds to the same counts.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.covering = enabled;
+    }
+
+    pub fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
+
+    pub fn take_coverage(&mut self) -> Coverage {
+        std::mem::take(&mut self.coverage)
+    }
+
+    /// hands allocations recorded since the last flush to the tracer
+    fn flush_journal(&mut self, fiber: Option<u64>) {
+        for mem in &mut self.mems {
+            for event in mem.take_journal() {
+                emit(&mut self.tracer, fiber, None, None, event);
+            }
+        }
+    }
+
+    /// faults collected by `execute` that were not returned from it
+    pub fn take_faults(&mut self) -> Vec<Fault> {
+        std::mem::take(&mut self.faults)
+    }
+
+    /// Spawns a fiber with the capabilities from the config.
     pub fn spawn(&mut self) -> Result<u64, MachineError> {
-        let fib = Fiber::new(&mut self.mem, &mut self.rng)?;
-        let id = fib.get_id(&self.mem)?;
+        self.spawn_with(self.config.capabilities.clone())
+    }
+
+    /// Spawns a fiber restricted to `capabilities`, the fibers it spawns in
+    /// turn get the same set.
+    pub fn spawn_with(&mut self, capabilities: Capabilities) -> Result<u64, MachineError> {
+        // the partition with the fewest fibers
+        let partition = (0..self.mems.len())
+            .min_by_key(|idx| self.fibers.iter().filter(|fiber| fiber.partition == *idx).count())
+            .unwrap_or(0);
+        let mem = &mut self.mems[partition];
+        let mut fib = Fiber::new(mem, &mut self.rng)?;
+        fib.partition = partition;
+        fib.node = self.config.node;
+        if self.config.snapshot_registers {
+            fib.snapshot_registers(mem)?;
+        }
+        if self.config.typed_stack {
+            fib.enable_typed_stack();
+        }
+        fib.set_float_traps(self.config.float_traps);
+        fib.set_decode_cache(self.config.decode_cache);
+        if self.config.optimize {
+            fib.set_optimize(true);
+        }
+        fib.set_tier_up_threshold(self.config.tier_up_threshold);
+        fib.set_fuel(self.config.fuel);
+        fib.set_capabilities(capabilities);
+        let id = fib.id();
         self.fibers.push(fib);
+        self.flush_journal(Some(id));
+        emit(&mut self.tracer, Some(id), None, None, Event::Spawn);
         Ok(id)
     }
 
+    pub fn verify(&mut self, fiber_id: u64) -> Result<Verification, MachineError> {
+        match self.fibers.iter_mut().find(|x| x.id() == fiber_id) {
+            Some(fiber) => fiber.verify(&self.mems[fiber.partition]),
+            None => Err(MachineError::InvalidFiber),
+        }
+    }
+
     pub fn write_bytecodes(&mut self, fiber_id: u64, bytecodes: &[u64]) -> Result<(), MachineError> {
-        if let Some(idx) = self.fibers.iter().position(|x| {
-            if let Ok(fid) = x.get_id(&self.mem) {
-                fid == fiber_id
-            } else { false }
-        }) {
+        if let Some(idx) = self.fibers.iter().position(|x| x.id() == fiber_id) {
+            let mem = &mut self.mems[self.fibers[idx].partition];
             for pair in bytecodes.chunks(2) {
                 match pair[0] {
-                    0 => self.fibers[idx].text_section.append_data::<u8>(&mut self.mem, pair[1] as u8)?,
-                    1 => self.fibers[idx].text_section.append_data::<u16>(&mut self.mem, pair[1] as u16)?,
-                    2 => self.fibers[idx].text_section.append_data::<u32>(&mut self.mem, pair[1] as u32)?,
-                    3 => self.fibers[idx].text_section.append_data::<u64>(&mut self.mem, pair[1] as u64)?,
+                    0 => self.fibers[idx].text_section.append_data::<u8>(mem, pair[1] as u8)?,
+                    1 => self.fibers[idx].text_section.append_data::<u16>(mem, pair[1] as u16)?,
+                    2 => self.fibers[idx].text_section.append_data::<u32>(mem, pair[1] as u32)?,
+                    3 => self.fibers[idx].text_section.append_data::<u64>(mem, pair[1])?,
                     _ => return Err(MachineError::InvalidBytecodeDataType),
                 };
             }
             Ok(())
         } else {
-            return Err(MachineError::InvalidFiber);
+            Err(MachineError::InvalidFiber)
         }
     }
 
     pub fn kill(&mut self, fiber_id: u64) -> Result<(), MachineError> {
-        if let Some(idx) = self.fibers.iter().position(|x| {
-            if let Ok(id) = x.get_id(&self.mem) {
-                return id == fiber_id;
-            } else {
-                false
-            }
-        }) {
-            self.fibers[idx].kill(&mut self.mem)?;
+        if let Some(idx) = self.fibers.iter().position(|x| x.id() == fiber_id) {
+            self.fibers[idx].kill(&mut self.mems[self.fibers[idx].partition])?;
             self.fibers.swap_remove(idx);
+            emit(&mut self.tracer, Some(fiber_id), None, None, Event::Kill);
+            self.flush_journal(Some(fiber_id));
+        }
+        Ok(())
+    }
+
+    pub fn node(&self) -> NodeId {
+        self.config.node
+    }
+
+    /// Puts `value` into the fiber's mailbox as sent by fiber 0, the host.
+    pub fn send(&mut self, fiber_id: u64, value: Value) -> Result<(), MachineError> {
+        let from = ActorId::new(self.config.node, 0);
+        self.deliver(fiber_id, Message { from, value, failure: None, rpc: None })
+    }
+
+    fn live(&mut self, fiber_id: u64) -> Option<&mut Fiber> {
+        self.fibers.iter_mut().find(|fiber| fiber.id() == fiber_id && fiber.state() != FiberState::HALTED)
+    }
+
+    /// Puts a message into the mailbox of a fiber that hasn't halted. Replies
+    /// go to the CALLR waiting for them instead, or nowhere once it gave up.
+    pub fn deliver(&mut self, fiber_id: u64, message: Message) -> Result<(), MachineError> {
+        let fiber = self.live(fiber_id).ok_or(MachineError::InvalidFiber)?;
+        match message.rpc {
+            Some(Rpc::Reply(request)) => Self::answer(fiber, request, Ok(message.value)),
+            Some(Rpc::Fault(request)) => {
+                let detail = match message.value {
+                    Value::Str(detail) => format!("{}: {}", message.from, detail),
+                    _ => format!("{} faulted", message.from),
+                };
+                Self::answer(fiber, request, Err(MachineError::RemoteFault(Some(detail))));
+            },
+            _ => Self::post(fiber, message),
         }
         Ok(())
     }
 
-    pub fn execute(&mut self) -> Result<(), MachineError> {
-        Ok(loop {
-            // TODO only for testing it'll break, reomve it later
-            if self.fibers.len() == 0 {
-                break;
-            }
-            let mut kills: Vec<u64> = Vec::new();
-            for fiber in &mut self.fibers {
-                let res = fiber.execute(&mut self.mem);
-                if let Ok(()) = res {
-                    if fiber.get_state(&self.mem)? == FiberState::HALTED {
-                        let id = fiber.get_id(&self.mem)?;
-                        kills.push(id);
-                    }
-                } else if let Err(err) = res {
-                    // TODO somehow log the error and manage it, for now, PANIC!
-                    let id = fiber.get_id(&self.mem)?;
-                    kills.push(id);
-                    panic!("{:?}", err);
+    /// Tells the fiber behind `from` that what it sent never reached `to`,
+    /// a CALLR fails with `RemoteFault`. Nobody hears about it when that
+    /// fiber is gone too, or is the host.
+    pub fn bounce(&mut self, outgoing: Outgoing, failure: Failure) {
+        let Outgoing { from, to, value, rpc } = outgoing;
+        if from.node != self.config.node {
+            return;
+        }
+        let Some(fiber) = self.live(from.local) else {
+            return;
+        };
+        match rpc {
+            None => Self::post(fiber, Message { from: to, value, failure: Some(failure), rpc: None }),
+            Some(Rpc::Request(request)) => {
+                let detail = format!("{} {}", failure, to);
+                Self::answer(fiber, request, Err(MachineError::RemoteFault(Some(detail))));
+            },
+            // the caller is gone, nobody waits for these
+            Some(Rpc::Reply(_) | Rpc::Fault(_)) => {},
+        }
+    }
+
+    /// Leaves messages for other nodes in `take_outgoing` instead of
+    /// bouncing them with `Failure::UnknownNode`.
+    pub fn set_routing(&mut self, routing: bool) {
+        self.routing = routing;
+    }
+
+    /// messages SENT to other nodes since the last call, oldest first
+    pub fn take_outgoing(&mut self) -> Vec<Outgoing> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Wakes the fiber if it still waits for `request`.
+    fn answer(fiber: &mut Fiber, request: u64, answer: Result<Value, MachineError>) {
+        if let Some(calling) = &mut fiber.calling
+            && calling.request == request && calling.answer.is_none() {
+            calling.answer = Some(answer);
+            fiber.change_state(FiberState::BLOCKED);
+        }
+    }
+
+    /// Hands the fibers back their answered or timed out calls, see `answer`.
+    /// Returns the faults nothing caught.
+    fn resume_calls(&mut self) -> Vec<Fault> {
+        let now = Instant::now();
+        let mut faults = Vec::new();
+        for fiber in &mut self.fibers {
+            let Some(calling) = fiber.calling.take_if(|calling| calling.answer.is_some() || calling.deadline.is_some_and(|deadline| deadline <= now)) else {
+                continue;
+            };
+            fiber.change_state(FiberState::BLOCKED);
+            let mem = &mut self.mems[fiber.partition];
+            let res = calling.answer.unwrap_or(Err(MachineError::CallTimeout))
+                .and_then(|value| fiber.push_value(mem, value))
+                .and_then(|_| fiber.refresh_snapshot(mem));
+            if let Err(err) = res
+                && let Err(fault) = fiber.raise(mem, err, calling.address) {
+                faults.push(fault);
+            }
+        }
+        faults
+    }
+
+    /// when the first CALLR still waiting times out, if any will
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.fibers.iter()
+            .filter_map(|fiber| fiber.calling.as_ref())
+            .filter(|calling| calling.answer.is_none())
+            .filter_map(|calling| calling.deadline)
+            .min()
+    }
+
+    fn post(fiber: &mut Fiber, message: Message) {
+        fiber.mailbox.push_back(message);
+        if fiber.receiving {
+            fiber.receiving = false;
+            fiber.change_state(FiberState::BLOCKED);
+        }
+    }
+
+    /// Makes `native` callable from bytecode as `SYSCALL id`, replacing
+    /// whatever was registered under `id` before.
+    pub fn register_native(&mut self, id: u64, signature: Signature,
+        native: impl FnMut(&mut Call) -> Result<Outcome, MachineError> + Send + 'static) {
+        self.natives.register(id, signature, Box::new(native));
+    }
+
+    /// Where OUT 1 goes, the process' stdout until replaced.
+    pub fn set_stdout(&mut self, sink: impl Sink + 'static) {
+        self.devices.stdout = Box::new(sink);
+    }
+
+    /// Where OUT 2 goes, the process' stderr until replaced.
+    pub fn set_stderr(&mut self, sink: impl Sink + 'static) {
+        self.devices.stderr = Box::new(sink);
+    }
+
+    /// What IN 0 reads. Until set stdin is closed and IN reads -1.
+    pub fn set_stdin(&mut self, input: Input) {
+        self.devices.stdin = input;
+    }
+
+    /// chunks a sink failed to take
+    pub fn device_errors(&self) -> usize {
+        self.devices.errors
+    }
+
+    /// Executes a single instruction of one fiber, see `Fiber::single_step`.
+    /// The fiber is never killed here, not even when it halts.
+    pub fn step(&mut self, fiber_id: u64) -> Result<(), Fault> {
+        let fiber = self.fibers.iter_mut()
+            .find(|fiber| fiber.id() == fiber_id)
+            .ok_or(MachineError::InvalidFiber)?;
+        let mem = &mut self.mems[fiber.partition];
+        let tracer = self.tracer.as_deref_mut().map(|tracer| tracer as &mut dyn Tracer);
+        let res = fiber.single_step(mem, tracer);
+        let res = Self::after_slice(&mut self.natives, &mut self.devices, mem, fiber, res);
+        let faults = self.settle();
+        self.flush_journal(Some(fiber_id));
+        res?;
+        match faults.into_iter().next() {
+            Some(fault) => Err(fault),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs a slice of every fiber in `ready` on the worker threads, one
+    /// partition at a time per worker. Results come back in fiber order.
+    fn run_parallel(&mut self, ready: &[usize]) -> Vec<(usize, Result<(), Fault>)> {
+        // the partitions and fibers go to the workers and come back after
+        let mut mems: Vec<Option<Memory>> = std::mem::take(&mut self.mems).into_iter().map(Some).collect();
+        let mut fibers: Vec<Option<Fiber>> = std::mem::take(&mut self.fibers).into_iter().map(Some).collect();
+        let mut picked: Vec<Vec<_>> = mems.iter().map(|_| Vec::new()).collect();
+        for &idx in ready {
+            if let Some(fiber) = fibers[idx].take() {
+                picked[fiber.partition].push((idx, fiber, Ok(())));
+            }
+        }
+        let jobs = picked.into_iter().enumerate()
+            .filter(|(_, fibers)| !fibers.is_empty())
+            .filter_map(|(partition, fibers)| Some((partition, mems[partition].take()?, fibers)))
+            .collect();
+        let workers = self.config.workers;
+        let done = self.pool.get_or_insert_with(|| Pool::new(workers, run_slices)).run(jobs);
+        let mut results = Vec::new();
+        for (partition, mem, ran) in done {
+            mems[partition] = Some(mem);
+            for (idx, fiber, res) in ran {
+                fibers[idx] = Some(fiber);
+                results.push((idx, res));
+            }
+        }
+        self.mems = mems.into_iter().flatten().collect();
+        self.fibers = fibers.into_iter().flatten().collect();
+        results.sort_by_key(|(idx, _)| *idx);
+        results
+    }
+
+    /// Everything after a fiber's slice that touches the rest of the machine.
+    fn end_slice(&mut self, idx: usize, res: Result<(), Fault>, kills: &mut Vec<u64>, faults: &mut Vec<Fault>) {
+        let fiber = &mut self.fibers[idx];
+        let mem = &mut self.mems[fiber.partition];
+        let res = Self::after_slice(&mut self.natives, &mut self.devices, mem, fiber, res);
+        if fiber.state() != FiberState::RUNNING {
+            emit(&mut self.tracer, Some(fiber.id()), Some(fiber.registers().get(Reg::PC)), None,
+                Event::State { from: FiberState::RUNNING, to: fiber.state() });
+        }
+        if self.tracer.is_some() {
+            for event in mem.take_journal() {
+                emit(&mut self.tracer, Some(fiber.id()), None, None, event);
+            }
+        }
+        match res {
+            Ok(()) => {
+                if fiber.state() == FiberState::HALTED {
+                    kills.push(fiber.id());
+                }
+            },
+            Err(err) => {
+                // callers hear why instead of only that it halted
+                for (to, request) in fiber.requests.drain(..) {
+                    fiber.outbox.push((to, Value::Str(err.error.to_string()), Some(Rpc::Fault(request))));
+                }
+                kills.push(fiber.id());
+                faults.push(err);
+            },
+        }
+    }
+
+    /// Writes out what the fiber wrote and serves the SYSCALL, IN or RECV it
+    /// stopped for, if any. SPAWN and CALLR are left to `settle`.
+    fn after_slice(natives: &mut Natives, devices: &mut Devices, mem: &mut Memory, fiber: &mut Fiber, res: Result<(), Fault>) -> Result<(), Fault> {
+        devices.flush(fiber.id(), &mut fiber.output);
+        res?;
+        match fiber.trap.take() {
+            Some(Trap::Syscall(syscall)) => natives.dispatch(fiber, mem, syscall)?,
+            Some(Trap::Read(address)) => {
+                let read = match devices.stdin.read() {
+                    Received::Byte(byte) => fiber.push(mem, byte as u64),
+                    Received::Eof => fiber.push(mem, u64::MAX),
+                    Received::Empty => {
+                        devices.stdin.wait(natives.park(fiber, address, &[Slot::Stack(Tag::Int)]));
+                        Ok(())
+                    },
+                };
+                if let Err(err) = read {
+                    fiber.raise(mem, err, address)?;
+                }
+            },
+            // nothing arrives while the fiber runs, it sleeps until a message is posted
+            Some(Trap::Receive(_)) => {
+                fiber.receiving = true;
+                fiber.change_state(FiberState::WAITING);
+            },
+            trap => fiber.trap = trap,
+        }
+        Ok(fiber.refresh_snapshot(mem)?)
+    }
+
+    /// Starts the children asked for with SPAWN, parks the fibers that made
+    /// a CALLR and delivers the messages sent during the round. Senders hear
+    /// back about messages to fibers that are gone, callers about callees
+    /// that halted without replying.
+    /// Returns the faults nothing caught.
+    fn settle(&mut self) -> Vec<Fault> {
+        let mut faults = Vec::new();
+        for idx in 0..self.fibers.len() {
+            let Some(Trap::Spawn { entry, address }) = self.fibers[idx].trap else {
+                continue;
+            };
+            self.fibers[idx].trap = None;
+            let child = self.spawn_child(idx, entry);
+            let fiber = &mut self.fibers[idx];
+            let mem = &mut self.mems[fiber.partition];
+            let node = fiber.node;
+            let res = child.and_then(|child| fiber.push_value(mem, Value::Actor(ActorId::new(node, child))))
+                .and_then(|_| fiber.refresh_snapshot(mem));
+            if let Err(err) = res
+                && let Err(fault) = fiber.raise(mem, err, address) {
+                faults.push(fault);
+            }
+        }
+        let mut messages = Vec::new();
+        for fiber in &mut self.fibers {
+            if let Some(Trap::Call { request, timeout, address }) = fiber.trap {
+                fiber.trap = None;
+                let timeout = if timeout > 0 { Some(Duration::from_millis(timeout)) } else { self.config.call_timeout };
+                let deadline = timeout.map(|timeout| Instant::now() + timeout);
+                fiber.calling = Some(Calling { request, address, deadline, answer: None });
+                fiber.change_state(FiberState::WAITING);
+            }
+            if fiber.state() == FiberState::HALTED {
+                for (to, request) in fiber.requests.drain(..) {
+                    fiber.outbox.push((to, Value::Str("halted without replying".to_string()), Some(Rpc::Fault(request))));
+                }
+            }
+            let from = fiber.actor();
+            messages.extend(fiber.outbox.drain(..).map(|(to, value, rpc)| Outgoing { from, to, value, rpc }));
+        }
+        for outgoing in messages {
+            if outgoing.to.node != self.config.node {
+                if self.routing {
+                    self.outgoing.push(outgoing);
+                } else {
+                    self.bounce(outgoing, Failure::UnknownNode);
+                }
+                continue;
+            }
+            let message = Message { from: outgoing.from, value: outgoing.value.clone(), failure: None, rpc: outgoing.rpc };
+            if let Err(MachineError::InvalidFiber) = self.deliver(outgoing.to.local, message) {
+                self.bounce(outgoing, Failure::DeadActor);
+            }
+        }
+        faults
+    }
+
+    /// A copy of the parent's text starting at `entry`, with the parent's
+    /// capabilities. It shares the parent's budget of children and never
+    /// gets more fuel than the parent has left. It may land in another
+    /// partition than the parent. A spawn that fails leaves no child behind
+    /// and gives the slot back to the budget.
+    fn spawn_child(&mut self, parent: usize, entry: u64) -> Result<u64, MachineError> {
+        let count = self.fibers.len();
+        let res = self.start_child(parent, entry);
+        if res.is_err() {
+            // a half-built child goes again, and the SPAWN gets its slot back
+            if self.fibers.len() > count && let Some(child) = self.fibers.pop() {
+                let _ = child.kill(&mut self.mems[child.partition]);
+            }
+            if let Some(left) = &self.fibers[parent].spawns_left {
+                left.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        res
+    }
+
+    fn start_child(&mut self, parent: usize, entry: u64) -> Result<u64, MachineError> {
+        let text = &self.fibers[parent].text_section;
+        let mem = &self.mems[self.fibers[parent].partition];
+        let code = (0..text.len()).map(|idx| text.read_u8(mem, idx)).collect::<Result<Vec<u8>, _>>()?;
+        let capabilities = self.fibers[parent].capabilities.clone();
+        let id = self.spawn_with(capabilities)?;
+        let child = self.fibers.len() - 1;
+        let mem = &mut self.mems[self.fibers[child].partition];
+        for byte in code {
+            self.fibers[child].text_section.append_data(mem, byte)?;
+        }
+        self.fibers[child].registers.set(Reg::PC, entry);
+        self.fibers[child].refresh_snapshot(mem)?;
+        if let Some(left) = self.fibers[parent].fuel {
+            self.fibers[child].fuel = Some(self.fibers[child].fuel.map_or(left, |fuel| fuel.min(left)));
+        }
+        self.fibers[child].spawns_left = self.fibers[parent].spawns_left.clone();
+        self.fibers[child].parent = Some(self.fibers[parent].id());
+        self.fibers[parent].children.push(id);
+        Ok(id)
+    }
+
+    /// Runs every fiber until all of them halted. A fault nobody caught
+    /// kills only the fiber that raised it, the others keep running. Once
+    /// everything is done the first of those faults is returned, the rest
+    /// stay available through `take_faults`. Blocks while every fiber left
+    /// waits for a deferred native or a CALLR that times out, and returns
+    /// once the only fibers left wait for messages nobody is going to send.
+    pub fn execute(&mut self) -> Result<(), Fault> {
+        while self.schedule()? {
+            if !self.is_runnable() {
+                let deadline = self.next_deadline();
+                if self.natives.is_waiting() {
+                    self.natives.wait(deadline);
+                } else if let Some(deadline) = deadline {
+                    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                } else {
+                    break;
+                }
+            }
+        }
+        self.finish()
+    }
+
+    /// whether a fiber can run without waiting for anything
+    pub(crate) fn is_runnable(&self) -> bool {
+        self.fibers.iter().any(|fiber| matches!(fiber.state(), FiberState::RUNNING | FiberState::BLOCKED))
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn natives(&self) -> &Natives {
+        &self.natives
+    }
+
+    /// the first fault collected, if any
+    pub(crate) fn finish(&mut self) -> Result<(), Fault> {
+        if self.faults.is_empty() {
+            Ok(())
+        } else {
+            Err(self.faults.remove(0))
+        }
+    }
+
+    /// Gives every fiber one slice. Returns whether any fiber can still run, uncaught
+    /// faults are collected for `take_faults` instead of being returned. With
+    /// `workers` above 1 and nothing tracing the slices run in parallel, what
+    /// they leave behind is still handled one fiber after the other in order.
+    pub fn schedule(&mut self) -> Result<bool, Fault> {
+        // TODO only for testing it'll break, reomve it later
+        if self.fibers.is_empty() {
+            return Ok(false);
+        }
+        let mut faults = self.natives.complete(&mut self.fibers, &mut self.mems);
+        faults.extend(self.resume_calls());
+        let mut kills: Vec<u64> = faults.iter().filter_map(|fault| fault.fiber).collect();
+        let mut ready = Vec::new();
+        for (idx, fiber) in self.fibers.iter_mut().enumerate() {
+            if matches!(fiber.state(), FiberState::HALTED | FiberState::WAITING) {
+                continue;
+            }
+            if self.config.verify && !fiber.is_verified()
+                && let Err(err) = fiber.verify(&self.mems[fiber.partition]) {
+                fiber.change_state(FiberState::HALTED);
+                kills.push(fiber.id());
+                faults.push(fiber.fault(err, fiber.registers().get(Reg::PC), None));
+                continue;
+            }
+            ready.push(idx);
+        }
+        if self.config.workers > 1 && self.tracer.is_none() && !self.profiling && !self.covering {
+            for (idx, res) in self.run_parallel(&ready) {
+                self.end_slice(idx, res, &mut kills, &mut faults);
+            }
+        } else {
+            for idx in ready {
+                let fiber = &mut self.fibers[idx];
+                let before = fiber.state();
+                if before != FiberState::RUNNING {
+                    emit(&mut self.tracer, Some(fiber.id()), Some(fiber.registers().get(Reg::PC)), None,
+                        Event::State { from: before, to: FiberState::RUNNING });
+                }
+                let mut tracer = self.tracer.as_deref_mut().map(|tracer| tracer as &mut dyn Tracer);
+                let mut covering;
+                if self.covering {
+                    covering = Tee { first: &mut self.coverage, second: tracer.take() };
+                    tracer = Some(&mut covering);
+                }
+                let mut profiling;
+                if self.profiling {
+                    profiling = Tee { first: &mut self.profile, second: tracer.take() };
+                    tracer = Some(&mut profiling);
+                }
+                let start = self.profiling.then(Instant::now);
+                let res = fiber.execute_traced(&mut self.mems[fiber.partition], tracer);
+                if let Some(start) = start {
+                    self.profile.add_slice(fiber.id(), start.elapsed());
                 }
+                self.end_slice(idx, res, &mut kills, &mut faults);
             }
+        }
+        for fault in self.settle() {
+            kills.extend(fault.fiber);
+            faults.push(fault);
+        }
+        for fault in faults {
+            emit(&mut self.tracer, fault.fiber, fault.pc, fault.instruction, Event::Fault { error: fault.error.to_string() });
+            self.faults.push(fault);
+        }
+        if !self.config.keep_halted {
             for id in kills {
                 self.kill(id)?;
             }
-        })
+        }
+        Ok(self.fibers.iter().any(|fiber| fiber.state() != FiberState::HALTED))
     }
 }
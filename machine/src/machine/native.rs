@@ -0,0 +1,258 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Condvar, Mutex}, time::Instant};
+
+use crate::{execptions::{Fault, MachineError}, fiber::{fiber::{Fiber, FiberState, Reg, Syscall}, value::{mismatch, Tag, Value}}, memory::memory::Memory};
+
+/// Where a native argument is taken from or a result is put.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    /// popped from or pushed onto the stack, checked against the tag on typed stacks
+    Stack(Tag),
+    /// read from or written to a register as an integer
+    Reg(Reg),
+}
+
+/// Arguments and results of a native. Stack arguments are popped in order,
+/// so the first one is the top of the stack. Stack results are pushed in
+/// order, the last one ends up on top.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub args: Vec<Slot>,
+    pub results: Vec<Slot>,
+}
+
+impl Signature {
+    pub fn new(args: &[Slot], results: &[Slot]) -> Self {
+        Self { args: args.to_vec(), results: results.to_vec() }
+    }
+}
+
+/// What a native did with the call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// results, in the order of the signature
+    Return(Vec<Value>),
+    /// the native took a `Pending` with `Call::defer` and completes it later,
+    /// the fiber waits until then
+    Deferred,
+}
+
+pub type Native = Box<dyn FnMut(&mut Call) -> Result<Outcome, MachineError> + Send>;
+
+/// A SYSCALL as the native sees it.
+pub struct Call<'a> {
+    pub fiber: u64,
+    pub args: Vec<Value>,
+    ticket: u64,
+    completions: &'a Arc<Completions>,
+    deferred: bool,
+}
+
+impl Call<'_> {
+    /// Takes the call over to finish it later, possibly from another thread.
+    /// The native has to return `Outcome::Deferred` afterwards.
+    pub fn defer(&mut self) -> Pending {
+        self.deferred = true;
+        Pending { ticket: self.ticket, completions: Some(self.completions.clone()) }
+    }
+}
+
+/// A deferred call. Dropping it without completing fails the call.
+pub struct Pending {
+    ticket: u64,
+    completions: Option<Arc<Completions>>,
+}
+
+impl Pending {
+    pub fn complete(mut self, result: Result<Vec<Value>, MachineError>) {
+        if let Some(completions) = self.completions.take() {
+            completions.push(self.ticket, result);
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Some(completions) = self.completions.take() {
+            completions.push(self.ticket, Err(MachineError::InvalidSyscall(Some("dropped without completing".to_string()))));
+        }
+    }
+}
+
+type Completion = (u64, Result<Vec<Value>, MachineError>);
+
+/// finished deferred calls, filled from any thread and drained by the machine
+#[derive(Default)]
+pub(crate) struct Completions {
+    queue: Mutex<VecDeque<Completion>>,
+    ready: Condvar,
+    /// task of an async driver waiting for the next completion
+    #[cfg(feature = "async")]
+    waker: Mutex<Option<std::task::Waker>>,
+}
+
+impl Completions {
+    fn push(&self, ticket: u64, result: Result<Vec<Value>, MachineError>) {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner()).push_back((ticket, result));
+        self.ready.notify_all();
+        #[cfg(feature = "async")]
+        if let Some(waker) = self.waker.lock().unwrap_or_else(|err| err.into_inner()).take() {
+            waker.wake();
+        }
+    }
+
+    fn drain(&self) -> Vec<Completion> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner()).drain(..).collect()
+    }
+
+    /// blocks until at least one call completed, or `deadline` passed
+    fn wait(&self, deadline: Option<Instant>) {
+        let mut queue = self.queue.lock().unwrap_or_else(|err| err.into_inner());
+        while queue.is_empty() {
+            queue = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return;
+                    }
+                    self.ready.wait_timeout(queue, deadline - now).unwrap_or_else(|err| err.into_inner()).0
+                },
+                None => self.ready.wait(queue).unwrap_or_else(|err| err.into_inner()),
+            };
+        }
+    }
+}
+
+/// a fiber parked on a deferred call
+struct Waiting {
+    fiber: u64,
+    /// where the fiber stopped, faults are reported there
+    address: u64,
+    results: Vec<Slot>,
+}
+
+/// Natives registered on a machine and the calls still waiting for them.
+#[derive(Default)]
+pub(crate) struct Natives {
+    table: HashMap<u64, (Signature, Native)>,
+    waiting: HashMap<u64, Waiting>,
+    next_ticket: u64,
+    completions: Arc<Completions>,
+}
+
+impl Natives {
+    pub(crate) fn register(&mut self, id: u64, signature: Signature, native: Native) {
+        self.table.insert(id, (signature, native));
+    }
+
+    pub(crate) fn is_waiting(&self) -> bool {
+        !self.waiting.is_empty()
+    }
+
+    pub(crate) fn wait(&self, deadline: Option<Instant>) {
+        self.completions.wait(deadline);
+    }
+
+    /// Has `waker` woken on the next completion. Returns whether one is
+    /// already there, in which case it won't be.
+    #[cfg(feature = "async")]
+    pub(crate) fn wake(&self, waker: &std::task::Waker) -> bool {
+        *self.completions.waker.lock().unwrap_or_else(|err| err.into_inner()) = Some(waker.clone());
+        !self.completions.queue.lock().unwrap_or_else(|err| err.into_inner()).is_empty()
+    }
+
+    /// Runs the native a fiber stopped for. Failures are raised inside the
+    /// fiber, only what nothing catches comes back.
+    pub(crate) fn dispatch(&mut self, fiber: &mut Fiber, mem: &mut Memory, syscall: Syscall) -> Result<(), Fault> {
+        let Some((signature, native)) = self.table.get_mut(&syscall.id) else {
+            return fiber.raise(mem, MachineError::InvalidSyscall(Some(format!("no native {:#x}", syscall.id))), syscall.address);
+        };
+        let args = match take_args(fiber, mem, &signature.args) {
+            Ok(args) => args,
+            Err(err) => return fiber.raise(mem, err, syscall.address),
+        };
+        self.next_ticket += 1;
+        let mut call = Call { fiber: fiber.id(), args, ticket: self.next_ticket, completions: &self.completions, deferred: false };
+        let res = match native(&mut call) {
+            Ok(Outcome::Return(values)) => put_results(fiber, mem, &signature.results, values),
+            Ok(Outcome::Deferred) if call.deferred => {
+                fiber.change_state(FiberState::WAITING);
+                self.waiting.insert(call.ticket, Waiting { fiber: fiber.id(), address: syscall.address, results: signature.results.clone() });
+                Ok(())
+            },
+            Ok(Outcome::Deferred) => Err(MachineError::InvalidSyscall(Some("deferred without taking the call".to_string()))),
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(()) => Ok(()),
+            Err(err) => fiber.raise(mem, err, syscall.address),
+        }
+    }
+
+    /// Parks a fiber that stopped at `address` until the returned handle
+    /// completes with `results`.
+    pub(crate) fn park(&mut self, fiber: &Fiber, address: u64, results: &[Slot]) -> Pending {
+        self.next_ticket += 1;
+        fiber.change_state(FiberState::WAITING);
+        self.waiting.insert(self.next_ticket, Waiting { fiber: fiber.id(), address, results: results.to_vec() });
+        Pending { ticket: self.next_ticket, completions: Some(self.completions.clone()) }
+    }
+
+    /// Hands finished deferred calls back to their fibers, which become
+    /// runnable again. Returns the faults nothing caught.
+    pub(crate) fn complete(&mut self, fibers: &mut [Fiber], mems: &mut [Memory]) -> Vec<Fault> {
+        let mut faults = Vec::new();
+        for (ticket, result) in self.completions.drain() {
+            let Some(waiting) = self.waiting.remove(&ticket) else {
+                continue;
+            };
+            // the fiber may have been killed in the meantime
+            let Some(fiber) = fibers.iter_mut().find(|fiber| fiber.id() == waiting.fiber) else {
+                continue;
+            };
+            fiber.change_state(FiberState::BLOCKED);
+            let mem = &mut mems[fiber.partition];
+            let res = result.and_then(|values| put_results(fiber, mem, &waiting.results, values));
+            if let Err(err) = res
+                && let Err(fault) = fiber.raise(mem, err, waiting.address) {
+                faults.push(fault);
+            }
+        }
+        faults
+    }
+}
+
+fn take_args(fiber: &mut Fiber, mem: &mut Memory, slots: &[Slot]) -> Result<Vec<Value>, MachineError> {
+    slots.iter().map(|slot| match slot {
+        Slot::Stack(tag) => {
+            let (found, bits) = fiber.pop_tagged(mem)?;
+            if fiber.is_typed() && found != *tag {
+                return Err(mismatch(*tag, found));
+            }
+            Fiber::to_value(mem, *tag, bits)
+        },
+        Slot::Reg(reg) => Ok(Value::Int(fiber.registers().get(*reg) as i64)),
+    }).collect()
+}
+
+fn put_results(fiber: &mut Fiber, mem: &mut Memory, slots: &[Slot], values: Vec<Value>) -> Result<(), MachineError> {
+    if values.len() != slots.len() {
+        return Err(MachineError::InvalidSyscall(Some(format!("{} results for a signature of {}", values.len(), slots.len()))));
+    }
+    for (slot, value) in slots.iter().zip(values) {
+        match slot {
+            Slot::Stack(tag) if value.tag() != *tag => return Err(mismatch(*tag, value.tag())),
+            Slot::Stack(_) => fiber.push_value(mem, value)?,
+            Slot::Reg(reg) => {
+                let bits = match value {
+                    Value::Int(val) => val as u64,
+                    Value::Bool(val) => val as u64,
+                    Value::Ref(val) => val,
+                    Value::Actor(actor) => actor.to_bits(),
+                    other => return Err(mismatch(Tag::Int, other.tag())),
+                };
+                fiber.registers().set(*reg, bits);
+            },
+        }
+    }
+    Ok(())
+}
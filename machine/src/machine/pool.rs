@@ -0,0 +1,93 @@
+use std::{collections::VecDeque, panic::{self, AssertUnwindSafe}, sync::{mpsc::{self, Receiver, Sender}, Arc, Condvar, Mutex, MutexGuard}, thread::{self, JoinHandle}};
+
+fn lock<T>(queue: &Mutex<T>) -> MutexGuard<'_, T> {
+    queue.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// what the workers share with the pool
+struct Queues<J> {
+    queues: Vec<Mutex<VecDeque<J>>>,
+    /// bumped whenever jobs were queued, `None` once the pool is dropped
+    round: Mutex<Option<u64>>,
+    wake: Condvar,
+}
+
+/// Worker threads that live as long as the pool and run `work` over the
+/// jobs of every `run`. Jobs are dealt out round robin, a worker that runs
+/// out takes jobs from the back of the others' queues.
+pub(crate) struct Pool<J, R> {
+    shared: Arc<Queues<J>>,
+    results: Receiver<thread::Result<R>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<J: Send + 'static, R: Send + 'static> Pool<J, R> {
+    pub(crate) fn new(workers: usize, work: fn(J) -> R) -> Self {
+        let workers = workers.max(1);
+        let shared = Arc::new(Queues {
+            queues: (0..workers).map(|_| Mutex::default()).collect(),
+            round: Mutex::new(Some(0)),
+            wake: Condvar::new(),
+        });
+        let (sender, results) = mpsc::channel();
+        let threads = (0..workers).map(|worker| {
+            let (shared, sender) = (shared.clone(), sender.clone());
+            thread::spawn(move || serve(&shared, worker, work, sender))
+        }).collect();
+        Self { shared, results, threads }
+    }
+
+    /// Runs every job and waits for all of them. Results come back in no
+    /// particular order, a job that panicked panics here.
+    pub(crate) fn run(&self, jobs: Vec<J>) -> Vec<R> {
+        let count = jobs.len();
+        let workers = self.shared.queues.len();
+        for (idx, job) in jobs.into_iter().enumerate() {
+            lock(&self.shared.queues[idx % workers]).push_back(job);
+        }
+        if let Some(round) = lock(&self.shared.round).as_mut() {
+            *round += 1;
+        }
+        self.shared.wake.notify_all();
+        (0..count).map(|_| match self.results.recv() {
+            Ok(Ok(res)) => res,
+            Ok(Err(panic)) => panic::resume_unwind(panic),
+            Err(_) => panic!("pool workers are gone"),
+        }).collect()
+    }
+}
+
+impl<J, R> Drop for Pool<J, R> {
+    fn drop(&mut self) {
+        *lock(&self.shared.round) = None;
+        self.shared.wake.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// a worker's loop, sleeps between runs until the pool is dropped
+fn serve<J, R>(shared: &Queues<J>, worker: usize, work: fn(J) -> R, results: Sender<thread::Result<R>>) {
+    loop {
+        let Some(seen) = *lock(&shared.round) else {
+            return;
+        };
+        while let Some(job) = next(&shared.queues, worker) {
+            let _ = results.send(panic::catch_unwind(AssertUnwindSafe(|| work(job))));
+        }
+        // jobs are queued before the round moves on, so none get missed
+        let mut round = lock(&shared.round);
+        while *round == Some(seen) {
+            round = shared.wake.wait(round).unwrap_or_else(|err| err.into_inner());
+        }
+    }
+}
+
+/// the worker's own next job, or one stolen from the others
+fn next<J>(queues: &[Mutex<VecDeque<J>>], worker: usize) -> Option<J> {
+    if let Some(job) = lock(&queues[worker]).pop_front() {
+        return Some(job);
+    }
+    (1..queues.len()).find_map(|offset| lock(&queues[(worker + offset) % queues.len()]).pop_back())
+}
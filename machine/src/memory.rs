@@ -1,4 +1,5 @@
 pub mod memory;
 pub mod access;
 pub mod allocation;
-pub mod hexdump;
\ No newline at end of file
+pub mod hexdump;
+pub mod store;
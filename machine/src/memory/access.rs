@@ -1,77 +1,53 @@
-use crate::{execptions::MachineError, memory::memory::Memory, utils::binary::{Combine, Split}};
+use crate::{execptions::MachineError, memory::memory::{Endianness, Memory}};
 
-impl Memory {
-    pub fn read_u8(&self, address: usize) -> Result<u8, MachineError> {
-        if address >= self.data.len() {
-            return Err(MachineError::InvalidAddress(None));
-        }
-        Ok(self.data[address])
-    }
-
-    pub fn read_u16(&self, address: usize) -> Result<u16, MachineError> {
-        if address + 1 >= self.data.len() {
-            return Err(MachineError::InvalidAddress(None));
-        }
-        Ok(u16::combine((self.data[address],self.data[address + 1])))
-    }
-
-    pub fn read_u32(&self, address: usize) -> Result<u32, MachineError> {
-        if address + 3 >= self.data.len() {
-            return Err(MachineError::InvalidAddress(None));
+macro_rules! access {
+    ($read:ident, $write:ident, $ty:ty) => {
+        pub fn $read(&self, address: usize) -> Result<$ty, MachineError> {
+            let bytes = self.bytes(address)?;
+            Ok(match self.endianness {
+                Endianness::Little => <$ty>::from_le_bytes(bytes),
+                Endianness::Big => <$ty>::from_be_bytes(bytes),
+            })
         }
-        Ok(u32::combine((self.data[address],self.data[address + 1],self.data[address + 2],self.data[address + 3])))
-    }
 
-    pub fn read_u64(&self, address: usize) -> Result<u64, MachineError> {
-        if address + 7 >= self.data.len() {
-            return Err(MachineError::InvalidAddress(None));
+        pub fn $write(&mut self, address: usize, val: $ty) -> Result<(), MachineError> {
+            let bytes = match self.endianness {
+                Endianness::Little => val.to_le_bytes(),
+                Endianness::Big => val.to_be_bytes(),
+            };
+            self.put_bytes(address, &bytes)
         }
-        Ok(u64::combine((self.data[address],self.data[address + 1],self.data[address + 2],self.data[address + 3],self.data[address + 4],self.data[address + 5],self.data[address + 6],self.data[address + 7])))
-    }
+    };
+}
 
-    pub fn write_u8(&mut self, address: usize, val: u8) -> Result<(), MachineError> {
-        if address >= self.data.len() {
-            return Err(MachineError::InvalidAddress(None));
-        }
-        self.data[address] = val;
+impl Memory {
+    #[inline(always)]
+    fn bytes<const N: usize>(&self, address: usize) -> Result<[u8; N], MachineError> {
+        address.checked_add(N)
+            .and_then(|end| self.store.bytes().get(address..end))
+            .and_then(|slice| slice.try_into().ok())
+            .ok_or(MachineError::InvalidAddress(None))
+    }
+
+    #[inline(always)]
+    fn put_bytes(&mut self, address: usize, bytes: &[u8]) -> Result<(), MachineError> {
+        address.checked_add(bytes.len())
+            .and_then(|end| self.store.bytes_mut().get_mut(address..end))
+            .ok_or(MachineError::InvalidAddress(None))?
+            .copy_from_slice(bytes);
         Ok(())
     }
 
-    pub fn write_u16(&mut self, address: usize, val: u16) -> Result<(), MachineError> {
-        if address + 1 >= self.data.len() {
-            return Err(MachineError::InvalidAddress(None));
-        }
-        let res: (u8, u8) = u16::split(val);
-        self.data[address] = res.0;
-        self.data[address + 1] = res.1;
-        Ok(())
-    }
+    access!(read_u8, write_u8, u8);
+    access!(read_u16, write_u16, u16);
+    access!(read_u32, write_u32, u32);
+    access!(read_u64, write_u64, u64);
 
-    pub fn write_u32(&mut self, address: usize, val: u32) -> Result<(), MachineError> {
-        if address + 3 >= self.data.len() {
-            return Err(MachineError::InvalidAddress(None));
-        }
-        let res: (u8, u8, u8, u8) = u32::split(val);
-        self.data[address] = res.0;
-        self.data[address + 1] = res.1;
-        self.data[address + 2] = res.2;
-        self.data[address + 3] = res.3;
-        Ok(())
-    }
+    access!(read_i8, write_i8, i8);
+    access!(read_i16, write_i16, i16);
+    access!(read_i32, write_i32, i32);
+    access!(read_i64, write_i64, i64);
 
-    pub fn write_u64(&mut self, address: usize, val: u64) -> Result<(), MachineError> {
-        if address + 7 >= self.data.len() {
-            return Err(MachineError::InvalidAddress(None));
-        }
-        let res: (u8, u8, u8, u8, u8, u8, u8, u8) = u64::split(val);
-        self.data[address] = res.0;
-        self.data[address + 1] = res.1;
-        self.data[address + 2] = res.2;
-        self.data[address + 3] = res.3;
-        self.data[address + 4] = res.4;
-        self.data[address + 5] = res.5;
-        self.data[address + 6] = res.6;
-        self.data[address + 7] = res.7;
-        Ok(())
-    }
-}
\ No newline at end of file
+    access!(read_f32, write_f32, f32);
+    access!(read_f64, write_f64, f64);
+}
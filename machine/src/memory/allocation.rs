@@ -1,6 +1,6 @@
 use std::ops::Range;
 
-use crate::{execptions::MachineError, memory::memory::Memory, utils::normalize::normalize_size};
+use crate::{execptions::MachineError, memory::memory::Memory, trace::event::Event, utils::normalize::normalize_size};
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct Pointer {
@@ -9,17 +9,24 @@ pub struct Pointer {
 }
 
 impl Memory {
-    // first-fit
     pub fn allocate(&mut self, size: usize) -> Result<Pointer, MachineError> {
+        let ptr = self.first_fit(size)?;
+        if let Some(journal) = &mut self.journal {
+            journal.push(Event::Allocate { address: ptr.address, size: ptr.size });
+        }
+        Ok(ptr)
+    }
+
+    // first-fit
+    fn first_fit(&mut self, size: usize) -> Result<Pointer, MachineError> {
         let size = normalize_size(size);
         self.blocks.sort_by_key(|x| x.start);
 
-        if let Some(block) = self.blocks.first() {
-            if block.start > 0 && block.start > size {
-                self.blocks.push(0..size);
-                self.set_zero(0..size)?;
-                return Ok(Pointer{address: 0, size: size});
-            }
+        if let Some(block) = self.blocks.first()
+            && block.start > 0 && block.start > size {
+            self.blocks.push(0..size);
+            self.set_zero(0..size)?;
+            return Ok(Pointer{address: 0, size});
         }
 
         let windows: Vec<_> = self.blocks.windows(2).map(|w| (w[0].end, w[1].start)).collect();
@@ -31,52 +38,44 @@ impl Memory {
             }
         }
 
-        if let Some(block) = self.blocks.last() {
-            let block_end = block.end;
-            if block_end + size < self.data.len() {
-                self.blocks.push(block_end..block_end + size);
-                self.set_zero(block_end..block_end + size)?;
-                return Ok(Pointer{address: block_end, size: size});
-            }
-        } else {
-            if self.blocks.len() == 0 && size < self.data.len() {
-                self.blocks.push(0..size);
-                self.set_zero(0..size)?;
-                return Ok(Pointer { address: 0, size });
-            }
+        let end = self.blocks.last().map(|block| block.end).unwrap_or(0);
+        if end + size >= self.store.len() {
+            // nothing fits, try to grow before giving up
+            self.grow_to_fit(end + size + 1)?;
         }
-
-        Err(MachineError::InsufficientMemory(None))
+        self.blocks.push(end..end + size);
+        self.set_zero(end..end + size)?;
+        Ok(Pointer { address: end, size })
     }
 
     fn set_zero(&mut self, range: Range<usize>) -> Result<(), MachineError> {
-        if range.end > self.data.len() {
+        if range.end > self.store.len() {
             return Err(MachineError::InsufficientMemory(None));
         }
-        for idx in range {
-            self.data[idx] = 0;
-        }
+        self.store.bytes_mut()[range].fill(0);
         Ok(())
     }
 
     pub fn deallocate(&mut self, ptr: &Pointer) -> Result<(), MachineError> {
         if let Some(idx) = self.blocks.iter().position(|x| x.start == ptr.address) {
-            self.blocks.swap_remove(idx);
+            let block = self.blocks.swap_remove(idx);
+            if let Some(journal) = &mut self.journal {
+                journal.push(Event::Deallocate { address: block.start, size: block.len() });
+            }
             Ok(())
         } else {
             Err(MachineError::InvalidPointer(None))
         }
     }
 
-    // TODO enable grow/shrink for better performance
+    // TODO enable in-place grow/shrink for better performance
     pub fn reallocate(&mut self, ptr: &Pointer, size: usize) -> Result<Pointer, MachineError> {
         if ptr.size == size {
             return Ok(ptr.clone());
         }
         let new_ptr = self.allocate(size)?;
-        for idx in 0..size {
-            self.data[new_ptr.address + idx] = self.data[ptr.address + idx];
-        }
+        let len = size.min(ptr.size);
+        self.store.bytes_mut().copy_within(ptr.address..ptr.address + len, new_ptr.address);
         self.deallocate(ptr)?;
         Ok(new_ptr)
     }
@@ -5,7 +5,7 @@ use crate::memory::memory::Memory;
 pub fn hexdump(mem: &Memory, range: Range<usize>) {
     let mut cur = range.start;
     let mut result = String::new();
-    for chunk in mem.data[range].chunks(8) {
+    for chunk in mem.store.bytes()[range].chunks(8) {
         let mut line = String::new();
         line.push_str(format!("0x{:08x} | ", cur).as_str());
         let mut printable = String::new();
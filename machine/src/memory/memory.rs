@@ -1,18 +1,84 @@
 use std::ops::Range;
 
-use crate::execptions::MachineError;
+use crate::{execptions::MachineError, memory::store::{BackingStore, RamStore}, trace::event::Event};
+
+pub const PAGE_SIZE: usize = 4 * 1024;
+
+/// Byte order used for every multi-byte access. New images are little-endian,
+/// big-endian is kept for images written before the setting existed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
 
 #[derive(Debug)]
 pub struct Memory {
-    pub(crate) data: Vec<u8>,
+    pub(crate) store: Box<dyn BackingStore>,
     pub(crate) blocks: Vec<Range<usize>>,
+    pub(crate) max_size: usize,
+    pub(crate) endianness: Endianness,
+    /// allocation events not yet handed to a tracer, `None` when nobody listens
+    pub(crate) journal: Option<Vec<Event>>,
 }
 
 impl Memory {
+    /// fixed size memory kept in RAM
     pub fn new(size: usize) -> Result<Self, MachineError> {
+        Self::with_store(Box::new(RamStore::new(size)), size)
+    }
+
+    /// RAM memory that grows page by page up to `max_size`
+    pub fn growable(size: usize, max_size: usize) -> Result<Self, MachineError> {
+        Self::with_store(Box::new(RamStore::new(size)), max_size)
+    }
+
+    pub fn with_store(store: Box<dyn BackingStore>, max_size: usize) -> Result<Self, MachineError> {
+        if store.len() > max_size {
+            return Err(MachineError::InsufficientMemory(Some(format!("initial size {} exceeds maximum {}", store.len(), max_size))));
+        }
         Ok(Self {
-            data: vec![0u8; size],
+            store,
             blocks: Vec::new(),
+            max_size,
+            endianness: Endianness::default(),
+            journal: None,
         })
     }
-}
\ No newline at end of file
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    pub fn size(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub(crate) fn set_journal(&mut self, enabled: bool) {
+        self.journal = enabled.then(Vec::new);
+    }
+
+    /// allocation events since the last call
+    pub(crate) fn take_journal(&mut self) -> Vec<Event> {
+        self.journal.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// grows the backing store so that it is larger than `required` bytes,
+    /// rounded up to whole pages and capped by `max_size`
+    pub(crate) fn grow_to_fit(&mut self, required: usize) -> Result<(), MachineError> {
+        if required > self.max_size {
+            return Err(MachineError::InsufficientMemory(None));
+        }
+        let size = required.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        self.store.grow(size.min(self.max_size))
+    }
+}
@@ -0,0 +1,119 @@
+use std::{fmt::Debug, fs::{File, OpenOptions}, path::{Path, PathBuf}};
+
+use memmap2::MmapMut;
+
+use crate::execptions::MachineError;
+
+/// Raw bytes behind a `Memory`. Addresses handed out by the allocator are
+/// offsets into this store, so growing it never invalidates a `Pointer`.
+pub trait BackingStore: Debug + Send {
+    fn bytes(&self) -> &[u8];
+    fn bytes_mut(&mut self) -> &mut [u8];
+    /// grows the store to `size` bytes, new bytes are zeroed
+    fn grow(&mut self, size: usize) -> Result<(), MachineError>;
+
+    fn len(&self) -> usize {
+        self.bytes().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug)]
+pub struct RamStore {
+    data: Vec<u8>,
+}
+
+impl RamStore {
+    pub fn new(size: usize) -> Self {
+        Self { data: vec![0u8; size] }
+    }
+}
+
+impl BackingStore for RamStore {
+    fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    fn grow(&mut self, size: usize) -> Result<(), MachineError> {
+        if size > self.data.len() {
+            self.data.resize(size, 0);
+        }
+        Ok(())
+    }
+}
+
+/// Memory-mapped file store. The file is extended sparsely, so pages that
+/// are never touched are never read from or written to disk.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    map: Option<MmapMut>,
+}
+
+impl FileStore {
+    pub fn new<P: AsRef<Path>>(path: P, size: usize) -> Result<Self, MachineError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| MachineError::InsufficientMemory(Some(format!("{}: {}", path.display(), e))))?;
+        let mut store = Self { path, file, map: None };
+        store.remap(size)?;
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn remap(&mut self, size: usize) -> Result<(), MachineError> {
+        // the old mapping has to go before the file is resized under it
+        self.map = None;
+        self.file.set_len(size as u64)
+            .map_err(|e| MachineError::InsufficientMemory(Some(e.to_string())))?;
+        if size > 0 {
+            // SAFETY: the file is private to this store and only ever accessed through the mapping
+            let map = unsafe { MmapMut::map_mut(&self.file) }
+                .map_err(|e| MachineError::InsufficientMemory(Some(e.to_string())))?;
+            self.map = Some(map);
+        }
+        Ok(())
+    }
+}
+
+impl BackingStore for FileStore {
+    fn bytes(&self) -> &[u8] {
+        match &self.map {
+            Some(map) => map,
+            None => &[],
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        match &mut self.map {
+            Some(map) => map,
+            None => &mut [],
+        }
+    }
+
+    fn grow(&mut self, size: usize) -> Result<(), MachineError> {
+        if size > self.len() {
+            if let Some(map) = &self.map {
+                map.flush().map_err(|e| MachineError::InsufficientMemory(Some(e.to_string())))?;
+            }
+            self.remap(size)?;
+        }
+        Ok(())
+    }
+}
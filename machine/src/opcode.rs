@@ -1,2 +1,8 @@
 pub mod commands;
-pub mod opcodes;
\ No newline at end of file
+pub mod opcodes;
+pub mod instruction;
+pub mod verifier;
+pub mod cache;
+pub mod peephole;
+pub mod tier;
+pub mod assembler;
@@ -0,0 +1,99 @@
+use crate::{execptions::MachineError, fiber::{fiber::Reg, value::Tag}, opcode::{instruction::{Instruction, Operands}, opcodes::{OperandKind, Opcodes}}};
+
+fn syntax(detail: String) -> MachineError {
+    MachineError::InvalidBytecode(Some(detail))
+}
+
+fn opcode(name: &str) -> Result<Opcodes, MachineError> {
+    (0..=u8::MAX as u16)
+        .filter_map(|raw| Opcodes::try_from(raw).ok())
+        .find(|opcode| opcode.to_string().eq_ignore_ascii_case(name))
+        .ok_or_else(|| MachineError::InvalidOpcode(Some(format!("unknown mnemonic {}", name))))
+}
+
+fn register(name: &str) -> Result<Reg, MachineError> {
+    [Reg::R0, Reg::R1, Reg::R2, Reg::R3, Reg::R4, Reg::R5, Reg::R6, Reg::R7, Reg::PC, Reg::SP].into_iter()
+        .find(|reg| reg.to_string().eq_ignore_ascii_case(name))
+        .ok_or_else(|| syntax(format!("unknown register {}", name)))
+}
+
+fn tag(name: &str) -> Result<Tag, MachineError> {
+    (0..=u8::MAX)
+        .map_while(|raw| Tag::from_u8(raw).ok())
+        .find(|tag| tag.to_string().eq_ignore_ascii_case(name))
+        .ok_or_else(|| syntax(format!("unknown tag {}", name)))
+}
+
+/// `42`, `-1`, `0x2a` or `#2a` for an address
+fn immediate(text: &str) -> Result<u64, MachineError> {
+    let parsed = if let Some(hex) = text.strip_prefix('#').or_else(|| text.strip_prefix("0x")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if text.starts_with('-') {
+        text.parse::<i64>().ok().map(|val| val as u64)
+    } else {
+        text.parse::<u64>().ok()
+    };
+    parsed.ok_or_else(|| syntax(format!("invalid immediate {}", text)))
+}
+
+fn typed(tag: Tag, text: &str) -> Result<u64, MachineError> {
+    match tag {
+        Tag::Float => text.parse::<f64>()
+            .map(f64::to_bits)
+            .map_err(|_| syntax(format!("invalid float {}", text))),
+        Tag::Bool => match text {
+            "true" => Ok(1),
+            "false" => Ok(0),
+            _ => immediate(text),
+        },
+        _ => immediate(text),
+    }
+}
+
+/// Assembles one line in the syntax instructions are displayed with, e.g.
+/// `MOV R0, 5`, `JNZ #a` or `PUSHT Float, 1.5`. Anything after `;` is a
+/// comment. The instruction is placed at `address`.
+pub fn assemble(line: &str, address: u64) -> Result<Instruction, MachineError> {
+    let line = line.split(';').next().unwrap_or_default().trim();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let opcode = opcode(name)?;
+    let args: Vec<&str> = match rest.trim() {
+        "" => Vec::new(),
+        rest => rest.split(',').map(str::trim).collect(),
+    };
+    let expected = match opcode.operand_kind() {
+        OperandKind::None => 0,
+        OperandKind::Reg | OperandKind::Imm => 1,
+        OperandKind::RegImm | OperandKind::TagImm => 2,
+    };
+    if args.len() != expected {
+        return Err(syntax(format!("{} takes {} operands, got {}", opcode, expected, args.len())));
+    }
+    let operands = match opcode.operand_kind() {
+        OperandKind::None => Operands::None,
+        OperandKind::Reg => Operands::Reg(register(args[0])?),
+        OperandKind::Imm => Operands::Imm(immediate(args[0])?),
+        OperandKind::RegImm => Operands::RegImm(register(args[0])?, immediate(args[1])?),
+        OperandKind::TagImm => {
+            let tag = tag(args[0])?;
+            Operands::TagImm(tag, typed(tag, args[1])?)
+        },
+    };
+    Ok(Instruction { address, opcode, operands })
+}
+
+impl Instruction {
+    /// Encodes the instruction as `(data type, value)` pairs for
+    /// `Machine::write_bytecodes`.
+    pub fn bytecodes(&self) -> Vec<u64> {
+        let mut out = vec![1, self.opcode as u64];
+        match self.operands {
+            Operands::None => {},
+            Operands::Reg(reg) => out.extend([0, reg.to_u8() as u64]),
+            Operands::Imm(val) => out.extend([3, val]),
+            Operands::RegImm(reg, val) => out.extend([0, reg.to_u8() as u64, 3, val]),
+            Operands::TagImm(tag, val) => out.extend([0, tag as u64, 3, val]),
+        }
+        out
+    }
+}
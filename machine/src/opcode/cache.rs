@@ -0,0 +1,94 @@
+use crate::{execptions::MachineError, fiber::section::Section, memory::memory::Memory, opcode::{instruction::{decode, Instruction}, peephole::{fuse, Fused}, verifier::verify}};
+
+/// What the dispatch loop finds at a PC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Single(Instruction),
+    Fused(Fused),
+}
+
+/// Instructions of a text section decoded on first use and kept in an array
+/// indexed by PC. Any write to the section drops everything decoded so far.
+///
+/// With `optimize` set the whole section is decoded up front and runs found
+/// by the peephole pass are stored at their first address. That needs the
+/// section to pass the verifier, which is what proves where jumps can land;
+/// sections it rejects are decoded lazily without fusing.
+#[derive(Debug, Default)]
+pub struct DecodeCache {
+    generation: u64,
+    slots: Vec<Option<Op>>,
+    optimize: bool,
+}
+
+impl DecodeCache {
+    pub fn new(optimize: bool) -> Self {
+        Self { optimize, ..Default::default() }
+    }
+
+    pub fn optimize(&self) -> bool {
+        self.optimize
+    }
+
+    #[inline(always)]
+    pub fn get(&mut self, mem: &Memory, section: &Section, pc: usize) -> Result<Op, MachineError> {
+        if self.generation != section.generation() {
+            self.rebuild(mem, section);
+        }
+        if let Some(Some(op)) = self.slots.get(pc) {
+            return Ok(*op);
+        }
+        let instr = decode(mem, section, pc)?;
+        // only instructions that lie completely inside the section are kept,
+        // a partly written one has to be read again once the rest arrives
+        if instr.next() as usize <= section.len()
+            && let Some(slot) = self.slots.get_mut(pc) {
+            *slot = Some(Op::Single(instr));
+        }
+        Ok(Op::Single(instr))
+    }
+
+    fn rebuild(&mut self, mem: &Memory, section: &Section) {
+        self.generation = section.generation();
+        self.slots.clear();
+        self.slots.resize(section.len(), None);
+        if !self.optimize {
+            return;
+        }
+        let Ok(verification) = verify(mem, section) else {
+            return;
+        };
+        let instrs: Vec<Instruction> = verification.depths.keys()
+            .filter_map(|address| decode(mem, section, *address as usize).ok())
+            .collect();
+        for instr in &instrs {
+            self.slots[instr.address as usize] = Some(Op::Single(*instr));
+        }
+        // the instructions inside a run keep their own slot in case the
+        // host points PC at one of them
+        for (address, fused) in fuse(&instrs, &verification.targets) {
+            self.slots[address as usize] = Some(Op::Fused(fused));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+    }
+
+    /// number of slots holding a decoded instruction or run
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// fused runs currently held
+    pub fn fused(&self) -> impl Iterator<Item = &Fused> {
+        self.slots.iter().filter_map(|slot| match slot {
+            Some(Op::Fused(fused)) => Some(fused),
+            _ => None,
+        })
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{execptions::MachineError, fiber::fiber::{Fiber, Flag, Reg}, memory::memory::Memory};
+use crate::{execptions::MachineError, fiber::{actor::ActorId, capability::Capability, fiber::{Fiber, Flag, Handler, Reg, Rpc}, value::{mismatch, Tag, Value}}, machine::device::{STDERR, STDOUT}, memory::memory::Memory};
 
 pub fn push(mem: &mut Memory, fib: &mut Fiber, value: u64) -> Result<(), MachineError> {
     fib.push(mem, value)
@@ -10,32 +10,74 @@ pub fn pop(mem: &mut Memory, fib: &mut Fiber, reg: Reg) -> Result<(), MachineErr
     fib.set_register(mem, reg, val)
 }
 
+/// Pushes a literal with an explicit tag. Reference tags are refused so
+/// bytecode can never make up a reference on its own.
+pub fn pusht(mem: &mut Memory, fib: &mut Fiber, tag: Tag, value: u64) -> Result<(), MachineError> {
+    if tag.is_reference() {
+        return Err(MachineError::TypeMismatch(Some(format!("cannot push a {} literal", tag))));
+    }
+    fib.push_tagged(mem, tag, value)
+}
+
 pub fn mov(mem: &mut Memory, fib: &Fiber, reg: Reg, num: u64) -> Result<(), MachineError> {
+    check_writable(fib, reg)?;
     fib.set_register(mem, reg, num)
 }
 
+/// SP can't be moved by hand on a typed stack, the tags would no longer line up
+fn check_writable(fib: &Fiber, reg: Reg) -> Result<(), MachineError> {
+    if fib.is_typed() && reg == Reg::SP {
+        return Err(MachineError::TypeMismatch(Some("SP is read-only on a typed stack".to_string())));
+    }
+    Ok(())
+}
+
+/// pops two operands for a bitwise operation, both `Int` or both `Bool`
+fn pop_bitwise(mem: &mut Memory, fib: &mut Fiber) -> Result<(Tag, u64, u64), MachineError> {
+    let (tag, a) = fib.pop_tagged(mem)?;
+    if tag != Tag::Int && tag != Tag::Bool {
+        return Err(mismatch(Tag::Int, tag));
+    }
+    let b = fib.pop_typed(mem, tag)?;
+    Ok((tag, a, b))
+}
+
 pub fn add(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)? as i64;
-    let b = fib.pop(mem)? as i64;
+    let a = fib.pop_typed(mem, Tag::Int)?;
+    let b = fib.pop_typed(mem, Tag::Int)?;
+    let c = add_values(mem, fib, a, b)?;
+    fib.push(mem, c)
+}
+
+/// `a + b` with the flags ADD sets
+pub(crate) fn add_values(mem: &mut Memory, fib: &Fiber, a: u64, b: u64) -> Result<u64, MachineError> {
+    let (a, b) = (a as i64, b as i64);
     let c = a.wrapping_add(b);
     fib.set_flag(mem, Flag::Zero, c == 0)?;
     fib.set_flag(mem, Flag::Negative, c < 0)?;
     fib.set_flag(mem, Flag::Overflow, (a > 0 && b > 0 && c < 0) || (a < 0 && b < 0 && c > 0))?;
     let carry = (a as u64).overflowing_add(b as u64).1;
     fib.set_flag(mem, Flag::Carry, carry)?;
-    fib.push(mem, c as u64)
+    Ok(c as u64)
 }
 
 pub fn sub(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)? as i64;
-    let b = fib.pop(mem)? as i64;
+    let a = fib.pop_typed(mem, Tag::Int)?;
+    let b = fib.pop_typed(mem, Tag::Int)?;
+    let c = sub_values(mem, fib, a, b)?;
+    fib.push(mem, c)
+}
+
+/// `a - b` with the flags SUB sets
+pub(crate) fn sub_values(mem: &mut Memory, fib: &Fiber, a: u64, b: u64) -> Result<u64, MachineError> {
+    let (a, b) = (a as i64, b as i64);
     let c = a.wrapping_sub(b);
     fib.set_flag(mem, Flag::Zero, c == 0)?;
     fib.set_flag(mem, Flag::Negative, c < 0)?;
     fib.set_flag(mem, Flag::Overflow, (a > 0 && b < 0 && c < 0) || (a < 0 && b > 0 && c > 0))?;
     let borrow = (a as u64).overflowing_sub(b as u64).1;
     fib.set_flag(mem, Flag::Carry, !borrow)?;
-    fib.push(mem, c as u64)
+    Ok(c as u64)
 }
 
 pub fn drop(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
@@ -45,8 +87,8 @@ pub fn drop(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
 }
 
 pub fn dup(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let val = fib.peek(mem)?;
-    fib.push(mem, val)
+    let (tag, val) = fib.peek_tagged(mem)?;
+    fib.push_tagged(mem, tag, val)
 }
 
 pub fn swap(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
@@ -54,13 +96,15 @@ pub fn swap(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
 }
 
 pub fn inc(mem: &mut Memory, fib: &Fiber, reg: Reg) -> Result<(), MachineError> {
-    let val = fib.get_register(mem, reg.clone())?;
+    check_writable(fib, reg)?;
+    let val = fib.get_register(mem, reg)?;
     let res = val.wrapping_add(1);
     fib.set_register(mem, reg, res)
 }
 
 pub fn dec(mem: &mut Memory, fib: &Fiber, reg: Reg) -> Result<(), MachineError> {
-    let val = fib.get_register(mem, reg.clone())?;
+    check_writable(fib, reg)?;
+    let val = fib.get_register(mem, reg)?;
     let res = val.wrapping_sub(1);
     fib.set_register(mem, reg, res)
 }
@@ -112,56 +156,281 @@ pub fn jle(mem: &mut Memory, fib: &Fiber, address: usize) -> Result<(), MachineE
 }
 
 pub fn and(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)?;
-    let b = fib.pop(mem)?;
+    let (tag, a, b) = pop_bitwise(mem, fib)?;
     let c = a & b;
-    fib.push(mem, c)
+    fib.push_tagged(mem, tag, c)
 }
 
 pub fn or(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)?;
-    let b = fib.pop(mem)?;
+    let (tag, a, b) = pop_bitwise(mem, fib)?;
     let c = a | b;
-    fib.push(mem, c)
+    fib.push_tagged(mem, tag, c)
 }
 
 pub fn not(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)?;
-    let b = !a;
-    fib.push(mem, b)
+    let (tag, a) = fib.pop_tagged(mem)?;
+    match tag {
+        Tag::Int => fib.push(mem, !a),
+        // keep booleans 0 or 1
+        Tag::Bool => fib.push_tagged(mem, Tag::Bool, (a == 0) as u64),
+        _ => Err(mismatch(Tag::Int, tag)),
+    }
 }
 
 pub fn xor(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)?;
-    let b = fib.pop(mem)?;
+    let (tag, a, b) = pop_bitwise(mem, fib)?;
     let c = a ^ b;
-    fib.push(mem, c)
+    fib.push_tagged(mem, tag, c)
 }
 
 pub fn shl(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)?;
-    let b = fib.pop(mem)?;
+    let a = fib.pop_typed(mem, Tag::Int)?;
+    let b = fib.pop_typed(mem, Tag::Int)?;
     let c = a << b;
     fib.push(mem, c)
 }
 
 pub fn shr(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)?;
-    let b = fib.pop(mem)?;
+    let a = fib.pop_typed(mem, Tag::Int)?;
+    let b = fib.pop_typed(mem, Tag::Int)?;
     let c = a >> b;
     fib.push(mem, c)
 }
 
 pub fn rol(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)?;
-    let b = fib.pop(mem)?;
+    let a = fib.pop_typed(mem, Tag::Int)?;
+    let b = fib.pop_typed(mem, Tag::Int)?;
     let c = a.rotate_left(b as u32);
     fib.push(mem, c)
 }
 
 pub fn ror(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
-    let a = fib.pop(mem)?;
-    let b = fib.pop(mem)?;
+    let a = fib.pop_typed(mem, Tag::Int)?;
+    let b = fib.pop_typed(mem, Tag::Int)?;
     let c = a.rotate_right(b as u32);
     fib.push(mem, c)
-}
\ No newline at end of file
+}
+
+/// Sets the flags after a float operation: Zero and Negative follow the
+/// result, Overflow marks an infinity, Carry marks a NaN.
+fn float_flags(mem: &mut Memory, fib: &Fiber, c: f64) -> Result<(), MachineError> {
+    fib.set_flag(mem, Flag::Zero, c == 0.0)?;
+    fib.set_flag(mem, Flag::Negative, c < 0.0)?;
+    fib.set_flag(mem, Flag::Overflow, c.is_infinite())?;
+    fib.set_flag(mem, Flag::Carry, c.is_nan())
+}
+
+fn pop_float(mem: &mut Memory, fib: &mut Fiber) -> Result<f64, MachineError> {
+    Ok(f64::from_bits(fib.pop_typed(mem, Tag::Float)?))
+}
+
+fn push_float(mem: &mut Memory, fib: &mut Fiber, c: f64) -> Result<(), MachineError> {
+    float_flags(mem, fib, c)?;
+    fib.push_tagged(mem, Tag::Float, c.to_bits())
+}
+
+/// in trap mode a NaN coming out of non-NaN operands is an invalid operation
+fn check_invalid(fib: &Fiber, operands: &[f64], c: f64) -> Result<(), MachineError> {
+    if fib.float_traps() && c.is_nan() && !operands.iter().any(|x| x.is_nan()) {
+        return Err(MachineError::FloatingPoint(Some("invalid operation".to_string())));
+    }
+    Ok(())
+}
+
+fn float_binary(mem: &mut Memory, fib: &mut Fiber, op: fn(f64, f64) -> f64) -> Result<(), MachineError> {
+    let a = pop_float(mem, fib)?;
+    let b = pop_float(mem, fib)?;
+    let c = op(a, b);
+    check_invalid(fib, &[a, b], c)?;
+    push_float(mem, fib, c)
+}
+
+fn float_unary(mem: &mut Memory, fib: &mut Fiber, op: fn(f64) -> f64) -> Result<(), MachineError> {
+    let a = pop_float(mem, fib)?;
+    let c = op(a);
+    check_invalid(fib, &[a], c)?;
+    push_float(mem, fib, c)
+}
+
+pub fn fadd(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    float_binary(mem, fib, |a, b| a + b)
+}
+
+pub fn fsub(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    float_binary(mem, fib, |a, b| a - b)
+}
+
+pub fn fmul(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    float_binary(mem, fib, |a, b| a * b)
+}
+
+pub fn fdiv(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    let a = pop_float(mem, fib)?;
+    let b = pop_float(mem, fib)?;
+    if fib.float_traps() && b == 0.0 {
+        return Err(MachineError::FloatingPoint(Some("division by zero".to_string())));
+    }
+    let c = a / b;
+    check_invalid(fib, &[a, b], c)?;
+    push_float(mem, fib, c)
+}
+
+pub fn fsqrt(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    float_unary(mem, fib, f64::sqrt)
+}
+
+pub fn fneg(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    float_unary(mem, fib, |a| -a)
+}
+
+pub fn fabs(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    float_unary(mem, fib, f64::abs)
+}
+
+/// signed integer to float
+pub fn itof(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    let a = fib.pop_typed(mem, Tag::Int)? as i64;
+    push_float(mem, fib, a as f64)
+}
+
+/// unsigned integer to float
+pub fn utof(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    let a = fib.pop_typed(mem, Tag::Int)?;
+    push_float(mem, fib, a as f64)
+}
+
+/// float to signed integer, truncating toward zero. Out of range values
+/// saturate and NaN becomes 0 unless traps are on.
+pub fn ftoi(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    let a = pop_float(mem, fib)?;
+    if fib.float_traps() && (a.is_nan() || a.trunc() < i64::MIN as f64 || a.trunc() >= i64::MAX as f64) {
+        return Err(MachineError::FloatingPoint(Some(format!("{} does not fit a signed integer", a))));
+    }
+    let c = a as i64;
+    fib.set_flag(mem, Flag::Zero, c == 0)?;
+    fib.set_flag(mem, Flag::Negative, c < 0)?;
+    fib.push(mem, c as u64)
+}
+
+/// float to unsigned integer, same rules as `ftoi`
+pub fn ftou(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    let a = pop_float(mem, fib)?;
+    if fib.float_traps() && (a.is_nan() || a.trunc() < 0.0 || a.trunc() >= u64::MAX as f64) {
+        return Err(MachineError::FloatingPoint(Some(format!("{} does not fit an unsigned integer", a))));
+    }
+    let c = a as u64;
+    fib.set_flag(mem, Flag::Zero, c == 0)?;
+    fib.set_flag(mem, Flag::Negative, false)?;
+    fib.push(mem, c)
+}
+
+/// Compares the top of the stack against the slot below it, like SUB does
+/// for integers, and consumes both. Ordered results set Zero for equal and
+/// Negative for less than, so the signed jumps work unchanged. Unordered
+/// results (either side NaN) set Negative and Carry: JNZ, JL and JLE are
+/// taken, JZ, JG and JGE are not. In trap mode unordered compares fault.
+pub fn fcmp(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    let a = pop_float(mem, fib)?;
+    let b = pop_float(mem, fib)?;
+    let unordered = a.is_nan() || b.is_nan();
+    if unordered && fib.float_traps() {
+        return Err(MachineError::FloatingPoint(Some("unordered compare".to_string())));
+    }
+    fib.set_flag(mem, Flag::Zero, a == b)?;
+    fib.set_flag(mem, Flag::Negative, unordered || a < b)?;
+    fib.set_flag(mem, Flag::Overflow, false)?;
+    fib.set_flag(mem, Flag::Carry, unordered)
+}
+
+/// registers a handler at `address` for faults raised before the matching ENDTRY
+pub fn try_handler(mem: &mut Memory, fib: &mut Fiber, address: u64) -> Result<(), MachineError> {
+    let sp = fib.get_register(mem, Reg::SP)?;
+    fib.handlers.push(Handler { address, sp });
+    Ok(())
+}
+
+pub fn endtry(_mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    fib.handlers.pop().ok_or(MachineError::InvalidHandler)?;
+    Ok(())
+}
+
+/// Writes the top of the stack to an output port: strings and byte blocks
+/// as a whole, anything else as its low byte. The machine hands the bytes
+/// to the device once the slice ends.
+pub fn out(mem: &mut Memory, fib: &mut Fiber, port: u64) -> Result<(), MachineError> {
+    if port != STDOUT && port != STDERR {
+        return Err(MachineError::InvalidDevice(Some(format!("port {} can't be written", port))));
+    }
+    if !fib.capabilities.devices.allows(port) {
+        return Err(MachineError::CapabilityDenied(Capability::Device(port)));
+    }
+    let (tag, bits) = fib.pop_tagged(mem)?;
+    let bytes = match tag {
+        Tag::Str | Tag::Bytes if fib.is_typed() => Fiber::load_bytes(mem, bits)?,
+        _ => vec![bits as u8],
+    };
+    match fib.output.last_mut() {
+        Some((last, buf)) if *last == port => buf.extend(bytes),
+        _ => fib.output.push((port, bytes)),
+    }
+    Ok(())
+}
+
+/// Pops the receiver, then the message. The message is copied out right
+/// away and handed to the machine once the slice ends.
+pub fn send(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    let to = ActorId::from_bits(fib.pop_typed(mem, Tag::Actor)?);
+    let value = fib.pop_value(mem)?;
+    if !fib.may_address(to) {
+        return Err(MachineError::CapabilityDenied(Capability::Peer(to)));
+    }
+    fib.outbox.push((to, value, None));
+    Ok(())
+}
+
+/// Pops the callee, then the request, like SEND. Returns the id the reply
+/// has to come back with.
+pub fn callr(mem: &mut Memory, fib: &mut Fiber) -> Result<u64, MachineError> {
+    let to = ActorId::from_bits(fib.pop_typed(mem, Tag::Actor)?);
+    let value = fib.pop_value(mem)?;
+    if !fib.may_address(to) {
+        return Err(MachineError::CapabilityDenied(Capability::Peer(to)));
+    }
+    fib.last_request += 1;
+    fib.outbox.push((to, value, Some(Rpc::Request(fib.last_request))));
+    Ok(fib.last_request)
+}
+
+/// Pops the reply to the newest request taken with RECV. Callers may
+/// address their callees without the capability for it.
+pub fn reply(mem: &mut Memory, fib: &mut Fiber) -> Result<(), MachineError> {
+    if fib.requests.is_empty() {
+        return Err(MachineError::InvalidReply);
+    }
+    let value = fib.pop_value(mem)?;
+    if let Some((to, request)) = fib.requests.pop() {
+        fib.outbox.push((to, value, Some(Rpc::Reply(request))));
+    }
+    Ok(())
+}
+
+/// Pushes the oldest message, then its sender on top, and clears the zero
+/// flag. A delivery failure pushes its code and the actor that wasn't
+/// reached instead, with the zero flag set. Returns false and leaves the
+/// stack alone when the mailbox is empty. A request made with CALLR is
+/// kept for REPLY.
+pub fn recv(mem: &mut Memory, fib: &mut Fiber) -> Result<bool, MachineError> {
+    let Some(message) = fib.mailbox.pop_front() else {
+        return Ok(false);
+    };
+    match message.failure {
+        Some(failure) => fib.push_value(mem, Value::Int(failure as i64))?,
+        None => fib.push_value(mem, message.value)?,
+    }
+    fib.push_value(mem, Value::Actor(message.from))?;
+    if let Some(Rpc::Request(request)) = message.rpc {
+        fib.requests.push((message.from, request));
+    }
+    fib.set_flag(mem, Flag::Zero, message.failure.is_some())?;
+    Ok(true)
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+use crate::{execptions::MachineError, fiber::{fiber::Reg, section::Section, value::Tag}, memory::memory::Memory, opcode::opcodes::{OperandKind, Opcodes}};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operands {
+    None,
+    Reg(Reg),
+    Imm(u64),
+    RegImm(Reg, u64),
+    TagImm(Tag, u64),
+}
+
+/// A decoded instruction together with the text section address it was read from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    pub address: u64,
+    pub opcode: Opcodes,
+    pub operands: Operands,
+}
+
+impl Instruction {
+    /// size in bytes, opcode included
+    pub fn size(&self) -> u64 {
+        2 + self.opcode.operand_kind().size()
+    }
+
+    /// address of the instruction that follows this one
+    pub fn next(&self) -> u64 {
+        self.address + self.size()
+    }
+
+    pub fn reg(&self) -> Result<Reg, MachineError> {
+        match self.operands {
+            Operands::Reg(reg) | Operands::RegImm(reg, _) => Ok(reg),
+            _ => Err(self.missing("register")),
+        }
+    }
+
+    pub fn imm(&self) -> Result<u64, MachineError> {
+        match self.operands {
+            Operands::Imm(val) | Operands::RegImm(_, val) | Operands::TagImm(_, val) => Ok(val),
+            _ => Err(self.missing("immediate")),
+        }
+    }
+
+    pub fn tag(&self) -> Result<Tag, MachineError> {
+        match self.operands {
+            Operands::TagImm(tag, _) => Ok(tag),
+            _ => Err(self.missing("tag")),
+        }
+    }
+
+    fn missing(&self, operand: &str) -> MachineError {
+        MachineError::InvalidInstruction(Some(format!("{} at #{:x} has no {} operand", self.opcode, self.address, operand)))
+    }
+}
+
+/// Decodes the instruction at `address` of a text section.
+pub fn decode(mem: &Memory, section: &Section, address: usize) -> Result<Instruction, MachineError> {
+    let raw = section.read_u16(mem, address)?;
+    let opcode = Opcodes::try_from(raw)
+        .map_err(|_| MachineError::InvalidOpcode(Some(format!("opcode: {} at #{:x}", raw, address))))?;
+    let operands = match opcode.operand_kind() {
+        OperandKind::None => Operands::None,
+        OperandKind::Reg => Operands::Reg(Reg::from_u8(section.read_u8(mem, address + 2)?)?),
+        OperandKind::Imm => Operands::Imm(section.read_u64(mem, address + 2)?),
+        OperandKind::RegImm => Operands::RegImm(
+            Reg::from_u8(section.read_u8(mem, address + 2)?)?,
+            section.read_u64(mem, address + 3)?,
+        ),
+        OperandKind::TagImm => Operands::TagImm(
+            Tag::from_u8(section.read_u8(mem, address + 2)?)?,
+            section.read_u64(mem, address + 3)?,
+        ),
+    };
+    Ok(Instruction { address: address as u64, opcode, operands })
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.operands {
+            Operands::None => write!(f, "{}", self.opcode),
+            Operands::Reg(reg) => write!(f, "{} {}", self.opcode, reg),
+            Operands::Imm(val) if self.opcode.is_jump() || self.opcode == Opcodes::TRY => write!(f, "{} #{:x}", self.opcode, val),
+            Operands::Imm(val) => write!(f, "{} {}", self.opcode, val),
+            Operands::RegImm(reg, val) => write!(f, "{} {}, {}", self.opcode, reg, val),
+            Operands::TagImm(Tag::Float, val) => write!(f, "{} Float, {:?}", self.opcode, f64::from_bits(val)),
+            Operands::TagImm(tag, val) => write!(f, "{} {}, {}", self.opcode, tag, val),
+        }
+    }
+}
@@ -1,6 +1,10 @@
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt};
 
-#[derive(Debug)]
+/// Version of the instruction set, bumped whenever opcodes or their
+/// encoding change. Nodes only talk to nodes running the same one.
+pub const ISA_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Opcodes {
     PUSH = 0x0001,
     POP = 0x0002,
@@ -29,6 +33,30 @@ pub enum Opcodes {
     ROR = 0x0019,
     HLT = 0x001a,
     YLD = 0x001b,
+    PUSHT = 0x001c,
+    FADD = 0x001d,
+    FSUB = 0x001e,
+    FMUL = 0x001f,
+    FDIV = 0x0020,
+    FSQRT = 0x0021,
+    FNEG = 0x0022,
+    FABS = 0x0023,
+    ITOF = 0x0024,
+    UTOF = 0x0025,
+    FTOI = 0x0026,
+    FTOU = 0x0027,
+    FCMP = 0x0028,
+    TRY = 0x0029,
+    ENDTRY = 0x002a,
+    THROW = 0x002b,
+    SYSCALL = 0x002c,
+    OUT = 0x002d,
+    IN = 0x002e,
+    SPAWN = 0x002f,
+    SEND = 0x0030,
+    RECV = 0x0031,
+    CALLR = 0x0032,
+    REPLY = 0x0033,
 }
 
 impl From<Opcodes> for u16 {
@@ -69,7 +97,106 @@ impl TryFrom<u16> for Opcodes {
             0x0019 => Ok(Opcodes::ROR),
             0x001a => Ok(Opcodes::HLT),
             0x001b => Ok(Opcodes::YLD),
+            0x001c => Ok(Opcodes::PUSHT),
+            0x001d => Ok(Opcodes::FADD),
+            0x001e => Ok(Opcodes::FSUB),
+            0x001f => Ok(Opcodes::FMUL),
+            0x0020 => Ok(Opcodes::FDIV),
+            0x0021 => Ok(Opcodes::FSQRT),
+            0x0022 => Ok(Opcodes::FNEG),
+            0x0023 => Ok(Opcodes::FABS),
+            0x0024 => Ok(Opcodes::ITOF),
+            0x0025 => Ok(Opcodes::UTOF),
+            0x0026 => Ok(Opcodes::FTOI),
+            0x0027 => Ok(Opcodes::FTOU),
+            0x0028 => Ok(Opcodes::FCMP),
+            0x0029 => Ok(Opcodes::TRY),
+            0x002a => Ok(Opcodes::ENDTRY),
+            0x002b => Ok(Opcodes::THROW),
+            0x002c => Ok(Opcodes::SYSCALL),
+            0x002d => Ok(Opcodes::OUT),
+            0x002e => Ok(Opcodes::IN),
+            0x002f => Ok(Opcodes::SPAWN),
+            0x0030 => Ok(Opcodes::SEND),
+            0x0031 => Ok(Opcodes::RECV),
+            0x0032 => Ok(Opcodes::CALLR),
+            0x0033 => Ok(Opcodes::REPLY),
             _ => Err(()),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Operand layout that follows the 2 byte opcode in the text section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    None,
+    /// 1 byte register
+    Reg,
+    /// 8 byte immediate or address
+    Imm,
+    /// 1 byte register then an 8 byte immediate
+    RegImm,
+    /// 1 byte value tag then an 8 byte immediate
+    TagImm,
+}
+
+impl OperandKind {
+    pub fn size(self) -> u64 {
+        match self {
+            Self::None => 0,
+            Self::Reg => 1,
+            Self::Imm => 8,
+            Self::RegImm | Self::TagImm => 9,
+        }
+    }
+}
+
+impl Opcodes {
+    pub fn operand_kind(self) -> OperandKind {
+        match self {
+            Opcodes::PUSH => OperandKind::Imm,
+            Opcodes::PUSHT => OperandKind::TagImm,
+            Opcodes::POP | Opcodes::INC | Opcodes::DEC => OperandKind::Reg,
+            Opcodes::MOV => OperandKind::RegImm,
+            Opcodes::JMP | Opcodes::JZ | Opcodes::JNZ | Opcodes::JG |
+            Opcodes::JGE | Opcodes::JL | Opcodes::JLE => OperandKind::Imm,
+            Opcodes::TRY | Opcodes::THROW | Opcodes::SYSCALL => OperandKind::Imm,
+            Opcodes::OUT | Opcodes::IN | Opcodes::SPAWN | Opcodes::CALLR => OperandKind::Imm,
+            _ => OperandKind::None,
+        }
+    }
+
+    /// slots popped and pushed, ignoring the fault paths
+    pub fn stack_effect(self) -> (usize, usize) {
+        match self {
+            Opcodes::PUSH | Opcodes::PUSHT | Opcodes::IN | Opcodes::SPAWN => (0, 1),
+            Opcodes::RECV => (0, 2),
+            Opcodes::SEND => (2, 0),
+            Opcodes::CALLR => (2, 1),
+            Opcodes::POP | Opcodes::DROP | Opcodes::OUT | Opcodes::REPLY => (1, 0),
+            Opcodes::DUP => (1, 2),
+            Opcodes::SWP => (2, 2),
+            Opcodes::ADD | Opcodes::SUB | Opcodes::AND | Opcodes::OR | Opcodes::XOR |
+            Opcodes::SHR | Opcodes::SHL | Opcodes::ROL | Opcodes::ROR |
+            Opcodes::FADD | Opcodes::FSUB | Opcodes::FMUL | Opcodes::FDIV => (2, 1),
+            Opcodes::NOT | Opcodes::FSQRT | Opcodes::FNEG | Opcodes::FABS |
+            Opcodes::ITOF | Opcodes::UTOF | Opcodes::FTOI | Opcodes::FTOU => (1, 1),
+            Opcodes::FCMP => (2, 0),
+            _ => (0, 0),
+        }
+    }
+
+    pub fn is_branch(self) -> bool {
+        matches!(self, Opcodes::JZ | Opcodes::JNZ | Opcodes::JG | Opcodes::JGE | Opcodes::JL | Opcodes::JLE)
+    }
+
+    pub fn is_jump(self) -> bool {
+        self == Opcodes::JMP || self.is_branch()
+    }
+}
+
+impl fmt::Display for Opcodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeSet;
+
+use crate::{execptions::MachineError, fiber::{fiber::{Fiber, Flag, Reg}, value::Tag}, memory::memory::Memory, opcode::{commands, instruction::{Instruction, Operands}, opcodes::Opcodes}};
+
+/// A run of instructions executed with a single dispatch. `next` is the
+/// address after the last instruction of the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fused {
+    /// PUSH a, PUSH b, ADD
+    PushPushAdd { a: u64, b: u64, next: u64 },
+    /// PUSH a, PUSH b, SUB
+    PushPushSub { a: u64, b: u64, next: u64 },
+    /// DUP, JZ target
+    DupJz { target: u64, next: u64 },
+    /// POP reg, PUSH imm
+    PopPush { reg: Reg, imm: u64, next: u64 },
+    /// SWP, SWP only checks the depth
+    SwpSwp { next: u64 },
+    /// PUSH imm, DROP only sets Zero
+    PushDrop { imm: u64, next: u64 },
+}
+
+impl Fused {
+    pub fn next(&self) -> u64 {
+        match *self {
+            Self::PushPushAdd { next, .. } | Self::PushPushSub { next, .. } | Self::DupJz { next, .. } |
+            Self::PopPush { next, .. } | Self::SwpSwp { next } | Self::PushDrop { next, .. } => next,
+        }
+    }
+}
+
+/// Finds the runs in `instrs` (sorted by address) that can be fused. A run
+/// is only fused when control can't arrive in the middle of it, so no
+/// instruction after the first may be in `targets`.
+pub fn fuse(instrs: &[Instruction], targets: &BTreeSet<u64>) -> Vec<(u64, Fused)> {
+    let mut fused = Vec::new();
+    let mut idx = 0;
+    while idx < instrs.len() {
+        let run = straight_run(&instrs[idx..], targets);
+        let found = match run {
+            [
+                Instruction { opcode: Opcodes::PUSH, operands: Operands::Imm(a), .. },
+                Instruction { opcode: Opcodes::PUSH, operands: Operands::Imm(b), .. },
+                last @ Instruction { opcode: Opcodes::ADD | Opcodes::SUB, .. },
+                ..
+            ] => Some((3, match last.opcode {
+                Opcodes::ADD => Fused::PushPushAdd { a: *a, b: *b, next: last.next() },
+                _ => Fused::PushPushSub { a: *a, b: *b, next: last.next() },
+            })),
+            [
+                Instruction { opcode: Opcodes::DUP, .. },
+                last @ Instruction { opcode: Opcodes::JZ, operands: Operands::Imm(target), .. },
+                ..
+            ] => Some((2, Fused::DupJz { target: *target, next: last.next() })),
+            [
+                Instruction { opcode: Opcodes::POP, operands: Operands::Reg(reg), .. },
+                last @ Instruction { opcode: Opcodes::PUSH, operands: Operands::Imm(imm), .. },
+                ..
+            ] if *reg != Reg::PC && *reg != Reg::SP => Some((2, Fused::PopPush { reg: *reg, imm: *imm, next: last.next() })),
+            [
+                Instruction { opcode: Opcodes::SWP, .. },
+                last @ Instruction { opcode: Opcodes::SWP, .. },
+                ..
+            ] => Some((2, Fused::SwpSwp { next: last.next() })),
+            [
+                Instruction { opcode: Opcodes::PUSH, operands: Operands::Imm(imm), .. },
+                last @ Instruction { opcode: Opcodes::DROP, .. },
+                ..
+            ] => Some((2, Fused::PushDrop { imm: *imm, next: last.next() })),
+            _ => None,
+        };
+        match found {
+            Some((len, op)) => {
+                fused.push((instrs[idx].address, op));
+                idx += len;
+            },
+            None => idx += 1,
+        }
+    }
+    fused
+}
+
+/// the longest prefix (up to 3) that runs straight through without being jumped into
+fn straight_run<'a>(instrs: &'a [Instruction], targets: &BTreeSet<u64>) -> &'a [Instruction] {
+    let mut len = 1.min(instrs.len());
+    while len < instrs.len().min(3)
+        && instrs[len].address == instrs[len - 1].next()
+        && !targets.contains(&instrs[len].address) {
+        len += 1;
+    }
+    &instrs[..len]
+}
+
+impl Fiber {
+    /// Runs a fused run in one go. Returns false, without touching anything,
+    /// when a fault or a stack resize could happen on the way; the caller
+    /// then executes the run one instruction at a time.
+    pub(crate) fn run_fused(&mut self, mem: &mut Memory, fused: &Fused) -> Result<bool, MachineError> {
+        let sp = self.registers.get(Reg::SP) as usize;
+        // the block may be larger than the fiber is allowed to use
+        let room = self.stack_limit.min(self.stack.size).saturating_sub(sp) / 8;
+        match *fused {
+            Fused::PushPushAdd { a, b, .. } | Fused::PushPushSub { a, b, .. } => {
+                if room < 2 {
+                    return Ok(false);
+                }
+                let c = match fused {
+                    Fused::PushPushAdd { .. } => commands::add_values(mem, self, b, a)?,
+                    _ => commands::sub_values(mem, self, b, a)?,
+                };
+                self.push(mem, c)?;
+            },
+            Fused::DupJz { target, next } => {
+                if sp < 8 || room < 1 {
+                    return Ok(false);
+                }
+                let (tag, val) = self.peek_tagged(mem)?;
+                self.push_tagged(mem, tag, val)?;
+                let zero = self.registers.flag(Flag::Zero);
+                self.registers.set(Reg::PC, if zero { target } else { next });
+                return Ok(true);
+            },
+            Fused::PopPush { reg, imm, .. } => {
+                if sp < 8 {
+                    return Ok(false);
+                }
+                let val = mem.read_u64(self.stack.address + sp - 8)?;
+                self.registers.set_flag(Flag::Zero, val == 0);
+                self.registers.set(reg, val);
+                mem.write_u64(self.stack.address + sp - 8, imm)?;
+                if let Some(tags) = &mut self.tags {
+                    *tags.last_mut().unwrap() = Tag::Int;
+                }
+            },
+            Fused::SwpSwp { .. } => {
+                if sp < 16 {
+                    return Ok(false);
+                }
+            },
+            Fused::PushDrop { imm, .. } => {
+                if room < 1 {
+                    return Ok(false);
+                }
+                self.registers.set_flag(Flag::Zero, imm == 0);
+            },
+        }
+        self.registers.set(Reg::PC, fused.next());
+        Ok(true)
+    }
+}
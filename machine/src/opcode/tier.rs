@@ -0,0 +1,193 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{execptions::MachineError, fiber::{fiber::{Fiber, Flow, Reg}, section::Section}, memory::memory::Memory, opcode::{commands, instruction::{decode, Instruction, Operands}, opcodes::Opcodes}};
+
+/// longest block compiled in one piece, in instructions
+const MAX_BLOCK_LEN: usize = 256;
+
+type Step = Box<dyn Fn(&mut Fiber, &mut Memory) -> Result<Flow, MachineError> + Send + Sync>;
+
+/// One instruction of a compiled block, operands baked into the closure.
+struct Compiled {
+    instr: Instruction,
+    run: Step,
+}
+
+/// A straight run of instructions compiled into closures. It ends after the
+/// first instruction that can move PC somewhere else or stop the fiber, and
+/// before the first one that doesn't decode. Only the last instruction may
+/// return anything but `Flow::Continue`.
+pub(crate) struct Block {
+    steps: Vec<Compiled>,
+}
+
+/// Second execution tier. Counts how often execution arrives at the start of
+/// a block and compiles the block once the count reaches the threshold.
+/// Writes to the text section throw everything away.
+pub struct Tier {
+    threshold: u32,
+    generation: u64,
+    counts: HashMap<u64, u32>,
+    blocks: HashMap<u64, Arc<Block>>,
+}
+
+impl std::fmt::Debug for Tier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tier")
+            .field("threshold", &self.threshold)
+            .field("blocks", &self.blocks.len())
+            .finish()
+    }
+}
+
+impl Tier {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            generation: 0,
+            counts: HashMap::new(),
+            blocks: HashMap::new(),
+        }
+    }
+
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+    }
+
+    /// number of compiled blocks
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn is_compiled(&self, address: u64) -> bool {
+        self.blocks.contains_key(&address)
+    }
+
+    /// Called whenever execution arrives at the start of a block, returns
+    /// the compiled block once it's hot.
+    pub(crate) fn enter(&mut self, mem: &Memory, section: &Section, pc: u64) -> Option<Arc<Block>> {
+        if self.generation != section.generation() {
+            self.generation = section.generation();
+            self.counts.clear();
+            self.blocks.clear();
+        }
+        if let Some(block) = self.blocks.get(&pc) {
+            return Some(block.clone());
+        }
+        let count = self.counts.entry(pc).or_insert(0);
+        *count += 1;
+        if *count <= self.threshold {
+            return None;
+        }
+        self.counts.remove(&pc);
+        let block = Arc::new(compile(mem, section, pc)?);
+        self.blocks.insert(pc, block.clone());
+        Some(block)
+    }
+}
+
+fn compile(mem: &Memory, section: &Section, start: u64) -> Option<Block> {
+    let mut steps = Vec::new();
+    let mut address = start;
+    while steps.len() < MAX_BLOCK_LEN {
+        let Ok(instr) = decode(mem, section, address as usize) else { break };
+        if instr.next() as usize > section.len() {
+            break;
+        }
+        steps.push(Compiled { instr, run: closure(&instr) });
+        if ends_block(&instr) {
+            break;
+        }
+        address = instr.next();
+    }
+    (!steps.is_empty()).then_some(Block { steps })
+}
+
+fn ends_block(instr: &Instruction) -> bool {
+    match instr.opcode {
+        Opcodes::HLT | Opcodes::YLD | Opcodes::THROW | Opcodes::SYSCALL | Opcodes::IN |
+        Opcodes::SPAWN | Opcodes::RECV | Opcodes::CALLR => true,
+        op if op.is_jump() => true,
+        Opcodes::POP | Opcodes::MOV | Opcodes::INC | Opcodes::DEC => matches!(instr.reg(), Ok(Reg::PC)),
+        _ => false,
+    }
+}
+
+macro_rules! op {
+    ($command:path) => {
+        Box::new(|fib, mem| $command(mem, fib).map(|_| Flow::Continue))
+    };
+    ($command:path, $($arg:expr),+) => {
+        Box::new(move |fib, mem| $command(mem, fib, $($arg),+).map(|_| Flow::Continue))
+    };
+}
+
+/// the instruction as a closure, everything `Fiber::step` would look up is resolved here
+fn closure(instr: &Instruction) -> Step {
+    match (instr.opcode, instr.operands) {
+        (Opcodes::PUSH, Operands::Imm(imm)) => op!(commands::push, imm),
+        (Opcodes::PUSHT, Operands::TagImm(tag, imm)) => op!(commands::pusht, tag, imm),
+        (Opcodes::POP, Operands::Reg(reg)) => op!(commands::pop, reg),
+        (Opcodes::MOV, Operands::RegImm(reg, imm)) => op!(commands::mov, reg, imm),
+        (Opcodes::INC, Operands::Reg(reg)) => op!(commands::inc, reg),
+        (Opcodes::DEC, Operands::Reg(reg)) => op!(commands::dec, reg),
+        (Opcodes::JMP, Operands::Imm(imm)) => op!(commands::jmp, imm as usize),
+        (Opcodes::JZ, Operands::Imm(imm)) => op!(commands::jz, imm as usize),
+        (Opcodes::JNZ, Operands::Imm(imm)) => op!(commands::jnz, imm as usize),
+        (Opcodes::JG, Operands::Imm(imm)) => op!(commands::jg, imm as usize),
+        (Opcodes::JGE, Operands::Imm(imm)) => op!(commands::jge, imm as usize),
+        (Opcodes::JL, Operands::Imm(imm)) => op!(commands::jl, imm as usize),
+        (Opcodes::JLE, Operands::Imm(imm)) => op!(commands::jle, imm as usize),
+        (Opcodes::THROW, Operands::Imm(imm)) => Box::new(move |_, _| Err(MachineError::Thrown(imm))),
+        (Opcodes::ADD, _) => op!(commands::add),
+        (Opcodes::SUB, _) => op!(commands::sub),
+        (Opcodes::DROP, _) => op!(commands::drop),
+        (Opcodes::DUP, _) => op!(commands::dup),
+        (Opcodes::SWP, _) => op!(commands::swap),
+        (Opcodes::AND, _) => op!(commands::and),
+        (Opcodes::OR, _) => op!(commands::or),
+        (Opcodes::NOT, _) => op!(commands::not),
+        (Opcodes::XOR, _) => op!(commands::xor),
+        (Opcodes::SHR, _) => op!(commands::shr),
+        (Opcodes::SHL, _) => op!(commands::shl),
+        (Opcodes::ROL, _) => op!(commands::rol),
+        (Opcodes::ROR, _) => op!(commands::ror),
+        (Opcodes::HLT, _) => Box::new(|_, _| Ok(Flow::Halt)),
+        (Opcodes::YLD, _) => Box::new(|_, _| Ok(Flow::Yield)),
+        // the rest is rare enough to go through the interpreter's dispatch,
+        // which also reports operands that don't fit the opcode
+        _ => {
+            let instr = *instr;
+            Box::new(move |fib, mem| fib.step(mem, &instr))
+        },
+    }
+}
+
+impl Block {
+    /// Runs the block. Nothing inside a block reads PC, so it is only kept
+    /// up to date for the instruction that ends the block and on faults,
+    /// where it is left exactly as the interpreter would leave it. A fault
+    /// comes back with the instruction that raised it.
+    pub(crate) fn run(&self, fib: &mut Fiber, mem: &mut Memory) -> Result<Flow, (Instruction, MachineError)> {
+        let Some((last, body)) = self.steps.split_last() else {
+            return Ok(Flow::Jump);
+        };
+        for step in body {
+            if let Err(err) = (step.run)(fib, mem) {
+                fib.registers.set(Reg::PC, step.instr.next());
+                return Err((step.instr, err));
+            }
+        }
+        fib.registers.set(Reg::PC, last.instr.next());
+        match (last.run)(fib, mem) {
+            // wherever the block left PC is a block start of its own
+            Ok(Flow::Continue) => Ok(Flow::Jump),
+            Ok(flow) => Ok(flow),
+            Err(err) => Err((last.instr, err)),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{execptions::MachineError, fiber::{fiber::Reg, section::Section}, memory::memory::Memory, opcode::{instruction::{decode, Instruction, Operands}, opcodes::Opcodes}};
+
+/// deepest stack the verifier keeps track of, in slots, anything deeper is unknown
+const MAX_TRACKED_DEPTH: usize = 1024 * 1024 / 8;
+/// nesting of TRY blocks past which the verifier stops counting
+const MAX_TRACKED_HANDLERS: usize = 64;
+
+/// What the verifier learned about a text section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// stack depth in slots when each reachable instruction starts, `None`
+    /// once it depends on values only known at runtime
+    pub depths: BTreeMap<u64, Option<usize>>,
+    /// addresses control can arrive at other than by falling through
+    pub targets: BTreeSet<u64>,
+    /// section length the result is valid for
+    pub len: usize,
+}
+
+impl Verification {
+    pub fn max_depth(&self) -> Option<usize> {
+        self.depths.values().try_fold(0, |max, depth| depth.map(|depth| max.max(depth)))
+    }
+}
+
+/// abstract state on entry of an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    /// MAX_TRACKED_DEPTH stands for unknown
+    depth: usize,
+    /// an upper bound on the TRY blocks entered
+    handlers: usize,
+}
+
+impl State {
+    /// Joins two states reaching the same instruction. Depths are only
+    /// used to prove underflows, so a depth that differs between paths
+    /// is widened to unknown instead of iterating up to the limit.
+    fn join(self, other: State) -> State {
+        State {
+            depth: if self.depth == other.depth { self.depth } else { MAX_TRACKED_DEPTH },
+            handlers: self.handlers.max(other.handlers),
+        }
+    }
+}
+
+fn reject(address: u64, instr: Option<&Instruction>, reason: impl std::fmt::Display) -> MachineError {
+    match instr {
+        Some(instr) => MachineError::InvalidBytecode(Some(format!("#{:x} {}: {}", address, instr, reason))),
+        None => MachineError::InvalidBytecode(Some(format!("#{:x}: {}", address, reason))),
+    }
+}
+
+/// Checks every instruction reachable from the start of `section`: each one
+/// decodes and ends inside the section, jumps and handlers land on
+/// instruction boundaries, PC is only written with constants and no
+/// instruction is certain to underflow the stack outside of a TRY block.
+pub fn verify(mem: &Memory, section: &Section) -> Result<Verification, MachineError> {
+    let len = section.len() as u64;
+    let mut states: BTreeMap<u64, State> = BTreeMap::new();
+    let mut instrs: BTreeMap<u64, Instruction> = BTreeMap::new();
+    let mut targets = BTreeSet::new();
+    let mut work = vec![(0u64, State { depth: 0, handlers: 0 })];
+
+    while let Some((address, state)) = work.pop() {
+        let state = match states.get(&address) {
+            Some(seen) if seen.join(state) == *seen => continue,
+            Some(seen) => seen.join(state),
+            None => state,
+        };
+        states.insert(address, state);
+
+        if address >= len {
+            return Err(reject(address, None, "outside of the section"));
+        }
+        let instr = decode(mem, section, address as usize).map_err(|err| reject(address, None, err))?;
+        if instr.next() > len {
+            return Err(reject(address, Some(&instr), "runs past the end of the section"));
+        }
+        instrs.insert(address, instr);
+
+        let (pops, pushes) = instr.opcode.stack_effect();
+        if state.depth < pops {
+            // faults here, reported below once every path has been joined
+            continue;
+        }
+        let mut next = State {
+            depth: if state.depth == MAX_TRACKED_DEPTH { state.depth } else { (state.depth - pops + pushes).min(MAX_TRACKED_DEPTH) },
+            ..state
+        };
+
+        match (instr.opcode, instr.operands) {
+            (Opcodes::POP | Opcodes::INC | Opcodes::DEC, Operands::Reg(Reg::PC)) => {
+                return Err(reject(address, Some(&instr), "PC can only be set to a constant"));
+            },
+            (Opcodes::MOV, Operands::RegImm(Reg::PC, target)) => {
+                targets.insert(target);
+                work.push((target, next));
+                continue;
+            },
+            (Opcodes::POP | Opcodes::INC | Opcodes::DEC, Operands::Reg(Reg::SP)) |
+            (Opcodes::MOV, Operands::RegImm(Reg::SP, _)) => next.depth = MAX_TRACKED_DEPTH,
+            // how many slots a native takes is only known to the host
+            (Opcodes::SYSCALL, _) => next.depth = MAX_TRACKED_DEPTH,
+            _ => {},
+        }
+
+        match instr.opcode {
+            Opcodes::HLT | Opcodes::THROW => continue,
+            Opcodes::JMP => {
+                targets.insert(instr.imm()?);
+                work.push((instr.imm()?, next));
+                continue;
+            },
+            op if op.is_branch() => {
+                targets.insert(instr.imm()?);
+                work.push((instr.imm()?, next));
+            },
+            Opcodes::TRY => {
+                // the handler starts with the stack cut back and the fault code on top
+                let handler = State { depth: (state.depth + 1).min(MAX_TRACKED_DEPTH), handlers: state.handlers };
+                targets.insert(instr.imm()?);
+                work.push((instr.imm()?, handler));
+                next.handlers = (state.handlers + 1).min(MAX_TRACKED_HANDLERS);
+            },
+            // a child starts there with an empty stack, on a copy of this text
+            Opcodes::SPAWN => {
+                targets.insert(instr.imm()?);
+                work.push((instr.imm()?, State { depth: 0, handlers: 0 }));
+            },
+            // past the limit the count is only known to be large
+            Opcodes::ENDTRY if state.handlers < MAX_TRACKED_HANDLERS => {
+                next.handlers = state.handlers.saturating_sub(1);
+            },
+            _ => {},
+        }
+        work.push((instr.next(), next));
+    }
+
+    let mut end = 0;
+    for (address, instr) in &instrs {
+        // a target inside another instruction's operands would decode differently
+        if *address < end {
+            return Err(reject(*address, None, "lands inside another instruction"));
+        }
+        end = instr.next();
+        // with every path joined the depth is the most this instruction can
+        // see and the handler count the most that can catch its fault
+        let state = states[address];
+        if state.handlers == 0 {
+            if state.depth < instr.opcode.stack_effect().0 {
+                return Err(reject(*address, Some(instr), MachineError::StackUnderflow));
+            }
+            if instr.opcode == Opcodes::ENDTRY {
+                return Err(reject(*address, Some(instr), MachineError::InvalidHandler));
+            }
+        }
+    }
+
+    Ok(Verification {
+        depths: states.into_iter()
+            .filter(|(address, _)| instrs.contains_key(address))
+            .map(|(address, state)| (address, (state.depth < MAX_TRACKED_DEPTH).then_some(state.depth)))
+            .collect(),
+        targets,
+        len: len as usize,
+    })
+}
@@ -0,0 +1,6 @@
+pub mod event;
+pub mod tracer;
+pub mod json;
+pub mod ring;
+pub mod profile;
+pub mod coverage;
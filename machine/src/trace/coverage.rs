@@ -0,0 +1,195 @@
+use std::{collections::{BTreeMap, HashMap}, fmt::Write};
+
+use crate::{execptions::MachineError, fiber::{fiber::Fiber, section::Section}, memory::memory::Memory, opcode::instruction::{decode, Instruction}, trace::{event::{Event, EventKind, TraceEvent}, tracer::Tracer}};
+
+/// Which way a conditional jump went, and how often.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl Branch {
+    pub fn is_covered(&self) -> bool {
+        self.taken > 0 && self.not_taken > 0
+    }
+}
+
+/// Maps text section addresses to lines of the assembler source they came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    pub file: String,
+    lines: BTreeMap<u64, u32>,
+}
+
+impl SourceMap {
+    pub fn new(file: &str) -> Self {
+        Self { file: file.to_string(), lines: BTreeMap::new() }
+    }
+
+    pub fn insert(&mut self, address: u64, line: u32) {
+        self.lines.insert(address, line);
+    }
+
+    pub fn line(&self, address: u64) -> Option<u32> {
+        self.lines.get(&address).copied()
+    }
+}
+
+/// Executed addresses and branch directions, by fiber since every fiber's
+/// text starts at 0. Installed as a tracer it sees every instruction, results
+/// of several runs can be merged, and fibers known to run the same program
+/// folded into one with `merge_fiber`.
+///
+/// The direction of a branch is only known once the next instruction of the
+/// same fiber runs, a branch into a fault is not counted. A branch that
+/// lands on the instruction after it counts as not taken.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    /// by fiber and text address
+    hits: BTreeMap<(u64, u64), u64>,
+    branches: BTreeMap<(u64, u64), Branch>,
+    pending: HashMap<u64, Instruction>,
+}
+
+impl Coverage {
+    pub fn hits(&self, fiber: u64, address: u64) -> u64 {
+        self.hits.get(&(fiber, address)).copied().unwrap_or(0)
+    }
+
+    pub fn branch(&self, fiber: u64, address: u64) -> Option<Branch> {
+        self.branches.get(&(fiber, address)).copied()
+    }
+
+    /// fibers with at least one executed instruction
+    pub fn fibers(&self) -> impl Iterator<Item = u64> + '_ {
+        let mut fibers: Vec<u64> = self.hits.keys().map(|(fiber, _)| *fiber).collect();
+        fibers.dedup();
+        fibers.into_iter()
+    }
+
+    /// executed addresses of a fiber with their counts
+    pub fn addresses(&self, fiber: u64) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.hits.range((fiber, 0)..=(fiber, u64::MAX)).map(|((_, address), count)| (*address, *count))
+    }
+
+    pub fn merge(&mut self, other: &Coverage) {
+        for fiber in other.fibers() {
+            self.merge_fiber(other, fiber, fiber);
+        }
+    }
+
+    /// Adds what `other` recorded for fiber `from` to fiber `into`, for
+    /// fibers running the same program.
+    pub fn merge_fiber(&mut self, other: &Coverage, from: u64, into: u64) {
+        for (address, count) in other.addresses(from) {
+            *self.hits.entry((into, address)).or_insert(0) += count;
+        }
+        for ((_, address), branch) in other.branches.range((from, 0)..=(from, u64::MAX)) {
+            let own = self.branches.entry((into, *address)).or_default();
+            own.taken += branch.taken;
+            own.not_taken += branch.not_taken;
+        }
+    }
+
+    /// The fiber's whole text section with execution counts, `#####` marks
+    /// instructions that never ran.
+    pub fn listing(&self, mem: &Memory, fiber: &Fiber) -> Result<String, MachineError> {
+        let mut out = String::new();
+        for instr in disassemble(mem, fiber.text_section())? {
+            let count = match self.hits.get(&(fiber.id(), instr.address)) {
+                Some(count) => count.to_string(),
+                None => "#####".to_string(),
+            };
+            let _ = write!(out, "{:>10} {:>8}  {}", count, format!("#{:x}", instr.address), instr);
+            if instr.opcode.is_branch() {
+                let branch = self.branch(fiber.id(), instr.address).unwrap_or_default();
+                let _ = write!(out, "  [taken {}, not taken {}]", branch.taken, branch.not_taken);
+            }
+            let _ = writeln!(out);
+        }
+        Ok(out)
+    }
+
+    /// LCOV tracefile of a fiber with line and branch records. Instructions of
+    /// its text section that are missing in `map` are left out.
+    pub fn lcov(&self, mem: &Memory, fiber: &Fiber, map: &SourceMap) -> Result<String, MachineError> {
+        let mut lines: BTreeMap<u32, u64> = BTreeMap::new();
+        let mut branches: Vec<(u32, u64, Option<Branch>)> = Vec::new();
+        for instr in disassemble(mem, fiber.text_section())? {
+            let Some(line) = map.line(instr.address) else {
+                continue;
+            };
+            let hits = self.hits(fiber.id(), instr.address);
+            *lines.entry(line).or_insert(0) += hits;
+            if instr.opcode.is_branch() {
+                branches.push((line, instr.address, (hits > 0).then(|| self.branch(fiber.id(), instr.address).unwrap_or_default())));
+            }
+        }
+
+        let mut out = String::new();
+        let _ = writeln!(out, "TN:");
+        let _ = writeln!(out, "SF:{}", map.file);
+        for (line, address, branch) in &branches {
+            match branch {
+                Some(branch) => {
+                    let _ = writeln!(out, "BRDA:{},{},0,{}", line, address, branch.taken);
+                    let _ = writeln!(out, "BRDA:{},{},1,{}", line, address, branch.not_taken);
+                },
+                None => {
+                    let _ = writeln!(out, "BRDA:{},{},0,-", line, address);
+                    let _ = writeln!(out, "BRDA:{},{},1,-", line, address);
+                },
+            }
+        }
+        let hit = branches.iter()
+            .filter_map(|(_, _, branch)| *branch)
+            .map(|branch| (branch.taken > 0) as usize + (branch.not_taken > 0) as usize)
+            .sum::<usize>();
+        let _ = writeln!(out, "BRF:{}", branches.len() * 2);
+        let _ = writeln!(out, "BRH:{}", hit);
+        for (line, count) in &lines {
+            let _ = writeln!(out, "DA:{},{}", line, count);
+        }
+        let _ = writeln!(out, "LF:{}", lines.len());
+        let _ = writeln!(out, "LH:{}", lines.values().filter(|count| **count > 0).count());
+        let _ = writeln!(out, "end_of_record");
+        Ok(out)
+    }
+}
+
+fn disassemble(mem: &Memory, section: &Section) -> Result<Vec<Instruction>, MachineError> {
+    let mut instrs = Vec::new();
+    let mut address = 0;
+    while address < section.len() as u64 {
+        let instr = decode(mem, section, address as usize)?;
+        address = instr.next();
+        instrs.push(instr);
+    }
+    Ok(instrs)
+}
+
+impl Tracer for Coverage {
+    fn wants(&self, kind: EventKind) -> bool {
+        kind == EventKind::Execute
+    }
+
+    fn record(&mut self, event: &TraceEvent) {
+        let (Event::Execute, Some(fiber), Some(pc)) = (&event.event, event.fiber, event.pc) else {
+            return;
+        };
+        if let Some(branch) = self.pending.remove(&fiber) {
+            let counts = self.branches.entry((fiber, branch.address)).or_default();
+            if pc == branch.next() {
+                counts.not_taken += 1;
+            } else {
+                counts.taken += 1;
+            }
+        }
+        *self.hits.entry((fiber, pc)).or_insert(0) += 1;
+        if let Some(instr) = event.instruction
+            && instr.opcode.is_branch() {
+            self.pending.insert(fiber, instr);
+        }
+    }
+}
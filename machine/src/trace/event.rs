@@ -0,0 +1,104 @@
+use crate::{fiber::fiber::FiberState, opcode::instruction::Instruction};
+
+/// What happened, with the data specific to it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// the instruction is about to execute
+    Execute,
+    Spawn,
+    Kill,
+    State { from: FiberState, to: FiberState },
+    Allocate { address: usize, size: usize },
+    Deallocate { address: usize, size: usize },
+    /// a fault nothing caught
+    Fault { error: String },
+}
+
+/// Event kinds, used to filter before an event is even built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Execute = 0x00,
+    Spawn = 0x01,
+    Kill = 0x02,
+    State = 0x03,
+    Allocate = 0x04,
+    Deallocate = 0x05,
+    Fault = 0x06,
+}
+
+impl EventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Execute => "execute",
+            Self::Spawn => "spawn",
+            Self::Kill => "kill",
+            Self::State => "state",
+            Self::Allocate => "allocate",
+            Self::Deallocate => "deallocate",
+            Self::Fault => "fault",
+        }
+    }
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Execute => EventKind::Execute,
+            Self::Spawn => EventKind::Spawn,
+            Self::Kill => EventKind::Kill,
+            Self::State { .. } => EventKind::State,
+            Self::Allocate { .. } => EventKind::Allocate,
+            Self::Deallocate { .. } => EventKind::Deallocate,
+            Self::Fault { .. } => EventKind::Fault,
+        }
+    }
+}
+
+/// An event with the context it happened in. Allocations made by the
+/// machine itself carry no fiber.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub fiber: Option<u64>,
+    pub pc: Option<u64>,
+    pub instruction: Option<Instruction>,
+    pub event: Event,
+}
+
+/// Which events a sink keeps, everything by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    kinds: u8,
+    fibers: Option<Vec<u64>>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self { kinds: u8::MAX, fibers: None }
+    }
+}
+
+impl Filter {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// events of `kinds` coming from `fibers`, or from anywhere with `None`
+    pub fn new(kinds: &[EventKind], fibers: Option<&[u64]>) -> Self {
+        Self {
+            kinds: kinds.iter().fold(0, |acc, kind| acc | (1 << *kind as u8)),
+            fibers: fibers.map(|fibers| fibers.to_vec()),
+        }
+    }
+
+    pub fn wants(&self, kind: EventKind) -> bool {
+        (self.kinds >> kind as u8) & 1 == 1
+    }
+
+    pub fn matches(&self, event: &TraceEvent) -> bool {
+        self.wants(event.event.kind()) && match (&self.fibers, event.fiber) {
+            (Some(fibers), Some(fiber)) => fibers.contains(&fiber),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::{fmt::Write as _, io::Write};
+
+use crate::trace::{event::{Event, EventKind, Filter, TraceEvent}, tracer::Tracer};
+
+/// Writes one JSON object per event and line, e.g.
+/// `{"event":"execute","fiber":7,"pc":10,"instruction":"PUSH 65"}`.
+/// Write errors are counted rather than stopping the machine.
+#[derive(Debug)]
+pub struct JsonLines<W: Write + Send> {
+    out: W,
+    filter: Filter,
+    errors: usize,
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(out: W, filter: Filter) -> Self {
+        Self { out, filter, errors: 0 }
+    }
+
+    /// lines that could not be written
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Send> Tracer for JsonLines<W> {
+    fn wants(&self, kind: EventKind) -> bool {
+        self.filter.wants(kind)
+    }
+
+    fn record(&mut self, event: &TraceEvent) {
+        if !self.filter.matches(event) {
+            return;
+        }
+        if writeln!(self.out, "{}", to_json(event)).is_err() {
+            self.errors += 1;
+        }
+    }
+}
+
+pub fn to_json(event: &TraceEvent) -> String {
+    let mut line = format!("{{\"event\":\"{}\"", event.event.kind().name());
+    if let Some(fiber) = event.fiber {
+        let _ = write!(line, ",\"fiber\":{}", fiber);
+    }
+    if let Some(pc) = event.pc {
+        let _ = write!(line, ",\"pc\":{}", pc);
+    }
+    if let Some(instruction) = &event.instruction {
+        let _ = write!(line, ",\"instruction\":\"{}\"", escape(&instruction.to_string()));
+    }
+    match &event.event {
+        Event::State { from, to } => {
+            let _ = write!(line, ",\"from\":\"{:?}\",\"to\":\"{:?}\"", from, to);
+        },
+        Event::Allocate { address, size } | Event::Deallocate { address, size } => {
+            let _ = write!(line, ",\"address\":{},\"size\":{}", address, size);
+        },
+        Event::Fault { error } => {
+            let _ = write!(line, ",\"error\":\"{}\"", escape(error));
+        },
+        Event::Execute | Event::Spawn | Event::Kill => {},
+    }
+    line.push('}');
+    line
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
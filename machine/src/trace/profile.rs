@@ -0,0 +1,160 @@
+use std::{collections::{BTreeMap, HashMap}, fmt::Write, time::Duration};
+
+use crate::{opcode::{instruction::Instruction, opcodes::Opcodes}, trace::{event::{Event, EventKind, TraceEvent}, tracer::Tracer}};
+
+/// How often one text section address ran.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub instruction: Instruction,
+    pub count: u64,
+}
+
+/// Time spent in scheduling slices.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Slices {
+    pub count: u64,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Slices {
+    pub fn add(&mut self, slice: Duration) {
+        self.min = if self.count == 0 { slice } else { self.min.min(slice) };
+        self.max = self.max.max(slice);
+        self.total += slice;
+        self.count += 1;
+    }
+
+    pub fn merge(&mut self, other: &Slices) {
+        if other.count == 0 {
+            return;
+        }
+        self.min = if self.count == 0 { other.min } else { self.min.min(other.min) };
+        self.max = self.max.max(other.max);
+        self.total += other.total;
+        self.count += other.count;
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 { Duration::ZERO } else { self.total / self.count as u32 }
+    }
+}
+
+/// Counts of one fiber, kept after the fiber is gone.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FiberProfile {
+    pub addresses: BTreeMap<u64, Hit>,
+    pub opcodes: HashMap<Opcodes, u64>,
+    pub slices: Slices,
+}
+
+impl FiberProfile {
+    pub fn instructions(&self) -> u64 {
+        self.addresses.values().map(|hit| hit.count).sum()
+    }
+
+    fn merge(&mut self, other: &FiberProfile) {
+        for (address, hit) in &other.addresses {
+            self.addresses.entry(*address)
+                .and_modify(|own| own.count += hit.count)
+                .or_insert(*hit);
+        }
+        for (opcode, count) in &other.opcodes {
+            *self.opcodes.entry(*opcode).or_insert(0) += count;
+        }
+        self.slices.merge(&other.slices);
+    }
+}
+
+/// Execution counts per address and opcode, per fiber. Installed as a
+/// tracer it sees every instruction, see `Machine::set_profiling`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    fibers: HashMap<u64, FiberProfile>,
+}
+
+impl Profile {
+    pub fn fiber(&self, fiber: u64) -> Option<&FiberProfile> {
+        self.fibers.get(&fiber)
+    }
+
+    pub fn fibers(&self) -> impl Iterator<Item = (&u64, &FiberProfile)> {
+        self.fibers.iter()
+    }
+
+    /// Every fiber added up. Addresses of different fibers are only
+    /// comparable when they ran the same text.
+    pub fn aggregate(&self) -> FiberProfile {
+        let mut total = FiberProfile::default();
+        for profile in self.fibers.values() {
+            total.merge(profile);
+        }
+        total
+    }
+
+    pub fn add_slice(&mut self, fiber: u64, slice: Duration) {
+        self.fibers.entry(fiber).or_default().slices.add(slice);
+    }
+
+    /// Hot spots first: the `top` busiest addresses with their disassembly,
+    /// then counts per opcode and slice timings.
+    pub fn report(&self, top: usize) -> String {
+        let total = self.aggregate();
+        let instructions = total.instructions().max(1);
+        let mut out = String::new();
+
+        let _ = writeln!(out, "{:>8} {:>12} {:>7}  instruction", "address", "count", "%");
+        let mut hot: Vec<(&u64, &Hit)> = total.addresses.iter().collect();
+        hot.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        for (address, hit) in hot.iter().take(top) {
+            let _ = writeln!(out, "{:>8} {:>12} {:>6.2}%  {}",
+                format!("#{:x}", address), hit.count, hit.count as f64 * 100.0 / instructions as f64, hit.instruction);
+        }
+
+        let _ = writeln!(out);
+        let _ = writeln!(out, "{:>8} {:>12} {:>7}", "opcode", "count", "%");
+        let mut opcodes: Vec<(&Opcodes, &u64)> = total.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(u16::from(*a.0).cmp(&u16::from(*b.0))));
+        for (opcode, count) in opcodes {
+            let _ = writeln!(out, "{:>8} {:>12} {:>6.2}%", opcode, count, *count as f64 * 100.0 / instructions as f64);
+        }
+
+        let _ = writeln!(out);
+        let slices = total.slices;
+        let _ = writeln!(out, "slices: {} total {:?} mean {:?} min {:?} max {:?}",
+            slices.count, slices.total, slices.mean(), slices.min, slices.max);
+        out
+    }
+
+    /// Folded stacks (`frame;frame count` per line) for flamegraph tools.
+    /// There are no call frames yet, so a stack is the fiber followed by the
+    /// instruction that ran.
+    pub fn folded(&self) -> String {
+        let mut fibers: Vec<(&u64, &FiberProfile)> = self.fibers.iter().collect();
+        fibers.sort_by_key(|(id, _)| **id);
+        let mut out = String::new();
+        for (id, profile) in fibers {
+            for (address, hit) in &profile.addresses {
+                let _ = writeln!(out, "fiber {:x};#{:x} {} {}", id, address, hit.instruction, hit.count);
+            }
+        }
+        out
+    }
+}
+
+impl Tracer for Profile {
+    fn wants(&self, kind: EventKind) -> bool {
+        kind == EventKind::Execute
+    }
+
+    fn record(&mut self, event: &TraceEvent) {
+        if let (Event::Execute, Some(fiber), Some(pc), Some(instruction)) = (&event.event, event.fiber, event.pc, event.instruction) {
+            let profile = self.fibers.entry(fiber).or_default();
+            profile.addresses.entry(pc)
+                .and_modify(|hit| hit.count += 1)
+                .or_insert(Hit { instruction, count: 1 });
+            *profile.opcodes.entry(instruction.opcode).or_insert(0) += 1;
+        }
+    }
+}
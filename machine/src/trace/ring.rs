@@ -0,0 +1,56 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+use crate::trace::{event::{EventKind, Filter, TraceEvent}, tracer::Tracer};
+
+/// Keeps the last `capacity` events in memory. Clones share the buffer, so
+/// a test can hand one to the machine and read events through another.
+#[derive(Debug, Clone)]
+pub struct RingBuffer {
+    events: Arc<Mutex<VecDeque<TraceEvent>>>,
+    capacity: usize,
+    filter: Filter,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize, filter: Filter) -> Self {
+        Self {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            filter,
+        }
+    }
+
+    /// oldest first
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
+
+impl Tracer for RingBuffer {
+    fn wants(&self, kind: EventKind) -> bool {
+        self.filter.wants(kind)
+    }
+
+    fn record(&mut self, event: &TraceEvent) {
+        if self.capacity == 0 || !self.filter.matches(event) {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+    }
+}
@@ -0,0 +1,31 @@
+use crate::trace::event::{EventKind, TraceEvent};
+
+/// Receives trace events from a `Machine`. Events a tracer doesn't want
+/// are never built, instruction events in particular cost nothing then.
+pub trait Tracer: Send {
+    fn wants(&self, kind: EventKind) -> bool;
+    fn record(&mut self, event: &TraceEvent);
+}
+
+/// Hands every event to two tracers, each only gets what it asks for.
+pub struct Tee<'a> {
+    pub first: &'a mut dyn Tracer,
+    pub second: Option<&'a mut dyn Tracer>,
+}
+
+impl Tracer for Tee<'_> {
+    fn wants(&self, kind: EventKind) -> bool {
+        self.first.wants(kind) || self.second.as_ref().is_some_and(|tracer| tracer.wants(kind))
+    }
+
+    fn record(&mut self, event: &TraceEvent) {
+        let kind = event.event.kind();
+        if self.first.wants(kind) {
+            self.first.record(event);
+        }
+        if let Some(tracer) = &mut self.second
+            && tracer.wants(kind) {
+            tracer.record(event);
+        }
+    }
+}
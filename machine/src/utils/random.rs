@@ -1,5 +1,8 @@
 use rand::RngCore;
 
+use crate::fiber::actor::MAX_LOCAL_ID;
+
+/// never 0, that's the host
 pub fn random_fiber_id(rng: &mut Box<rand::prelude::ThreadRng>) -> u64 {
-    rng.next_u64()
+    (rng.next_u64() & MAX_LOCAL_ID).max(1)
 }
\ No newline at end of file
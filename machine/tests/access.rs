@@ -1,6 +1,7 @@
 #[cfg(test)]
 pub mod tests {
-    use machine::memory::memory::Memory;
+    use machine::{memory::memory::{Endianness, Memory}, utils::binary::{Combine, Split}};
+    use proptest::prelude::*;
 
     #[test]
     fn read_write() {
@@ -25,4 +26,69 @@ pub mod tests {
         let mut mem = Memory::new(32).unwrap();
         mem.write_u64(30, 120).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn byte_order() {
+        let mut mem = Memory::new(64).unwrap();
+        assert_eq!(mem.endianness(), Endianness::Little);
+        mem.write_u32(0, 0x11223344).unwrap();
+        assert_eq!(mem.read_u8(0).unwrap(), 0x44);
+
+        mem.set_endianness(Endianness::Big);
+        mem.write_u32(0, 0x11223344).unwrap();
+        assert_eq!(mem.read_u8(0).unwrap(), 0x11);
+    }
+
+    #[test]
+    fn signed_and_float() {
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let mut mem = Memory::new(128).unwrap();
+            mem.set_endianness(endianness);
+            mem.write_i8(0, i8::MIN).unwrap();
+            mem.write_i16(8, -1234).unwrap();
+            mem.write_i32(16, -123123).unwrap();
+            mem.write_i64(24, i64::MIN).unwrap();
+            mem.write_f32(32, -1.5).unwrap();
+            mem.write_f64(40, std::f64::consts::PI).unwrap();
+            assert_eq!(mem.read_i8(0).unwrap(), i8::MIN);
+            assert_eq!(mem.read_i16(8).unwrap(), -1234);
+            assert_eq!(mem.read_i32(16).unwrap(), -123123);
+            assert_eq!(mem.read_i64(24).unwrap(), i64::MIN);
+            assert_eq!(mem.read_f32(32).unwrap(), -1.5);
+            assert_eq!(mem.read_f64(40).unwrap(), std::f64::consts::PI);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn big_endian_matches_split_combine(a: u16, b: u32, c: u64) {
+            let mut mem = Memory::new(64).unwrap();
+            mem.set_endianness(Endianness::Big);
+            mem.write_u16(0, a).unwrap();
+            mem.write_u32(8, b).unwrap();
+            mem.write_u64(16, c).unwrap();
+
+            let (h, l): (u8, u8) = u16::split(a);
+            prop_assert_eq!((mem.read_u8(0).unwrap(), mem.read_u8(1).unwrap()), (h, l));
+            let bytes: Vec<u8> = (8..12).map(|i| mem.read_u8(i).unwrap()).collect();
+            prop_assert_eq!(u32::combine((bytes[0], bytes[1], bytes[2], bytes[3])), b);
+            let bytes: Vec<u8> = (16..24).map(|i| mem.read_u8(i).unwrap()).collect();
+            let split: (u8, u8, u8, u8, u8, u8, u8, u8) = u64::split(c);
+            prop_assert_eq!(split, (bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]));
+            prop_assert_eq!(mem.read_u64(16).unwrap(), c);
+        }
+
+        #[test]
+        fn round_trip_across_byte_orders(val: u64, f: f64) {
+            let mut mem = Memory::new(64).unwrap();
+            mem.set_endianness(Endianness::Big);
+            mem.write_u64(0, val).unwrap();
+            mem.set_endianness(Endianness::Little);
+            prop_assert_eq!(mem.read_u64(0).unwrap(), val.swap_bytes());
+            mem.write_u64(0, val).unwrap();
+            prop_assert_eq!(mem.read_u64(0).unwrap(), val);
+            mem.write_f64(8, f).unwrap();
+            prop_assert_eq!(mem.read_f64(8).unwrap().to_bits(), f.to_bits());
+        }
+    }
+}
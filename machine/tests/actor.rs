@@ -0,0 +1,85 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{fiber::{actor::{ActorId, Failure, MAX_LOCAL_ID}, fiber::{FiberState, Reg}, value::Value}, machine::{config::MachineConfig, machine::{Machine, Outgoing}}, opcode::assembler::assemble};
+
+    fn machine(node: u16) -> Machine {
+        Machine::with_config(MachineConfig {
+            keep_halted: true,
+            node,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[&str]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    /// sends 5 to the actor from the host, keeps what comes back in R0 and
+    /// R1 and sets R2 when RECV says it is a failure
+    const SENDER: &[&str] = &[
+        "RECV", "DROP", "PUSH 5", "SWP", "SEND", "RECV", "JZ #26", "POP R1", "POP R0", "HLT",
+        "INC R2", "JMP #1e",
+    ];
+
+    #[test]
+    fn bits() {
+        let actor = ActorId::new(0x12, 0xabc);
+        assert_eq!(actor.to_bits(), 0x0012_0000_0000_0abc);
+        assert_eq!(ActorId::from_bits(actor.to_bits()), actor);
+        assert_eq!(ActorId::new(1, u64::MAX), ActorId::new(1, MAX_LOCAL_ID));
+        assert_eq!(actor.to_string(), "0xabc@18");
+
+        let mut machine = machine(7);
+        let fid = machine.spawn().unwrap();
+        assert!(fid <= MAX_LOCAL_ID);
+        assert_eq!(machine.fiber(fid).unwrap().actor(), ActorId::new(7, fid));
+    }
+
+    #[test]
+    fn dead_actor() {
+        let mut machine = machine(0);
+        let dead = machine.spawn().unwrap();
+        load(&mut machine, dead, &["HLT"]);
+        machine.execute().unwrap();
+
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, SENDER);
+        machine.send(fid, Value::Actor(ActorId::new(0, dead))).unwrap();
+        machine.execute().unwrap();
+        let regs = machine.fiber(fid).unwrap().registers();
+        assert_eq!(regs.get(Reg::R0), Failure::DeadActor as u64);
+        assert_eq!(regs.get(Reg::R1), dead);
+        assert_eq!(regs.get(Reg::R2), 1);
+    }
+
+    #[test]
+    fn unknown_node() {
+        let mut machine = machine(1);
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, SENDER);
+        let remote = ActorId::new(2, 0x33);
+        machine.send(fid, Value::Actor(remote)).unwrap();
+        machine.execute().unwrap();
+        let regs = machine.fiber(fid).unwrap().registers();
+        assert_eq!(regs.get(Reg::R0), Failure::UnknownNode as u64);
+        assert_eq!(regs.get(Reg::R1), remote.to_bits());
+        assert_eq!(regs.get(Reg::R2), 1);
+
+        // with routing on it waits for the node layer to carry the message
+        let mut routed = self::machine(1);
+        routed.set_routing(true);
+        let fid = routed.spawn().unwrap();
+        load(&mut routed, fid, SENDER);
+        routed.send(fid, Value::Actor(remote)).unwrap();
+        routed.execute().unwrap();
+        assert_eq!(routed.fiber(fid).unwrap().state(), FiberState::WAITING);
+        let outgoing = routed.take_outgoing();
+        assert_eq!(outgoing, vec![Outgoing { from: ActorId::new(1, fid), to: remote, value: Value::Int(5), rpc: None }]);
+        routed.bounce(outgoing[0].clone(), Failure::DeadActor);
+        routed.execute().unwrap();
+        assert_eq!(routed.fiber(fid).unwrap().registers().get(Reg::R0), Failure::DeadActor as u64);
+    }
+}
@@ -0,0 +1,58 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{execptions::MachineError, fiber::fiber::{FiberState, Reg}, machine::machine::Machine, opcode::{assembler::assemble, instruction::{decode, Operands}, opcodes::Opcodes}};
+
+    #[test]
+    fn round_trip() {
+        let lines = ["PUSH 65", "POP R3", "MOV SP, 16", "JNZ #a", "PUSHT Float, 1.5", "PUSHT Bool, 1", "ADD", "THROW 7"];
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        let fid = machine.spawn().unwrap();
+        let mut address = 0;
+        for line in lines {
+            let instr = assemble(line, address).unwrap();
+            assert_eq!(instr.to_string(), line);
+            machine.write_bytecodes(fid, &instr.bytecodes()).unwrap();
+            let fiber = machine.fiber(fid).unwrap();
+            assert_eq!(decode(machine.memory(), fiber.text_section(), address as usize).unwrap(), instr);
+            address = instr.next();
+        }
+    }
+
+    #[test]
+    fn syntax() {
+        assert_eq!(assemble("push -1 ; comment", 0).unwrap().operands, Operands::Imm(u64::MAX));
+        assert_eq!(assemble("PUSH 0x10", 0).unwrap().imm().unwrap(), 16);
+        assert_eq!(assemble("PUSHT Bool, true", 0).unwrap().imm().unwrap(), 1);
+        assert!(matches!(assemble("NOPE", 0), Err(MachineError::InvalidOpcode(_))));
+        assert!(matches!(assemble("POP R9", 0), Err(MachineError::InvalidBytecode(_))));
+        assert!(matches!(assemble("ADD 1", 0), Err(MachineError::InvalidBytecode(_))));
+        assert!(matches!(assemble("MOV R0", 0), Err(MachineError::InvalidBytecode(_))));
+        assert_eq!(assemble("HLT", 4).unwrap().opcode, Opcodes::HLT);
+    }
+
+    #[test]
+    fn single_step() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        let fid = machine.spawn().unwrap();
+        for line in ["PUSH 2", "DROP", "DROP", "POP R0", "HLT"] {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+        machine.step(fid).unwrap();
+        let fiber = machine.fiber(fid).unwrap();
+        assert_eq!(fiber.stack_slots(machine.memory()).unwrap().len(), 1);
+        assert_eq!(fiber.registers().get(Reg::PC), 10);
+
+        machine.step(fid).unwrap();
+        // the fault leaves the fiber alive and past the DROP
+        let fault = machine.step(fid).unwrap_err();
+        assert!(matches!(fault.error, MachineError::StackUnderflow));
+        assert_eq!(fault.pc, Some(12));
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::PC), 14);
+
+        assert!(machine.step(fid).is_err());
+        machine.step(fid).unwrap();
+        assert_eq!(machine.fiber(fid).unwrap().state(), FiberState::HALTED);
+        assert!(matches!(machine.step(fid).unwrap_err().error, MachineError::InvalidFiberState));
+    }
+}
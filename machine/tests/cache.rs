@@ -0,0 +1,61 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{fiber::fiber::{Fiber, FiberState, Reg}, memory::memory::Memory, opcode::opcodes::Opcodes};
+
+    fn program(mem: &mut Memory, f: &Fiber) {
+        // MOV R0 3, DEC R0, JMP 24, YLD
+        f.text_section().append_data(mem, Opcodes::MOV as u16).unwrap();
+        f.text_section().append_data(mem, 0u8).unwrap();
+        f.text_section().append_data(mem, 3u64).unwrap();
+        f.text_section().append_data(mem, Opcodes::DEC as u16).unwrap();
+        f.text_section().append_data(mem, 0u8).unwrap();
+        f.text_section().append_data(mem, Opcodes::JMP as u16).unwrap();
+        f.text_section().append_data(mem, 24u64).unwrap();
+        f.text_section().append_data(mem, Opcodes::YLD as u16).unwrap();
+    }
+
+    #[test]
+    fn reuses_decoded() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        program(&mut mem, &f);
+        f.execute(&mut mem).unwrap();
+        assert_eq!(f.state(), FiberState::BLOCKED);
+        assert_eq!(f.decode_cache().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn invalidated_by_writes() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        program(&mut mem, &f);
+        f.execute(&mut mem).unwrap();
+
+        // INC R1, HLT
+        f.text_section().append_data(&mut mem, Opcodes::INC as u16).unwrap();
+        f.text_section().append_data(&mut mem, 1u8).unwrap();
+        f.text_section().append_data(&mut mem, Opcodes::HLT as u16).unwrap();
+        f.execute(&mut mem).unwrap();
+        assert_eq!(f.state(), FiberState::HALTED);
+        // only what ran after the write was decoded again
+        assert_eq!(f.decode_cache().unwrap().len(), 2);
+        assert_eq!(f.get_register(&mem, Reg::R1).unwrap(), 1);
+    }
+
+    #[test]
+    fn same_result_without_cache() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut cached = Fiber::new(&mut mem, &mut rng).unwrap();
+        let mut uncached = Fiber::new(&mut mem, &mut rng).unwrap();
+        uncached.set_decode_cache(false);
+        program(&mut mem, &cached);
+        program(&mut mem, &uncached);
+        cached.execute(&mut mem).unwrap();
+        uncached.execute(&mut mem).unwrap();
+        assert!(uncached.decode_cache().is_none());
+        assert_eq!(cached.registers().snapshot(), uncached.registers().snapshot());
+    }
+}
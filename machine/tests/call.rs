@@ -0,0 +1,142 @@
+#[cfg(test)]
+pub mod tests {
+    use std::time::{Duration, Instant};
+
+    use machine::{execptions::{Fault, MachineError}, fiber::{actor::ActorId, fiber::{FiberState, Reg}, value::Value}, machine::{config::MachineConfig, machine::Machine}, opcode::assembler::assemble};
+
+    fn machine(config: MachineConfig) -> Machine {
+        Machine::with_config(MachineConfig {
+            keep_halted: true,
+            ..config
+        }).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[&str]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    /// a client calling the actor it gets from the host with the number it
+    /// gets next, keeps the reply in R0
+    const CLIENT: &[&str] = &["RECV", "DROP", "RECV", "DROP", "SWP", "CALLR 0", "POP R0", "HLT"];
+
+    /// calls the actor from the host with 1 and a 20ms timeout, keeps the
+    /// reply in R1 or the fault code in R0
+    const GUARDED: &[&str] = &["TRY #29", "RECV", "DROP", "PUSH 1", "SWP", "CALLR 20", "POP R1", "HLT", "POP R0", "HLT"];
+
+    fn client(machine: &mut Machine, lines: &[&str], server: u64, args: &[i64]) -> u64 {
+        let fid = machine.spawn().unwrap();
+        load(machine, fid, lines);
+        machine.send(fid, Value::Actor(ActorId::new(0, server))).unwrap();
+        for arg in args {
+            machine.send(fid, Value::Int(*arg)).unwrap();
+        }
+        fid
+    }
+
+    #[test]
+    fn replies_find_their_callers() {
+        for config in [MachineConfig::default(), MachineConfig { typed_stack: true, verify: true, ..Default::default() }, MachineConfig { workers: 4, ..Default::default() }] {
+            let mut machine = machine(config);
+            // doubles every request, one after the other
+            let server = machine.spawn().unwrap();
+            load(&mut machine, server, &["RECV", "DROP", "DUP", "ADD", "REPLY", "JMP 0"]);
+            let clients: Vec<_> = (1..=5).map(|arg| client(&mut machine, CLIENT, server, &[arg])).collect();
+            machine.execute().unwrap();
+            for (arg, fid) in (1..=5).zip(&clients) {
+                let fiber = machine.fiber(*fid).unwrap();
+                assert_eq!(fiber.state(), FiberState::HALTED);
+                assert_eq!(fiber.registers().get(Reg::R0), arg * 2);
+            }
+        }
+
+    }
+
+    #[test]
+    fn newest_request_first() {
+        let mut machine = machine(MachineConfig::default());
+        // takes two requests and answers the second one first
+        let server = machine.spawn().unwrap();
+        load(&mut machine, server, &["RECV", "DROP", "RECV", "DROP", "PUSH 100", "ADD", "REPLY", "PUSH 200", "ADD", "REPLY", "HLT"]);
+        let first = client(&mut machine, CLIENT, server, &[1]);
+        // the first request is in the server's mailbox before the second
+        for _ in 0..CLIENT.len() - 2 {
+            machine.step(first).unwrap();
+        }
+        let second = client(&mut machine, CLIENT, server, &[2]);
+        machine.execute().unwrap();
+        assert_eq!(machine.fiber(first).unwrap().registers().get(Reg::R0), 201);
+        assert_eq!(machine.fiber(second).unwrap().registers().get(Reg::R0), 102);
+    }
+
+    #[test]
+    fn timeouts() {
+        let mut machine = machine(MachineConfig::default());
+        // takes the request and never answers
+        let server = machine.spawn().unwrap();
+        load(&mut machine, server, &["RECV", "DROP", "DROP", "RECV"]);
+        let fid = client(&mut machine, GUARDED, server, &[]);
+        let start = Instant::now();
+        machine.execute().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let fiber = machine.fiber(fid).unwrap();
+        assert_eq!(fiber.registers().get(Reg::R0), MachineError::CallTimeout.code());
+        assert_eq!(fiber.state(), FiberState::HALTED);
+
+        // CALLR 0 takes the timeout from the config
+        let mut configured = self::machine(MachineConfig { call_timeout: Some(Duration::from_millis(10)), ..Default::default() });
+        let server = configured.spawn().unwrap();
+        load(&mut configured, server, &["RECV", "DROP", "DROP", "RECV"]);
+        let fid = client(&mut configured, CLIENT, server, &[1]);
+        let fault = configured.execute().unwrap_err();
+        assert!(matches!(fault.error, MachineError::CallTimeout));
+        assert_eq!((fault.fiber, fault.pc), (Some(fid), Some(10)));
+    }
+
+    /// servers that never reply, with what their callers hear
+    const FAULTY: &[(&[&str], &str)] = &[
+        (&["RECV", "DROP", "DROP", "THROW #7"], "uncaught exception 0x7"),
+        (&["RECV", "HLT"], "halted without replying"),
+        (&["HLT"], "dead actor"),
+    ];
+
+    #[test]
+    fn remote_faults() {
+        for (server_lines, detail) in FAULTY {
+            for lines in [GUARDED, CLIENT] {
+                let mut machine = machine(MachineConfig::default());
+                let server = machine.spawn().unwrap();
+                load(&mut machine, server, server_lines);
+                let fid = client(&mut machine, lines, server, &[1]);
+                let mut faults: Vec<Fault> = machine.execute().err().into_iter().collect();
+                faults.extend(machine.take_faults());
+                let fault = faults.iter().find(|fault| fault.fiber == Some(fid));
+                if lines == GUARDED {
+                    assert!(fault.is_none());
+                    assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R0), MachineError::RemoteFault(None).code());
+                } else {
+                    // uncaught, the fault says what happened on the other side
+                    let fault = fault.unwrap();
+                    assert!(matches!(fault.error, MachineError::RemoteFault(_)));
+                    assert!(fault.error.to_string().contains(detail), "{}", fault.error);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reply_without_request() {
+        let mut machine = machine(MachineConfig::default());
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["PUSH 1", "REPLY", "HLT"]);
+        assert!(matches!(machine.execute().unwrap_err().error, MachineError::InvalidReply));
+
+        // plain messages can't be replied to either
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["RECV", "DROP", "REPLY", "HLT"]);
+        machine.send(fid, Value::Int(1)).unwrap();
+        assert!(matches!(machine.execute().unwrap_err().error, MachineError::InvalidReply));
+    }
+}
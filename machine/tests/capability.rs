@@ -0,0 +1,212 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{execptions::MachineError, fiber::{actor::ActorId, capability::{Allow, Capabilities, Capability, Limits}, fiber::{FiberState, Reg}, value::Value}, machine::{config::MachineConfig, device::OutputBuffer, machine::Machine, native::{Outcome, Signature}}, opcode::assembler::assemble};
+
+    fn machine() -> Machine {
+        Machine::with_config(MachineConfig {
+            keep_halted: true,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[&str]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    fn denied(machine: &mut Machine) -> Capability {
+        match machine.execute().unwrap_err().error {
+            MachineError::CapabilityDenied(capability) => capability,
+            err => panic!("expected a denied capability, got {}", err),
+        }
+    }
+
+    #[test]
+    fn syscalls_and_devices() {
+        let mut machine = machine();
+        machine.register_native(1, Signature::new(&[], &[]), |_| Ok(Outcome::Return(vec![])));
+        machine.register_native(2, Signature::new(&[], &[]), |_| Ok(Outcome::Return(vec![])));
+        let stdout = OutputBuffer::new();
+        machine.set_stdout(stdout.clone());
+        let caps = Capabilities { syscalls: Allow::only(&[1]), devices: Allow::only(&[1]), ..Capabilities::none() };
+
+        let fid = machine.spawn_with(caps.clone()).unwrap();
+        load(&mut machine, fid, &["SYSCALL 1", "PUSH 33", "OUT 1", "SYSCALL 2", "HLT"]);
+        let err = machine.execute().unwrap_err();
+        assert_eq!(err.error.to_string(), "capability denied: syscall 0x2");
+        assert_eq!(err.pc, Some(30));
+        assert_eq!(stdout.bytes(), b"!");
+
+        let fid = machine.spawn_with(caps.clone()).unwrap();
+        load(&mut machine, fid, &["PUSH 33", "OUT 2", "HLT"]);
+        assert_eq!(denied(&mut machine), Capability::Device(2));
+
+        let fid = machine.spawn_with(caps).unwrap();
+        load(&mut machine, fid, &["IN 0", "HLT"]);
+        assert_eq!(denied(&mut machine), Capability::Device(0));
+    }
+
+    #[test]
+    fn denied_faults_can_be_caught() {
+        let mut machine = machine();
+        let fid = machine.spawn_with(Capabilities::none()).unwrap();
+        // TRY handler, SPAWN 0, handler: POP R0, HLT
+        load(&mut machine, fid, &["TRY #16", "SPAWN 0", "HLT", "POP R0", "HLT"]);
+        machine.execute().unwrap();
+        let code = MachineError::CapabilityDenied(Capability::Spawn).code();
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R0), code);
+        assert!(machine.fiber(fid).unwrap().children().is_empty());
+    }
+
+    #[test]
+    fn children_inherit() {
+        let mut machine = machine();
+        let stdout = OutputBuffer::new();
+        machine.set_stdout(stdout.clone());
+        let caps = Capabilities { devices: Allow::only(&[1]), spawn: true, ..Capabilities::none() };
+        let parent = machine.spawn_with(caps.clone()).unwrap();
+        // the child writes to stdout, then tries stderr the parent never had
+        load(&mut machine, parent, &[
+            "SPAWN #c", "HLT",
+            "PUSH 33", "OUT 1", "PUSH 33", "OUT 2", "HLT",
+        ]);
+        let fault = machine.execute().unwrap_err();
+        let child = machine.fiber(parent).unwrap().children()[0];
+        assert_eq!(fault.fiber, Some(child));
+        assert!(matches!(fault.error, MachineError::CapabilityDenied(Capability::Device(2))));
+        assert_eq!(stdout.bytes(), b"!");
+        assert_eq!(machine.fiber(child).unwrap().capabilities(), &caps);
+        assert_eq!(machine.fiber(parent).unwrap().state(), FiberState::HALTED);
+    }
+
+    #[test]
+    fn grandchildren_stay_limited() {
+        let mut machine = machine();
+        let caps = Capabilities { spawn: true, limits: Limits { children: Some(2), ..Default::default() }, ..Capabilities::none() };
+        let root = machine.spawn_with(caps).unwrap();
+        // the root and its child spawn one each, which uses up the budget
+        // they share before the root tries a second
+        load(&mut machine, root, &["SPAWN #18", "YLD", "SPAWN #18", "HLT", "SPAWN #24", "HLT", "HLT"]);
+        assert_eq!(denied(&mut machine), Capability::Children(2));
+        assert!(machine.take_faults().is_empty());
+
+        let root = machine.fiber(root).unwrap();
+        assert_eq!(root.children().len(), 1);
+        let child = machine.fiber(root.children()[0]).unwrap();
+        assert_eq!(child.children().len(), 1);
+        assert_eq!(child.capabilities().limits.children, Some(2));
+        assert!(!machine.fiber(child.children()[0]).unwrap().capabilities().syscalls.allows(0));
+    }
+
+    #[test]
+    fn spawn_chains_run_out() {
+        // every fiber spawns a copy of itself, the chain ends with the budget
+        let mut machine = machine();
+        let caps = Capabilities { spawn: true, limits: Limits { children: Some(3), ..Default::default() }, ..Capabilities::none() };
+        let root = machine.spawn_with(caps).unwrap();
+        load(&mut machine, root, &["SPAWN 0", "HLT"]);
+        assert_eq!(denied(&mut machine), Capability::Children(3));
+        assert!(machine.take_faults().is_empty());
+        assert_eq!(machine.fibers().count(), 4);
+
+        // without a limit on children, every generation has less fuel left
+        let mut machine = self::machine();
+        let caps = Capabilities { spawn: true, limits: Limits { fuel: Some(20), ..Default::default() }, ..Capabilities::none() };
+        let root = machine.spawn_with(caps).unwrap();
+        load(&mut machine, root, &["SPAWN 0", "HLT"]);
+        assert!(matches!(machine.execute().unwrap_err().error, MachineError::OutOfFuel));
+        // the last two run dry, one after its SPAWN and one before anything
+        assert_eq!(machine.take_faults().len(), 1);
+        assert_eq!(machine.fibers().count(), 21);
+        let child = machine.fiber(root).unwrap().children()[0];
+        assert_eq!(machine.fiber(child).unwrap().fuel(), Some(17));
+    }
+
+    #[test]
+    fn failed_spawn_gives_slot_back() {
+        let mut machine = Machine::with_config(MachineConfig { memory_size: 64 * 1024, max_memory_size: 64 * 1024, keep_halted: true, ..Default::default() }).unwrap();
+        let caps = Capabilities { spawn: true, limits: Limits { children: Some(1), ..Default::default() }, ..Capabilities::none() };
+        let fid = machine.spawn_with(caps).unwrap();
+        // the first SPAWN runs out of memory, the second comes after the host made room
+        load(&mut machine, fid, &["TRY #16", "SPAWN #2b", "HLT", "POP R0", "RECV", "DROP", "DROP", "SPAWN #2b", "HLT", "HLT"]);
+        let mut fillers = Vec::new();
+        while let Ok(filler) = machine.spawn() {
+            load(&mut machine, filler, &["HLT"]);
+            fillers.push(filler);
+        }
+        machine.execute().unwrap();
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R0), MachineError::InsufficientMemory(None).code());
+        assert_eq!(machine.fibers().count(), fillers.len() + 1);
+        for filler in &fillers {
+            machine.kill(*filler).unwrap();
+        }
+        machine.send(fid, Value::Int(0)).unwrap();
+        machine.execute().unwrap();
+        assert_eq!(machine.fiber(fid).unwrap().children().len(), 1);
+    }
+
+    #[test]
+    fn peers() {
+        let mut machine = machine();
+        let stranger = machine.spawn().unwrap();
+        load(&mut machine, stranger, &["RECV", "POP R1", "POP R0", "HLT"]);
+        let caps = Capabilities { spawn: true, ..Capabilities::none() };
+        let parent = machine.spawn_with(caps).unwrap();
+        // parent and child may talk to each other, knowing the stranger's id
+        // is not enough to reach it
+        load(&mut machine, parent, &[
+            "RECV", "DROP", "SPAWN #33", "PUSH 1", "SWP", "SEND", "RECV", "DROP", "POP R0", "PUSH 2", "SWP", "SEND", "HLT",
+            "RECV", "SEND", "HLT",
+        ]);
+        machine.send(parent, Value::Actor(ActorId::new(0, stranger))).unwrap();
+        let fault = machine.execute().unwrap_err();
+        assert_eq!(fault.fiber, Some(parent));
+        assert!(matches!(fault.error, MachineError::CapabilityDenied(Capability::Peer(id)) if id == ActorId::new(0, stranger)));
+        assert_eq!(machine.fiber(parent).unwrap().registers().get(Reg::R0), 1);
+        // the stranger never heard from anyone
+        assert_eq!(machine.fiber(stranger).unwrap().state(), FiberState::WAITING);
+
+        // it still takes messages from fibers that were granted it
+        let friend = machine.spawn_with(Capabilities { peers: Allow::only(&[stranger]), ..Capabilities::none() }).unwrap();
+        load(&mut machine, friend, &["RECV", "DROP", "PUSH 3", "SWP", "SEND", "HLT"]);
+        machine.send(friend, Value::Actor(ActorId::new(0, stranger))).unwrap();
+        machine.execute().unwrap();
+        let stranger = machine.fiber(stranger).unwrap();
+        assert_eq!(stranger.registers().get(Reg::R0), 3);
+        assert_eq!(stranger.registers().get(Reg::R1), friend);
+    }
+
+    #[test]
+    fn limits() {
+        let mut machine = machine();
+        let stack = Capabilities { limits: Limits { stack: Some(32), ..Default::default() }, ..Capabilities::none() };
+        let fid = machine.spawn_with(stack).unwrap();
+        load(&mut machine, fid, &["PUSH 1", "DUP", "DUP", "DUP", "DUP", "HLT"]);
+        let fault = machine.execute().unwrap_err();
+        assert!(matches!(fault.error, MachineError::StackOverflow));
+        assert_eq!(fault.pc, Some(16));
+
+        // the tighter of the two fuel limits wins
+        let mut machine = Machine::with_config(MachineConfig { fuel: Some(100), keep_halted: true, ..Default::default() }).unwrap();
+        let fuel = Capabilities { limits: Limits { fuel: Some(3), ..Default::default() }, ..Capabilities::none() };
+        let fid = machine.spawn_with(fuel).unwrap();
+        load(&mut machine, fid, &["INC R0", "JMP 0"]);
+        assert!(matches!(machine.execute().unwrap_err().error, MachineError::OutOfFuel));
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R0), 2);
+    }
+
+    #[test]
+    fn optimizer_keeps_stack_limit() {
+        for optimize in [false, true] {
+            let mut machine = Machine::with_config(MachineConfig { optimize, keep_halted: true, ..Default::default() }).unwrap();
+            let stack = Capabilities { limits: Limits { stack: Some(8), ..Default::default() }, ..Capabilities::none() };
+            let fid = machine.spawn_with(stack).unwrap();
+            load(&mut machine, fid, &["PUSH 1", "PUSH 2", "ADD", "POP R0", "HLT"]);
+            let fault = machine.execute().unwrap_err();
+            assert!(matches!(fault.error, MachineError::StackOverflow), "optimize: {}", optimize);
+            assert_eq!(fault.pc, Some(10));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{fiber::fiber::Fiber, machine::machine::Machine, memory::memory::Memory, opcode::opcodes::Opcodes, trace::coverage::{Coverage, SourceMap}};
+
+    /// PUSH n, loop: PUSH 1, SWP, SUB, INC R0, JNZ loop, HLT, PUSH 7
+    fn countdown(n: u64) -> Vec<u64> {
+        vec![
+            1, Opcodes::PUSH as u64, 3, n,
+            1, Opcodes::PUSH as u64, 3, 1,
+            1, Opcodes::SWP as u64,
+            1, Opcodes::SUB as u64,
+            1, Opcodes::INC as u64, 0, 0,
+            1, Opcodes::JNZ as u64, 3, 10,
+            1, Opcodes::HLT as u64,
+            1, Opcodes::PUSH as u64, 3, 7,
+        ]
+    }
+
+    fn load(mem: &mut Memory, f: &Fiber, program: &[u64]) {
+        for pair in program.chunks(2) {
+            match pair[0] {
+                0 => f.text_section().append_data(mem, pair[1] as u8).unwrap(),
+                1 => f.text_section().append_data(mem, pair[1] as u16).unwrap(),
+                _ => f.text_section().append_data(mem, pair[1]).unwrap(),
+            }
+        }
+    }
+
+    #[test]
+    fn branch_directions() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        load(&mut mem, &f, &countdown(3));
+        let mut coverage = Coverage::default();
+        f.execute_traced(&mut mem, Some(&mut coverage)).unwrap();
+
+        assert_eq!(coverage.hits(f.id(), 0), 1);
+        assert_eq!(coverage.hits(f.id(), 10), 3);
+        assert_eq!(coverage.hits(f.id(), 39), 0);
+        let branch = coverage.branch(f.id(), 27).unwrap();
+        assert_eq!((branch.taken, branch.not_taken), (2, 1));
+        assert!(branch.is_covered());
+
+        let listing = coverage.listing(&mem, &f).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[5].contains("JNZ #a") && lines[5].ends_with("[taken 2, not taken 1]"));
+        assert!(lines[7].trim_start().starts_with("#####"));
+    }
+
+    #[test]
+    fn merged_across_fibers() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        machine.set_coverage(true);
+        let f1 = machine.spawn().unwrap();
+        let f2 = machine.spawn().unwrap();
+        machine.write_bytecodes(f1, &countdown(1)).unwrap();
+        machine.write_bytecodes(f2, &countdown(4)).unwrap();
+        machine.execute().unwrap();
+
+        let coverage = machine.take_coverage();
+        assert_eq!(coverage.hits(f1, 10), 1);
+        assert_eq!(coverage.hits(f2, 10), 4);
+        // both run the same program, so they fold into one
+        let mut total = Coverage::default();
+        for fid in [f1, f2] {
+            total.merge_fiber(&coverage, fid, f1);
+        }
+        assert_eq!(total.hits(f1, 10), 5);
+        let branch = total.branch(f1, 27).unwrap();
+        assert_eq!((branch.taken, branch.not_taken), (3, 2));
+
+        // a second run adds up
+        let f3 = machine.spawn().unwrap();
+        machine.write_bytecodes(f3, &countdown(2)).unwrap();
+        machine.execute().unwrap();
+        total.merge_fiber(machine.coverage(), f3, f1);
+        assert_eq!(total.hits(f1, 10), 7);
+        assert_eq!(total.branch(f1, 27).unwrap().taken, 4);
+    }
+
+    #[test]
+    fn programs_kept_apart() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        machine.set_coverage(true);
+        let f1 = machine.spawn().unwrap();
+        let f2 = machine.spawn().unwrap();
+        machine.write_bytecodes(f1, &countdown(2)).unwrap();
+        // PUSH 0, JZ to the very next instruction, HLT
+        machine.write_bytecodes(f2, &[1, Opcodes::PUSH as u64, 3, 0, 1, Opcodes::JZ as u64, 3, 20, 1, Opcodes::HLT as u64]).unwrap();
+        machine.execute().unwrap();
+
+        let mut coverage = Coverage::default();
+        coverage.merge(machine.coverage());
+        assert_eq!(coverage.fibers().count(), 2);
+        assert_eq!(coverage.hits(f1, 10), 2);
+        assert_eq!(coverage.hits(f2, 10), 1);
+        assert_eq!(coverage.addresses(f2).collect::<Vec<_>>(), vec![(0, 1), (10, 1), (20, 1)]);
+        // landing on the fall-through is not taken, whatever the target
+        let branch = coverage.branch(f2, 10).unwrap();
+        assert_eq!((branch.taken, branch.not_taken), (0, 1));
+        assert_eq!(coverage.branch(f1, 10), None);
+    }
+
+    #[test]
+    fn lcov_by_source_line() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        load(&mut mem, &f, &countdown(2));
+        let mut coverage = Coverage::default();
+        f.execute_traced(&mut mem, Some(&mut coverage)).unwrap();
+
+        let mut map = SourceMap::new("countdown.s");
+        for (address, line) in [(0, 1), (10, 3), (20, 4), (22, 4), (24, 5), (27, 6), (37, 7), (39, 9)] {
+            map.insert(address, line);
+        }
+        let lcov = coverage.lcov(&mem, &f, &map).unwrap();
+        let records: Vec<&str> = lcov.lines().collect();
+        assert_eq!(records[1], "SF:countdown.s");
+        assert!(records.contains(&"BRDA:6,27,0,1"));
+        assert!(records.contains(&"BRDA:6,27,1,1"));
+        assert!(records.contains(&"BRH:2"));
+        assert!(records.contains(&"DA:4,4"));
+        assert!(records.contains(&"DA:9,0"));
+        assert!(records.contains(&"LF:7"));
+        assert!(records.contains(&"LH:6"));
+        assert_eq!(records.last(), Some(&"end_of_record"));
+    }
+}
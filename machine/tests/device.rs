@@ -0,0 +1,111 @@
+#[cfg(test)]
+pub mod tests {
+    use std::{io::Cursor, thread, time::Duration};
+
+    use machine::{execptions::MachineError, fiber::fiber::{FiberState, Reg}, machine::{config::MachineConfig, device::{Chunk, Input, OutputBuffer}, machine::Machine}, opcode::assembler::assemble};
+
+    fn machine() -> Machine {
+        Machine::with_config(MachineConfig {
+            keep_halted: true,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[&str]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    #[test]
+    fn out() {
+        let mut machine = machine();
+        let (stdout, stderr) = (OutputBuffer::new(), OutputBuffer::new());
+        machine.set_stdout(stdout.clone());
+        machine.set_stderr(stderr.clone());
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["PUSH 104", "OUT 1", "PUSH 0x169", "OUT 1", "PUSH 33", "OUT 2", "HLT"]);
+        machine.execute().unwrap();
+        assert_eq!(stdout.bytes(), b"hi");
+        assert_eq!(stderr.chunks(), vec![Chunk { fiber: fid, bytes: b"!".to_vec() }]);
+    }
+
+    #[test]
+    fn interleaved_by_slice() {
+        let mut machine = machine();
+        let stdout = OutputBuffer::new();
+        machine.set_stdout(stdout.clone());
+        let f1 = machine.spawn().unwrap();
+        load(&mut machine, f1, &["PUSH 97", "OUT 1", "PUSH 98", "OUT 1", "YLD", "PUSH 99", "OUT 1", "HLT"]);
+        let f2 = machine.spawn().unwrap();
+        load(&mut machine, f2, &["PUSH 65", "OUT 1", "YLD", "PUSH 66", "OUT 1", "HLT"]);
+        machine.execute().unwrap();
+        let chunks: Vec<(u64, Vec<u8>)> = stdout.chunks().into_iter().map(|chunk| (chunk.fiber, chunk.bytes)).collect();
+        assert_eq!(chunks, vec![(f1, b"ab".to_vec()), (f2, b"A".to_vec()), (f1, b"c".to_vec()), (f2, b"B".to_vec())]);
+    }
+
+    #[test]
+    fn in_blocks() {
+        let mut machine = machine();
+        let stdout = OutputBuffer::new();
+        machine.set_stdout(stdout.clone());
+        let stdin = Input::new();
+        machine.set_stdin(stdin.clone());
+        stdin.write(b"x");
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["IN 0", "OUT 1", "IN 0", "OUT 1", "IN 0", "POP R0", "HLT"]);
+        let other = machine.spawn().unwrap();
+        load(&mut machine, other, &["YLD", "YLD", "HLT"]);
+
+        machine.schedule().unwrap();
+        machine.schedule().unwrap();
+        assert_eq!(machine.fiber(fid).unwrap().state(), FiberState::WAITING);
+        assert_eq!(stdout.bytes(), b"x");
+        machine.schedule().unwrap();
+        assert_eq!(machine.fiber(other).unwrap().state(), FiberState::HALTED);
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            stdin.write(b"y");
+            stdin.close();
+        });
+        machine.execute().unwrap();
+        assert_eq!(stdout.bytes(), b"xy");
+        // -1 once the input is closed
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R0), u64::MAX);
+    }
+
+    #[test]
+    fn from_reader() {
+        let mut machine = machine();
+        let stdout = OutputBuffer::new();
+        machine.set_stdout(stdout.clone());
+        machine.set_stdin(Input::from_reader(Cursor::new(b"ok".to_vec())));
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["IN 0", "OUT 1", "IN 0", "OUT 1", "HLT"]);
+        machine.execute().unwrap();
+        assert_eq!(stdout.bytes(), b"ok");
+    }
+
+    #[test]
+    fn wrong_direction() {
+        let mut machine = machine();
+        machine.set_stdout(OutputBuffer::new());
+        let f1 = machine.spawn().unwrap();
+        load(&mut machine, f1, &["PUSH 1", "OUT 0", "HLT"]);
+        let fault = machine.execute().unwrap_err();
+        assert!(matches!(fault.error, MachineError::InvalidDevice(_)));
+        assert_eq!(fault.pc, Some(10));
+
+        let f2 = machine.spawn().unwrap();
+        load(&mut machine, f2, &["IN 1", "HLT"]);
+        let fault = machine.execute().unwrap_err();
+        assert!(matches!(fault.error, MachineError::InvalidDevice(_)));
+        // stdin is closed unless the host sets one
+        let f3 = machine.spawn().unwrap();
+        load(&mut machine, f3, &["IN 0", "POP R0", "HLT"]);
+        machine.execute().unwrap();
+        assert_eq!(machine.fiber(f3).unwrap().registers().get(Reg::R0), u64::MAX);
+    }
+}
@@ -0,0 +1,137 @@
+#[cfg(all(test, feature = "async"))]
+pub mod tests {
+    use std::{future::Future, pin::pin, sync::{Arc, Mutex}, task::{Context, Poll, Wake, Waker}, thread::{self, Thread}, time::Duration};
+
+    use machine::{execptions::MachineError, fiber::{actor::ActorId, fiber::Reg, value::{Tag, Value}}, machine::{config::MachineConfig, driver::Exit, machine::Machine, native::{Outcome, Signature, Slot}}, opcode::assembler::assemble};
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// the simplest executor there is, returns the output and how often it polled
+    fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut polls = 0;
+        loop {
+            polls += 1;
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return (output, polls);
+            }
+            thread::park();
+        }
+    }
+
+    fn machine() -> Machine {
+        Machine::with_config(MachineConfig {
+            keep_halted: true,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[&str]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    #[test]
+    fn runs_in_slices() {
+        let mut machine = machine();
+        let fid = machine.spawn().unwrap();
+        // counts 20 down, one step per slice
+        load(&mut machine, fid, &["PUSH 20", "PUSH 1", "SWP", "SUB", "YLD", "JNZ #a", "POP R0", "HLT"]);
+        let (res, polls) = block_on(machine.drive(4));
+        assert_eq!(res.unwrap(), Exit::Done);
+        assert!(polls >= 5, "{} polls", polls);
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R0), 0);
+    }
+
+    #[test]
+    fn waits_for_messages() {
+        let mut machine = machine();
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["RECV", "POP R1", "POP R0", "HLT"]);
+        let drive = machine.drive(8);
+        let handle = drive.handle();
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            handle.send(fid, Value::Int(42));
+        });
+        let (res, polls) = block_on(drive);
+        sender.join().unwrap();
+        assert_eq!(res.unwrap(), Exit::Done);
+        // it sleeps until the message is there instead of spinning
+        assert!((2..10).contains(&polls), "{} polls", polls);
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R0), 42);
+    }
+
+    #[test]
+    fn waits_for_natives() {
+        let mut machine = machine();
+        machine.register_native(1, Signature::new(&[], &[Slot::Stack(Tag::Int)]), |call| {
+            let pending = call.defer();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                pending.complete(Ok(vec![Value::Int(7)]));
+            });
+            Ok(Outcome::Deferred)
+        });
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["SYSCALL 1", "POP R0", "HLT"]);
+        let (res, _) = block_on(machine.drive(8));
+        assert_eq!(res.unwrap(), Exit::Done);
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R0), 7);
+    }
+
+    #[test]
+    fn waits_for_timeouts() {
+        let mut machine = machine();
+        let silent = machine.spawn().unwrap();
+        load(&mut machine, silent, &["RECV", "DROP", "DROP", "RECV"]);
+        let fid = machine.spawn().unwrap();
+        // the call times out, the handler keeps the fault code in R0
+        load(&mut machine, fid, &["TRY #24", "PUSH 1", "RECV", "DROP", "CALLR 20", "HLT", "POP R0", "HLT"]);
+        machine.send(fid, Value::Actor(ActorId::new(0, silent))).unwrap();
+        let (res, _) = block_on(machine.drive(8));
+        assert_eq!(res.unwrap(), Exit::Done);
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R0), MachineError::CallTimeout.code());
+    }
+
+    #[test]
+    fn ticks_and_cancel() {
+        let mut machine = machine();
+        let fid = machine.spawn().unwrap();
+        // keeps whatever arrives last, forever
+        load(&mut machine, fid, &["RECV", "DROP", "POP R1", "JMP 0"]);
+        let seen = Arc::new(Mutex::new(0));
+        let ticks = seen.clone();
+        let drive = machine.drive(8).on_tick(move |machine| {
+            let mut ticks = ticks.lock().unwrap();
+            *ticks += 1;
+            machine.send(fid, Value::Int(*ticks)).unwrap();
+        });
+        let handle = drive.handle();
+        let host = thread::spawn(move || {
+            for _ in 0..3 {
+                thread::sleep(Duration::from_millis(5));
+                handle.tick();
+            }
+            thread::sleep(Duration::from_millis(5));
+            handle.cancel();
+        });
+        let (res, _) = block_on(drive);
+        host.join().unwrap();
+        assert_eq!(res.unwrap(), Exit::Cancelled);
+        assert_eq!(*seen.lock().unwrap(), 3);
+        // the fiber is left waiting for the next message
+        assert_eq!(machine.fiber(fid).unwrap().registers().get(Reg::R1), 3);
+        assert!(machine.fiber(fid).unwrap().mailbox().is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{execptions::{MachineError, CODE_MASK, THROWN}, fiber::fiber::{Fiber, FiberState, Reg}, machine::machine::Machine, memory::memory::Memory, opcode::opcodes::Opcodes};
+
+    fn fiber(mem: &mut Memory) -> Fiber {
+        let mut rng = Box::new(rand::rng());
+        Fiber::new(mem, &mut rng).unwrap()
+    }
+
+    fn op(mem: &mut Memory, f: &Fiber, opcode: Opcodes) {
+        f.text_section().append_data(mem, opcode as u16).unwrap();
+    }
+
+    fn op_imm(mem: &mut Memory, f: &Fiber, opcode: Opcodes, imm: u64) {
+        op(mem, f, opcode);
+        f.text_section().append_data(mem, imm).unwrap();
+    }
+
+    fn op_reg(mem: &mut Memory, f: &Fiber, opcode: Opcodes, reg: u8) {
+        op(mem, f, opcode);
+        f.text_section().append_data(mem, reg).unwrap();
+    }
+
+    #[test]
+    fn catch_underflow() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::TRY, 14);
+        op(&mut mem, &f, Opcodes::ADD);
+        op(&mut mem, &f, Opcodes::HLT);
+        // handler
+        op_reg(&mut mem, &f, Opcodes::POP, 0);
+        op(&mut mem, &f, Opcodes::HLT);
+
+        f.execute(&mut mem).unwrap();
+        assert_eq!(f.state(), FiberState::HALTED);
+        assert_eq!(f.get_register(&mem, Reg::R0).unwrap(), MachineError::StackUnderflow.code());
+        assert!(f.handlers().is_empty());
+    }
+
+    #[test]
+    fn throw_restores_stack() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::PUSH, 1);
+        op_imm(&mut mem, &f, Opcodes::TRY, 40);
+        op_imm(&mut mem, &f, Opcodes::PUSH, 2);
+        op_imm(&mut mem, &f, Opcodes::THROW, 0x99);
+        // handler
+        op_reg(&mut mem, &f, Opcodes::POP, 0);
+        op_reg(&mut mem, &f, Opcodes::POP, 1);
+        op(&mut mem, &f, Opcodes::HLT);
+
+        f.execute(&mut mem).unwrap();
+        assert_eq!(f.get_register(&mem, Reg::R0).unwrap(), 0x99 | THROWN);
+        assert_eq!(f.get_register(&mem, Reg::R1).unwrap(), 1);
+        assert_eq!(f.get_register(&mem, Reg::SP).unwrap(), 0);
+    }
+
+    #[test]
+    fn nested_rethrow() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::TRY, 35);
+        op_imm(&mut mem, &f, Opcodes::TRY, 23);
+        op_reg(&mut mem, &f, Opcodes::POP, 0);
+        // inner handler
+        op(&mut mem, &f, Opcodes::DROP);
+        op_imm(&mut mem, &f, Opcodes::THROW, 7);
+        // outer handler
+        op_reg(&mut mem, &f, Opcodes::POP, 1);
+        op(&mut mem, &f, Opcodes::HLT);
+
+        f.execute(&mut mem).unwrap();
+        assert_eq!(f.get_register(&mem, Reg::R1).unwrap(), 7 | THROWN);
+    }
+
+    #[test]
+    fn thrown_codes_stay_apart() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::TRY, 20);
+        op_imm(&mut mem, &f, Opcodes::THROW, 3);
+        // handler
+        op_reg(&mut mem, &f, Opcodes::POP, 0);
+        op(&mut mem, &f, Opcodes::HLT);
+
+        f.execute(&mut mem).unwrap();
+        let code = f.get_register(&mem, Reg::R0).unwrap();
+        assert_ne!(code, MachineError::InvalidPointer(None).code());
+        assert_eq!(code & CODE_MASK, 3);
+        assert_eq!(MachineError::Thrown(u64::MAX).code(), CODE_MASK | THROWN);
+    }
+
+    #[test]
+    fn endtry_leaves_block() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::TRY, 14);
+        op(&mut mem, &f, Opcodes::ENDTRY);
+        op(&mut mem, &f, Opcodes::ADD);
+        op(&mut mem, &f, Opcodes::HLT);
+
+        let fault = f.execute(&mut mem).unwrap_err();
+        assert!(matches!(fault.error, MachineError::StackUnderflow));
+        assert_eq!(f.state(), FiberState::HALTED);
+
+        let mut f = fiber(&mut mem);
+        op(&mut mem, &f, Opcodes::ENDTRY);
+        let fault = f.execute(&mut mem).unwrap_err();
+        assert!(matches!(fault.error, MachineError::InvalidHandler));
+    }
+
+    #[test]
+    fn uncaught_kills_only_offender() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        // THROW 3
+        let f1 = machine.spawn().unwrap();
+        machine.write_bytecodes(f1, &[1, Opcodes::THROW as u64, 3, 3]).unwrap();
+        // YLD, YLD, THROW 4
+        let f2 = machine.spawn().unwrap();
+        machine.write_bytecodes(f2, &[1, Opcodes::YLD as u64, 1, Opcodes::YLD as u64, 1, Opcodes::THROW as u64, 3, 4]).unwrap();
+        // PUSH 1, HLT
+        let f3 = machine.spawn().unwrap();
+        machine.write_bytecodes(f3, &[1, Opcodes::PUSH as u64, 3, 1, 1, Opcodes::HLT as u64]).unwrap();
+
+        let fault = machine.execute().unwrap_err();
+        assert_eq!(fault.fiber, Some(f1));
+        assert!(matches!(fault.error, MachineError::Thrown(3)));
+        let rest = machine.take_faults();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].fiber, Some(f2));
+        assert!(machine.fiber(f3).is_none());
+    }
+}
@@ -0,0 +1,75 @@
+#[cfg(test)]
+pub mod tests {
+    use std::error::Error;
+
+    use machine::{execptions::MachineError, fiber::fiber::{Fiber, Flag, Reg}, machine::machine::Machine, memory::memory::Memory, opcode::{instruction::{Instruction, Operands}, opcodes::Opcodes}};
+
+    #[test]
+    fn context() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        // MOV R3 7, PUSH 1, ADD
+        f.text_section().append_data(&mut mem, Opcodes::MOV as u16).unwrap();
+        f.text_section().append_data(&mut mem, 3u8).unwrap();
+        f.text_section().append_data(&mut mem, 7u64).unwrap();
+        f.text_section().append_data(&mut mem, Opcodes::PUSH as u16).unwrap();
+        f.text_section().append_data(&mut mem, 1u64).unwrap();
+        f.text_section().append_data(&mut mem, Opcodes::ADD as u16).unwrap();
+
+        let fault = f.execute(&mut mem).unwrap_err();
+        assert!(matches!(fault.error, MachineError::StackUnderflow));
+        assert_eq!(fault.fiber, Some(f.id()));
+        assert_eq!(fault.pc, Some(21));
+        assert_eq!(fault.instruction.unwrap().opcode, Opcodes::ADD);
+        let snapshot = fault.snapshot.as_ref().unwrap();
+        assert_eq!(snapshot.get(Reg::R3), 7);
+        assert!(!snapshot.flag(Flag::Zero));
+        assert!(fault.backtrace.is_empty());
+
+        let report = fault.to_string();
+        assert!(report.starts_with("stack underflow"));
+        assert!(report.contains("#15 ADD"));
+        assert!(report.contains("R3=0x7"));
+    }
+
+    #[test]
+    fn undecodable() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.text_section().append_data(&mut mem, 0xffffu16).unwrap();
+        let fault = f.execute(&mut mem).unwrap_err();
+        assert!(matches!(fault.error, MachineError::InvalidOpcode(_)));
+        assert_eq!(fault.pc, Some(0));
+        assert!(fault.instruction.is_none());
+    }
+
+    #[test]
+    fn missing_operand() {
+        let instr = Instruction { address: 0x1a, opcode: Opcodes::JZ, operands: Operands::None };
+        let err = instr.imm().unwrap_err();
+        assert!(matches!(err, MachineError::InvalidInstruction(_)));
+        assert_eq!(err.to_string(), "invalid instruction: JZ at #1a has no immediate operand");
+        assert!(matches!(instr.reg(), Err(MachineError::InvalidInstruction(_))));
+        let instr = Instruction { opcode: Opcodes::PUSH, operands: Operands::Reg(Reg::R0), ..instr };
+        assert!(matches!(instr.imm(), Err(MachineError::InvalidInstruction(_))));
+    }
+
+    fn run_host() -> Result<(), Box<dyn Error>> {
+        let mut machine = Machine::new(16 * 1024 * 1024)?;
+        let fid = machine.spawn()?;
+        // POP R9
+        machine.write_bytecodes(fid, &[1, 2, 0, 9])?;
+        machine.execute()?;
+        Ok(())
+    }
+
+    #[test]
+    fn composes_with_question_mark() {
+        let err = run_host().unwrap_err();
+        let fault = err.downcast_ref::<machine::execptions::Fault>().unwrap();
+        assert!(matches!(fault.error, MachineError::InvalidRegister));
+        assert!(err.source().is_some());
+    }
+}
@@ -1,6 +1,6 @@
 #[cfg(test)]
 pub mod tests {
-    use machine::{fiber::fiber::{Fiber, Flag}, memory::memory::Memory};
+    use machine::{fiber::fiber::{Fiber, FiberState, Flag, Reg, REGISTER_COUNT}, memory::memory::Memory};
 
     #[test]
     fn initialize() {
@@ -78,4 +78,35 @@ pub mod tests {
         assert_eq!(f.get_flag(&mem, Flag::Negative).unwrap(), false);
         assert_eq!(f.get_flag(&mem, Flag::Overflow).unwrap(), true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn register_snapshot() {
+        let mut mem = Memory::new(128 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.set_register(&mut mem, Reg::R0, 1998).unwrap();
+        f.set_flag(&mut mem, Flag::Negative, true).unwrap();
+        let snapshot = f.snapshot_registers(&mut mem).unwrap();
+        assert_eq!(mem.read_u64(snapshot.address + Reg::R0.index() * 8).unwrap(), 1998);
+        assert_eq!(mem.read_u8(snapshot.address + REGISTER_COUNT * 8).unwrap(), 0b0100);
+
+        f.set_register(&mut mem, Reg::R7, 42).unwrap();
+        assert_eq!(mem.read_u64(snapshot.address + Reg::R7.index() * 8).unwrap(), 0);
+        f.refresh_snapshot(&mut mem).unwrap();
+        assert_eq!(mem.read_u64(snapshot.address + Reg::R7.index() * 8).unwrap(), 42);
+        f.kill(&mut mem).unwrap();
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn memory_accessors_still_work() {
+        let mut mem = Memory::new(128 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let f = Fiber::new(&mut mem, &mut rng).unwrap();
+        assert_eq!(f.get_id(&mem).unwrap(), f.id());
+        f.set_state(&mut mem, FiberState::BLOCKED).unwrap();
+        assert_eq!(f.get_state(&mem).unwrap(), FiberState::BLOCKED);
+        assert_eq!(f.state(), FiberState::BLOCKED);
+        f.kill(&mut mem).unwrap();
+    }
+}
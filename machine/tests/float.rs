@@ -0,0 +1,149 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{execptions::MachineError, fiber::{fiber::{Fiber, Flag}, value::{Tag, Value}}, memory::memory::Memory, opcode::commands};
+
+    fn fiber(mem: &mut Memory) -> Fiber {
+        let mut rng = Box::new(rand::rng());
+        Fiber::new(mem, &mut rng).unwrap()
+    }
+
+    fn push(mem: &mut Memory, f: &mut Fiber, val: f64) {
+        commands::pusht(mem, f, Tag::Float, val.to_bits()).unwrap();
+    }
+
+    fn pop(mem: &mut Memory, f: &mut Fiber) -> f64 {
+        f64::from_bits(f.pop(mem).unwrap())
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        push(&mut mem, &mut f, 2.0);
+        push(&mut mem, &mut f, 7.0);
+        commands::fsub(&mut mem, &mut f).unwrap();
+        assert_eq!(pop(&mut mem, &mut f), 5.0);
+
+        push(&mut mem, &mut f, 4.0);
+        push(&mut mem, &mut f, 1.0);
+        commands::fdiv(&mut mem, &mut f).unwrap();
+        assert_eq!(pop(&mut mem, &mut f), 0.25);
+
+        push(&mut mem, &mut f, 1.5);
+        push(&mut mem, &mut f, 2.0);
+        commands::fmul(&mut mem, &mut f).unwrap();
+        commands::fneg(&mut mem, &mut f).unwrap();
+        assert!(f.get_flag(&mem, Flag::Negative).unwrap());
+        commands::fabs(&mut mem, &mut f).unwrap();
+        push(&mut mem, &mut f, 13.0);
+        commands::fadd(&mut mem, &mut f).unwrap();
+        commands::fsqrt(&mut mem, &mut f).unwrap();
+        assert_eq!(pop(&mut mem, &mut f), 4.0);
+    }
+
+    #[test]
+    fn conversions() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        commands::push(&mut mem, &mut f, -3i64 as u64).unwrap();
+        commands::itof(&mut mem, &mut f).unwrap();
+        assert_eq!(pop(&mut mem, &mut f), -3.0);
+
+        commands::push(&mut mem, &mut f, u64::MAX).unwrap();
+        commands::utof(&mut mem, &mut f).unwrap();
+        assert_eq!(pop(&mut mem, &mut f), u64::MAX as f64);
+
+        push(&mut mem, &mut f, -7.9);
+        commands::ftoi(&mut mem, &mut f).unwrap();
+        assert_eq!(f.pop(&mut mem).unwrap() as i64, -7);
+
+        push(&mut mem, &mut f, -7.9);
+        commands::ftou(&mut mem, &mut f).unwrap();
+        assert_eq!(f.pop(&mut mem).unwrap(), 0);
+
+        push(&mut mem, &mut f, f64::NAN);
+        commands::ftoi(&mut mem, &mut f).unwrap();
+        assert_eq!(f.pop(&mut mem).unwrap(), 0);
+    }
+
+    #[test]
+    fn ieee_rules() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        push(&mut mem, &mut f, 0.0);
+        push(&mut mem, &mut f, 1.0);
+        commands::fdiv(&mut mem, &mut f).unwrap();
+        assert_eq!(pop(&mut mem, &mut f), f64::INFINITY);
+        assert!(f.get_flag(&mem, Flag::Overflow).unwrap());
+
+        push(&mut mem, &mut f, 0.0);
+        push(&mut mem, &mut f, 0.0);
+        commands::fdiv(&mut mem, &mut f).unwrap();
+        assert!(pop(&mut mem, &mut f).is_nan());
+        assert!(f.get_flag(&mem, Flag::Carry).unwrap());
+
+        push(&mut mem, &mut f, -1.0);
+        commands::fsqrt(&mut mem, &mut f).unwrap();
+        assert!(pop(&mut mem, &mut f).is_nan());
+    }
+
+    #[test]
+    fn compare() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        push(&mut mem, &mut f, 2.0);
+        push(&mut mem, &mut f, 1.0);
+        commands::fcmp(&mut mem, &mut f).unwrap();
+        assert!(!f.get_flag(&mem, Flag::Zero).unwrap());
+        assert!(f.get_flag(&mem, Flag::Negative).unwrap());
+        assert!(!f.get_flag(&mem, Flag::Carry).unwrap());
+
+        push(&mut mem, &mut f, 1.0);
+        push(&mut mem, &mut f, 1.0);
+        commands::fcmp(&mut mem, &mut f).unwrap();
+        assert!(f.get_flag(&mem, Flag::Zero).unwrap());
+        assert!(!f.get_flag(&mem, Flag::Negative).unwrap());
+
+        push(&mut mem, &mut f, f64::NAN);
+        push(&mut mem, &mut f, 1.0);
+        commands::fcmp(&mut mem, &mut f).unwrap();
+        assert!(!f.get_flag(&mem, Flag::Zero).unwrap());
+        assert!(f.get_flag(&mem, Flag::Negative).unwrap());
+        assert!(f.get_flag(&mem, Flag::Carry).unwrap());
+        assert!(f.peek(&mem).is_err());
+    }
+
+    #[test]
+    fn traps() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        f.set_float_traps(true);
+        push(&mut mem, &mut f, 0.0);
+        push(&mut mem, &mut f, 1.0);
+        assert!(matches!(commands::fdiv(&mut mem, &mut f), Err(MachineError::FloatingPoint(_))));
+
+        push(&mut mem, &mut f, -1.0);
+        assert!(matches!(commands::fsqrt(&mut mem, &mut f), Err(MachineError::FloatingPoint(_))));
+
+        push(&mut mem, &mut f, f64::NAN);
+        push(&mut mem, &mut f, 1.0);
+        assert!(matches!(commands::fcmp(&mut mem, &mut f), Err(MachineError::FloatingPoint(_))));
+
+        push(&mut mem, &mut f, 1e300);
+        assert!(matches!(commands::ftoi(&mut mem, &mut f), Err(MachineError::FloatingPoint(_))));
+    }
+
+    #[test]
+    fn typed_operands() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = fiber(&mut mem);
+        f.enable_typed_stack();
+        commands::push(&mut mem, &mut f, 1).unwrap();
+        push(&mut mem, &mut f, 1.0);
+        assert!(matches!(commands::fadd(&mut mem, &mut f), Err(MachineError::TypeMismatch(_))));
+
+        commands::push(&mut mem, &mut f, 3).unwrap();
+        commands::itof(&mut mem, &mut f).unwrap();
+        assert_eq!(f.pop_value(&mut mem).unwrap(), Value::Float(3.0));
+    }
+}
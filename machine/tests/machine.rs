@@ -1,6 +1,6 @@
 #[cfg(test)]
 pub mod tests {
-    use machine::machine::machine::Machine;
+    use machine::{execptions::MachineError, fiber::fiber::{FiberState, Reg}, machine::{config::MachineConfig, machine::Machine}, memory::memory::Endianness};
 
 
     #[test]
@@ -8,6 +8,20 @@ pub mod tests {
         let _machine = Machine::new(16 * 1024 * 1024).unwrap();
     }
 
+    #[test]
+    fn growable_memory() {
+        let mut machine = Machine::with_config(MachineConfig {
+            memory_size: 4 * 1024,
+            max_memory_size: 1024 * 1024,
+            ..Default::default()
+        }).unwrap();
+        let f1 = machine.spawn().unwrap();
+        let f2 = machine.spawn().unwrap();
+        assert!(machine.memory().size() > 4 * 1024);
+        machine.kill(f1).unwrap();
+        machine.kill(f2).unwrap();
+    }
+
     #[test]
     fn spawn() {
         let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
@@ -54,4 +68,58 @@ pub mod tests {
         ]).unwrap();
         machine.execute().unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn big_endian_image() {
+        let mut machine = Machine::with_config(MachineConfig {
+            endianness: Endianness::Big,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(machine.memory().endianness(), Endianness::Big);
+        let fid = machine.spawn().unwrap();
+        machine.write_bytecodes(fid, &[
+            1, 1, 3, 65,
+            1, 1, 3, 66,
+            1, 4,
+            1, 2, 0, 0,
+            1, 26,
+        ]).unwrap();
+        machine.execute().unwrap();
+    }
+
+    #[test]
+    fn out_of_fuel() {
+        let mut machine = Machine::with_config(MachineConfig {
+            fuel: Some(100),
+            ..Default::default()
+        }).unwrap();
+        let fid = machine.spawn().unwrap();
+        // loop: TRY loop, JMP loop, the handler can't save it
+        machine.write_bytecodes(fid, &[1, 0x29, 3, 0, 1, 0x0b, 3, 0]).unwrap();
+        let fault = machine.execute().unwrap_err();
+        assert!(matches!(fault.error, MachineError::OutOfFuel));
+        assert_eq!(fault.fiber, Some(fid));
+        assert_eq!(fault.pc, Some(0));
+        assert_eq!(fault.error.to_string(), "out of fuel");
+    }
+
+    #[test]
+    fn keep_halted() {
+        let mut machine = Machine::with_config(MachineConfig {
+            keep_halted: true,
+            fuel: Some(10),
+            ..Default::default()
+        }).unwrap();
+        let fid = machine.spawn().unwrap();
+        // PUSH 65, POP R0, HLT
+        machine.write_bytecodes(fid, &[1, 1, 3, 65, 1, 2, 0, 0, 1, 26]).unwrap();
+        machine.execute().unwrap();
+        let fiber = machine.fiber(fid).unwrap();
+        assert_eq!(fiber.state(), FiberState::HALTED);
+        assert_eq!(fiber.registers().get(Reg::R0), 65);
+        assert_eq!(fiber.fuel(), Some(7));
+        assert!(!machine.schedule().unwrap());
+        machine.kill(fid).unwrap();
+        assert!(machine.fiber(fid).is_none());
+    }
+}
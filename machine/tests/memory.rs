@@ -1,9 +1,50 @@
 #[cfg(test)]
 pub mod tests {
-    use machine::memory::memory::Memory;
+    use machine::{execptions::MachineError, memory::{memory::{Memory, PAGE_SIZE}, store::FileStore}};
 
     #[test]
     fn initialize() {
         Memory::new(32).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn grow() {
+        let mut mem = Memory::growable(128, 4 * PAGE_SIZE).unwrap();
+        let ptr1 = mem.allocate(100).unwrap();
+        mem.write_u64(ptr1.address, 123123123123).unwrap();
+        let ptr2 = mem.allocate(1000).unwrap();
+        assert_eq!(mem.size(), PAGE_SIZE);
+        assert_eq!(ptr2.address, 100);
+        assert_eq!(mem.read_u64(ptr1.address).unwrap(), 123123123123);
+    }
+
+    #[test]
+    fn grow_past_max() {
+        let mut mem = Memory::growable(128, 2 * PAGE_SIZE).unwrap();
+        assert!(matches!(mem.allocate(2 * PAGE_SIZE), Err(MachineError::InsufficientMemory(_))));
+        assert_eq!(mem.size(), 128);
+    }
+
+    #[test]
+    fn grow_capped_at_max() {
+        let mut mem = Memory::growable(128, PAGE_SIZE + 16).unwrap();
+        mem.allocate(PAGE_SIZE).unwrap();
+        assert_eq!(mem.size(), PAGE_SIZE + 16);
+    }
+
+    #[test]
+    fn file_backed() {
+        let path = std::env::temp_dir().join(format!("machine-file-backed-{}", std::process::id()));
+        let store = FileStore::new(&path, PAGE_SIZE).unwrap();
+        let mut mem = Memory::with_store(Box::new(store), 64 * PAGE_SIZE).unwrap();
+        let ptr1 = mem.allocate(8).unwrap();
+        mem.write_u64(ptr1.address, u64::MAX).unwrap();
+        let ptr2 = mem.allocate(16 * PAGE_SIZE).unwrap();
+        mem.write_u64(ptr2.address + 16 * PAGE_SIZE - 8, 42).unwrap();
+        assert_eq!(mem.read_u64(ptr1.address).unwrap(), u64::MAX);
+        assert_eq!(mem.read_u64(ptr2.address + 16 * PAGE_SIZE - 8).unwrap(), 42);
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, mem.size());
+        drop(mem);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{fiber::{actor::ActorId, fiber::{FiberState, Message, Reg}, value::Value}, machine::{config::MachineConfig, device::OutputBuffer, machine::Machine}, opcode::assembler::assemble};
+
+    fn machine(config: MachineConfig) -> Machine {
+        Machine::with_config(MachineConfig {
+            keep_halted: true,
+            ..config
+        }).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[&str]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    /// the parent sends 5 to a child, which answers with twice the number
+    const PING_PONG: &[&str] = &[
+        "SPAWN #22", "PUSH 5", "SWP", "SEND", "RECV", "POP R1", "POP R0", "HLT",
+        // child
+        "RECV", "SWP", "DUP", "ADD", "SWP", "SEND", "HLT",
+    ];
+
+    fn ping_pong(config: MachineConfig) {
+        let mut machine = machine(config);
+        let parent = machine.spawn().unwrap();
+        load(&mut machine, parent, PING_PONG);
+        machine.execute().unwrap();
+
+        let fiber = machine.fiber(parent).unwrap();
+        let child = fiber.children()[0];
+        assert_eq!(fiber.registers().get(Reg::R0), 10);
+        assert_eq!(fiber.registers().get(Reg::R1), child);
+        assert_eq!(machine.fiber(child).unwrap().parent(), Some(parent));
+        assert_eq!(machine.fiber(child).unwrap().state(), FiberState::HALTED);
+    }
+
+    #[test]
+    fn spawn_send_recv() {
+        ping_pong(MachineConfig::default());
+        ping_pong(MachineConfig { typed_stack: true, verify: true, ..Default::default() });
+        ping_pong(MachineConfig { tier_up_threshold: Some(0), ..Default::default() });
+    }
+
+    #[test]
+    fn recv_waits_for_host() {
+        let mut machine = machine(MachineConfig { typed_stack: true, ..Default::default() });
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["RECV", "POP R1", "DROP", "RECV", "POP R2", "POP R0", "HLT"]);
+        machine.send(fid, Value::Int(1)).unwrap();
+        // nothing left to run once the second RECV finds the mailbox empty
+        machine.execute().unwrap();
+        assert_eq!(machine.fiber(fid).unwrap().state(), FiberState::WAITING);
+
+        machine.send(fid, Value::Str("hello".to_string())).unwrap();
+        assert_eq!(machine.fiber(fid).unwrap().mailbox().front(), Some(&Message { from: ActorId::new(0, 0), value: Value::Str("hello".to_string()), failure: None, rpc: None }));
+        machine.execute().unwrap();
+        let fiber = machine.fiber(fid).unwrap();
+        assert_eq!(fiber.state(), FiberState::HALTED);
+        assert_eq!(fiber.registers().get(Reg::R1), 0);
+        assert_eq!(fiber.registers().get(Reg::R2), 0);
+        assert!(fiber.mailbox().is_empty());
+    }
+
+    #[test]
+    fn strings_travel() {
+        let mut machine = machine(MachineConfig { typed_stack: true, ..Default::default() });
+        let stdout = OutputBuffer::new();
+        machine.set_stdout(stdout.clone());
+        let parent = machine.spawn().unwrap();
+        // the string goes from the host to the parent, on to the child and back
+        load(&mut machine, parent, &[
+            "RECV", "DROP", "SPAWN #20", "SEND", "RECV", "DROP", "OUT 1", "HLT",
+            "RECV", "SEND", "HLT",
+        ]);
+        machine.send(parent, Value::Str("copy".to_string())).unwrap();
+        machine.execute().unwrap();
+        assert_eq!(stdout.bytes(), b"copy");
+    }
+
+    #[test]
+    fn received_bytes_are_released() {
+        for typed_stack in [false, true] {
+            let mut machine = machine(MachineConfig { typed_stack, memory_size: 256 * 1024, max_memory_size: 256 * 1024, ..Default::default() });
+            let fid = machine.spawn().unwrap();
+            // the first payload stays on the stack, the rest are dropped
+            load(&mut machine, fid, &["RECV", "DROP", "RECV", "DROP", "DROP", "JMP #4"]);
+            machine.send(fid, Value::Bytes(vec![7; 16])).unwrap();
+            // a megabyte in all, four times the memory
+            for _ in 0..256 {
+                machine.send(fid, Value::Bytes(vec![1; 4096])).unwrap();
+                machine.execute().unwrap();
+            }
+            let fiber = machine.fiber(fid).unwrap();
+            assert_eq!(fiber.state(), FiberState::WAITING);
+            let mem = machine.memory();
+            let (_, kept) = fiber.stack_slots(mem).unwrap()[0];
+            assert_eq!(mem.read_u64(kept as usize).unwrap(), 16);
+            assert_eq!(mem.read_u8(kept as usize + 8).unwrap(), 7);
+        }
+    }
+}
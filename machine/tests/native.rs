@@ -0,0 +1,133 @@
+#[cfg(test)]
+pub mod tests {
+    use std::{sync::{Arc, Mutex}, thread, time::Duration};
+
+    use machine::{execptions::{MachineError, NATIVE}, fiber::{fiber::{FiberState, Reg}, value::{Tag, Value}}, machine::{config::MachineConfig, machine::Machine, native::{Outcome, Pending, Signature, Slot}}, opcode::assembler::assemble};
+
+    fn machine() -> Machine {
+        Machine::with_config(MachineConfig {
+            keep_halted: true,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[&str]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    fn sub() -> Signature {
+        Signature::new(&[Slot::Stack(Tag::Int), Slot::Stack(Tag::Int), Slot::Reg(Reg::R1)], &[Slot::Stack(Tag::Int), Slot::Reg(Reg::R2)])
+    }
+
+    #[test]
+    fn returns_results() {
+        let mut machine = machine();
+        machine.register_native(1, sub(), |call| {
+            let [Value::Int(a), Value::Int(b), Value::Int(c)] = call.args[..] else { unreachable!() };
+            Ok(Outcome::Return(vec![Value::Int(a - b), Value::Int(c * 2)]))
+        });
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["PUSH 2", "PUSH 40", "MOV R1, 21", "SYSCALL 1", "POP R0", "HLT"]);
+        machine.execute().unwrap();
+        let fiber = machine.fiber(fid).unwrap();
+        assert_eq!(fiber.registers().get(Reg::R0), 38);
+        assert_eq!(fiber.registers().get(Reg::R2), 42);
+        assert_eq!(fiber.registers().get(Reg::SP), 0);
+    }
+
+    #[test]
+    fn custom_errors() {
+        let mut machine = machine();
+        machine.register_native(7, Signature::new(&[], &[]), |_| Err(MachineError::Native(0x42, Some("no such file".to_string()))));
+        // TRY handler, SYSCALL 7, ENDTRY, HLT, handler: POP R0, HLT
+        let caught = machine.spawn().unwrap();
+        load(&mut machine, caught, &["TRY #18", "SYSCALL 7", "ENDTRY", "HLT", "POP R0", "HLT"]);
+        let uncaught = machine.spawn().unwrap();
+        load(&mut machine, uncaught, &["SYSCALL 7", "HLT"]);
+        let missing = machine.spawn().unwrap();
+        load(&mut machine, missing, &["SYSCALL 8", "HLT"]);
+
+        let fault = machine.execute().unwrap_err();
+        assert_eq!(machine.fiber(caught).unwrap().registers().get(Reg::R0), 0x42 | NATIVE);
+        assert_eq!(fault.fiber, Some(uncaught));
+        assert_eq!(fault.pc, Some(0));
+        assert_eq!(fault.error.to_string(), "native error 0x42: no such file");
+        let rest = machine.take_faults();
+        assert!(matches!(rest[0].error, MachineError::InvalidSyscall(_)));
+        assert_eq!(rest[0].fiber, Some(missing));
+    }
+
+    #[test]
+    fn broken_signature() {
+        let mut machine = machine();
+        machine.register_native(1, sub(), |_| Ok(Outcome::Return(vec![Value::Bool(true), Value::Int(0)])));
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["PUSH 1", "SYSCALL 1", "HLT"]);
+        let fault = machine.execute().unwrap_err();
+        // one stack argument short
+        assert!(matches!(fault.error, MachineError::StackUnderflow));
+
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &["PUSH 1", "PUSH 1", "SYSCALL 1", "HLT"]);
+        let fault = machine.execute().unwrap_err();
+        assert!(matches!(fault.error, MachineError::TypeMismatch(_)));
+    }
+
+    #[test]
+    fn deferred() {
+        let mut machine = machine();
+        let parked: Arc<Mutex<Option<Pending>>> = Arc::new(Mutex::new(None));
+        let slot = parked.clone();
+        machine.register_native(3, Signature::new(&[], &[Slot::Stack(Tag::Int)]), move |call| {
+            *slot.lock().unwrap() = Some(call.defer());
+            Ok(Outcome::Deferred)
+        });
+        let waiter = machine.spawn().unwrap();
+        load(&mut machine, waiter, &["SYSCALL 3", "POP R0", "HLT"]);
+        let other = machine.spawn().unwrap();
+        load(&mut machine, other, &["YLD", "MOV R0, 1", "HLT"]);
+
+        assert!(machine.schedule().unwrap());
+        assert_eq!(machine.fiber(waiter).unwrap().state(), FiberState::WAITING);
+        // the other fiber keeps running meanwhile
+        assert!(machine.schedule().unwrap());
+        assert_eq!(machine.fiber(other).unwrap().state(), FiberState::HALTED);
+        assert_eq!(machine.fiber(waiter).unwrap().state(), FiberState::WAITING);
+
+        let pending = parked.lock().unwrap().take().unwrap();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            pending.complete(Ok(vec![Value::Int(7)]));
+        });
+        machine.execute().unwrap();
+        assert_eq!(machine.fiber(waiter).unwrap().registers().get(Reg::R0), 7);
+    }
+
+    #[test]
+    fn dropped_pending() {
+        let mut machine = machine();
+        machine.register_native(3, Signature::new(&[], &[]), |call| {
+            drop(call.defer());
+            Ok(Outcome::Deferred)
+        });
+        machine.register_native(4, Signature::new(&[], &[]), |_| Ok(Outcome::Deferred));
+        let f1 = machine.spawn().unwrap();
+        load(&mut machine, f1, &["SYSCALL 3", "HLT"]);
+        let f2 = machine.spawn().unwrap();
+        load(&mut machine, f2, &["SYSCALL 4", "HLT"]);
+        let first = machine.execute().unwrap_err();
+        let rest = machine.take_faults();
+        assert_eq!(rest.len(), 1);
+        for fault in [&first, &rest[0]] {
+            assert!(matches!(fault.error, MachineError::InvalidSyscall(_)));
+        }
+        let mut fibers = vec![first.fiber.unwrap(), rest[0].fiber.unwrap()];
+        fibers.sort();
+        let mut expected = vec![f1, f2];
+        expected.sort();
+        assert_eq!(fibers, expected);
+    }
+}
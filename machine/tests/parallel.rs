@@ -0,0 +1,112 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{execptions::MachineError, fiber::fiber::Reg, machine::{config::MachineConfig, device::OutputBuffer, machine::Machine}, opcode::assembler::assemble};
+
+    fn machine(workers: usize) -> Machine {
+        Machine::with_config(MachineConfig {
+            memory_size: 256 * 1024,
+            max_memory_size: 16 * 1024 * 1024,
+            keep_halted: true,
+            workers,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[String]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    /// Spawns `workers` children that each print and double a number, then
+    /// adds up the answers in R0.
+    fn fan_out(workers: u64) -> Vec<String> {
+        let entry = 30 * workers + 15;
+        let mut lines = Vec::new();
+        for i in 1..=workers {
+            lines.extend([format!("SPAWN {:#x}", entry), format!("PUSH {}", i), "SWP".to_string(), "SEND".to_string()]);
+        }
+        lines.push("PUSH 0".to_string());
+        for _ in 0..workers {
+            lines.extend(["RECV", "DROP", "ADD"].map(String::from));
+        }
+        lines.extend(["POP R0", "HLT"].map(String::from));
+        lines.extend(["RECV", "SWP", "DUP", "OUT 1", "DUP", "ADD", "SWP", "SEND", "HLT"].map(String::from));
+        lines
+    }
+
+    /// R0 of every fiber the host spawned, in spawn order, and what went to stdout
+    fn run(workers: usize, program: impl Fn(u64) -> Vec<String>, fibers: u64) -> (Vec<u64>, Vec<u8>) {
+        let mut machine = machine(workers);
+        let stdout = OutputBuffer::new();
+        machine.set_stdout(stdout.clone());
+        let fids: Vec<u64> = (0..fibers).map(|n| {
+            let fid = machine.spawn().unwrap();
+            load(&mut machine, fid, &program(n));
+            fid
+        }).collect();
+        machine.execute().unwrap();
+        let results = fids.iter().map(|fid| machine.fiber(*fid).unwrap().registers().get(Reg::R0)).collect();
+        (results, stdout.bytes())
+    }
+
+    #[test]
+    fn messages_across_partitions() {
+        let serial = run(1, |n| fan_out(n % 7 + 1), 24);
+        for workers in [2, 4] {
+            assert_eq!(run(workers, |n| fan_out(n % 7 + 1), 24), serial);
+        }
+        let (results, _) = serial;
+        assert_eq!(results[6], 7 * 8);
+    }
+
+    #[test]
+    fn uneven_work() {
+        // counts up to a different number in each fiber, keeping every step on the stack
+        let count = |n: u64| {
+            ["PUSH 0", "DUP", "PUSH 1", "ADD", "DUP", "YLD", &format!("PUSH {}", n * 37 + 5), "SUB", "DROP", "JNZ #a", "POP R0", "HLT"]
+                .map(String::from).to_vec()
+        };
+        let serial = run(1, count, 32);
+        assert_eq!(serial.0[3], 3 * 37 + 5);
+        assert_eq!(run(3, count, 32), serial);
+    }
+
+    #[test]
+    fn fibers_are_spread() {
+        let mut machine = machine(2);
+        // four partitions per worker share the configured size
+        assert_eq!(machine.memory().size(), 256 * 1024 / 8);
+        let fids: Vec<u64> = (0..8).map(|_| machine.spawn().unwrap()).collect();
+        let memories: Vec<_> = fids.iter().map(|fid| machine.memory_of(*fid).unwrap() as *const _).collect();
+        assert!(memories.iter().any(|memory| *memory != memories[0]));
+        for fid in &fids {
+            load(&mut machine, *fid, &["PUSH 1".to_string(), "POP R0".to_string(), "HLT".to_string()]);
+        }
+        machine.execute().unwrap();
+        assert!(fids.iter().all(|fid| machine.fiber(*fid).unwrap().registers().get(Reg::R0) == 1));
+    }
+
+    #[test]
+    fn partitions_cap_fibers() {
+        // counts down on the stack and leaves every step there
+        let deep = |slots: u64| [format!("PUSH {}", slots).as_str(), "DUP", "PUSH 1", "SWP", "SUB", "JNZ #a", "HLT"].map(String::from);
+        // a quarter megabyte of stack fits in any sixteenth of 16 MiB
+        for workers in [1, 4] {
+            let mut machine = machine(workers);
+            let fid = machine.spawn().unwrap();
+            load(&mut machine, fid, &deep(20_000));
+            machine.execute().unwrap();
+        }
+        // the full megabyte needs room for the old stack while it grows
+        let mut machine = machine(1);
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &deep(100_000));
+        machine.execute().unwrap();
+        let mut machine = self::machine(4);
+        let fid = machine.spawn().unwrap();
+        load(&mut machine, fid, &deep(100_000));
+        assert!(matches!(machine.execute().unwrap_err().error, MachineError::InsufficientMemory(_)));
+    }
+}
@@ -0,0 +1,129 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{fiber::fiber::{Fiber, RegisterSnapshot}, memory::memory::Memory, opcode::{opcodes::Opcodes, peephole::Fused}};
+    use proptest::prelude::*;
+
+    /// appends instructions and keeps track of the next address
+    struct Asm<'a> {
+        mem: &'a mut Memory,
+        f: &'a Fiber,
+        at: u64,
+    }
+
+    impl Asm<'_> {
+        fn op(&mut self, opcode: Opcodes) {
+            self.f.text_section().append_data(self.mem, opcode as u16).unwrap();
+            self.at += 2;
+        }
+
+        fn imm(&mut self, opcode: Opcodes, imm: u64) {
+            self.op(opcode);
+            self.f.text_section().append_data(self.mem, imm).unwrap();
+            self.at += 8;
+        }
+
+        fn reg(&mut self, opcode: Opcodes, reg: u8) {
+            self.op(opcode);
+            self.f.text_section().append_data(self.mem, reg).unwrap();
+            self.at += 1;
+        }
+    }
+
+    type Snippet = (u8, u64, u64, u8);
+
+    fn assemble(mem: &mut Memory, f: &Fiber, snippets: &[Snippet]) {
+        let mut asm = Asm { mem, f, at: 0 };
+        for (kind, a, b, reg) in snippets {
+            match kind {
+                0 => { asm.imm(Opcodes::PUSH, *a); asm.imm(Opcodes::PUSH, *b); asm.op(Opcodes::ADD); },
+                1 => { asm.imm(Opcodes::PUSH, *a); asm.imm(Opcodes::PUSH, *b); asm.op(Opcodes::SUB); },
+                2 => { asm.op(Opcodes::DUP); let next = asm.at + 10; asm.imm(Opcodes::JZ, next); },
+                3 => { asm.reg(Opcodes::POP, *reg); asm.imm(Opcodes::PUSH, *a); },
+                4 => { asm.op(Opcodes::SWP); asm.op(Opcodes::SWP); },
+                5 => { asm.imm(Opcodes::PUSH, *a); asm.op(Opcodes::DROP); },
+                6 => asm.op(Opcodes::ADD),
+                7 => asm.reg(Opcodes::POP, *reg),
+                8 => asm.imm(Opcodes::PUSH, *b),
+                // jumps over the first PUSH, into what would otherwise be fused
+                _ => {
+                    let target = asm.at + 20;
+                    asm.imm(Opcodes::JMP, target);
+                    asm.imm(Opcodes::PUSH, *a);
+                    asm.imm(Opcodes::PUSH, *b);
+                    asm.op(Opcodes::ADD);
+                },
+            }
+        }
+        asm.op(Opcodes::HLT);
+    }
+
+    /// final error, registers, flags and stack contents
+    fn run(snippets: &[Snippet], optimize: bool, typed: bool) -> (Option<String>, RegisterSnapshot, Vec<u64>) {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.set_optimize(optimize);
+        if typed {
+            f.enable_typed_stack();
+        }
+        assemble(&mut mem, &f, snippets);
+        let error = f.execute(&mut mem).err().map(|fault| format!("{:?} at {:?}", fault.error, fault.pc));
+        let snapshot = f.registers().snapshot();
+        let mut stack = Vec::new();
+        while let Ok(val) = f.pop(&mut mem) {
+            stack.push(val);
+        }
+        (error, snapshot, stack)
+    }
+
+    #[test]
+    fn fuses_runs() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.set_optimize(true);
+        assemble(&mut mem, &f, &[(8, 0, 7, 0), (0, 1, 2, 0), (4, 0, 0, 0), (3, 5, 0, 1), (5, 0, 0, 0), (2, 0, 0, 0), (1, 1, 1, 0)]);
+        f.execute(&mut mem).unwrap();
+        let fused: Vec<Fused> = f.decode_cache().unwrap().fused().copied().collect();
+        assert_eq!(fused.len(), 6);
+        assert!(matches!(fused[0], Fused::PushPushAdd { a: 1, b: 2, next: 32 }));
+        assert!(matches!(fused[4], Fused::DupJz { .. }));
+    }
+
+    #[test]
+    fn no_fusion_into_jump_target() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.set_optimize(true);
+        assemble(&mut mem, &f, &[(8, 0, 3, 0), (9, 1, 2, 0)]);
+        f.execute(&mut mem).unwrap();
+        assert_eq!(f.decode_cache().unwrap().fused().count(), 0);
+    }
+
+    #[test]
+    fn rejected_code_is_not_fused() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.set_optimize(true);
+        // guaranteed underflow at the end
+        assemble(&mut mem, &f, &[(0, 1, 2, 0), (6, 0, 0, 0)]);
+        assert!(f.execute(&mut mem).is_err());
+        assert_eq!(f.decode_cache().unwrap().fused().count(), 0);
+    }
+
+    fn snippet() -> impl Strategy<Value = Snippet> {
+        (0u8..10, prop_oneof![0u64..3, any::<u64>()], prop_oneof![0u64..3, any::<u64>()], 0u8..8)
+    }
+
+    proptest! {
+        #[test]
+        fn same_state_with_and_without_optimizer(body in prop::collection::vec(snippet(), 0..40), typed: bool) {
+            // a few slots up front so most programs get past the verifier
+            let mut snippets = vec![(8, 0, 1, 0); 4];
+            snippets.extend(body);
+            prop_assert_eq!(run(&snippets, true, typed), run(&snippets, false, typed));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{machine::machine::Machine, opcode::opcodes::Opcodes};
+
+    /// PUSH n, loop: PUSH 1, SWP, SUB, INC R0, JNZ loop, HLT
+    fn countdown(n: u64) -> Vec<u64> {
+        vec![
+            1, Opcodes::PUSH as u64, 3, n,
+            1, Opcodes::PUSH as u64, 3, 1,
+            1, Opcodes::SWP as u64,
+            1, Opcodes::SUB as u64,
+            1, Opcodes::INC as u64, 0, 0,
+            1, Opcodes::JNZ as u64, 3, 10,
+            1, Opcodes::HLT as u64,
+        ]
+    }
+
+    #[test]
+    fn counts_hot_loop() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        machine.set_profiling(true);
+        let f1 = machine.spawn().unwrap();
+        let f2 = machine.spawn().unwrap();
+        machine.write_bytecodes(f1, &countdown(10)).unwrap();
+        machine.write_bytecodes(f2, &countdown(5)).unwrap();
+        machine.execute().unwrap();
+
+        let profile = machine.profile();
+        let p1 = profile.fiber(f1).unwrap();
+        assert_eq!(p1.addresses[&0].count, 1);
+        assert_eq!(p1.addresses[&10].count, 10);
+        assert_eq!(p1.addresses[&22].instruction.to_string(), "SUB");
+        assert_eq!(p1.instructions(), 2 + 10 * 5);
+        assert_eq!(p1.slices.count, 1);
+
+        let total = profile.aggregate();
+        assert_eq!(total.addresses[&10].count, 15);
+        assert_eq!(total.opcodes[&Opcodes::PUSH], 2 + 15);
+        assert_eq!(total.slices.count, 2);
+
+        let report = profile.report(3);
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines[1].contains("#a") && lines[1].contains("PUSH 1"));
+        assert!(report.contains("slices: 2"));
+    }
+
+    #[test]
+    fn toggled_between_rounds() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        // PUSH 1, YLD, PUSH 2, HLT
+        let fid = machine.spawn().unwrap();
+        machine.write_bytecodes(fid, &[1, Opcodes::PUSH as u64, 3, 1, 1, Opcodes::YLD as u64, 1, Opcodes::PUSH as u64, 3, 2, 1, Opcodes::HLT as u64]).unwrap();
+        assert!(machine.schedule().unwrap());
+        assert!(machine.profile().fiber(fid).is_none());
+
+        machine.set_profiling(true);
+        assert!(!machine.schedule().unwrap());
+        let profile = machine.take_profile();
+        let counts = profile.fiber(fid).unwrap();
+        assert_eq!(counts.instructions(), 2);
+        assert!(!counts.addresses.contains_key(&0));
+        assert_eq!(counts.addresses[&12].instruction.to_string(), "PUSH 2");
+        assert!(machine.profile().fiber(fid).is_none());
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        machine.set_profiling(true);
+        let fid = machine.spawn().unwrap();
+        machine.write_bytecodes(fid, &countdown(3)).unwrap();
+        machine.execute().unwrap();
+
+        let folded = machine.profile().folded();
+        assert_eq!(folded.lines().count(), 7);
+        assert!(folded.lines().any(|line| line == format!("fiber {:x};#a PUSH 1 3", fid)));
+    }
+}
@@ -108,4 +108,20 @@ pub mod tests {
         f.push(&mut mem, 1).unwrap();
         f.swap(&mut mem).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn grow() {
+        let mut mem = Memory::new(8 * 1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        let other = Fiber::new(&mut mem, &mut rng).unwrap();
+        for i in 0..10000 {
+            f.push(&mut mem, i).unwrap();
+        }
+        for i in (0..10000).rev() {
+            assert_eq!(f.pop(&mut mem).unwrap(), i);
+        }
+        other.kill(&mut mem).unwrap();
+        f.kill(&mut mem).unwrap();
+    }
+}
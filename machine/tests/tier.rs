@@ -0,0 +1,125 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{execptions::MachineError, fiber::fiber::{Fiber, FiberState, Reg, RegisterSnapshot}, memory::memory::Memory, opcode::opcodes::Opcodes};
+
+    fn op(mem: &mut Memory, f: &Fiber, opcode: Opcodes) {
+        f.text_section().append_data(mem, opcode as u16).unwrap();
+    }
+
+    fn op_imm(mem: &mut Memory, f: &Fiber, opcode: Opcodes, imm: u64) {
+        op(mem, f, opcode);
+        f.text_section().append_data(mem, imm).unwrap();
+    }
+
+    fn op_reg(mem: &mut Memory, f: &Fiber, opcode: Opcodes, reg: u8) {
+        op(mem, f, opcode);
+        f.text_section().append_data(mem, reg).unwrap();
+    }
+
+    /// PUSH n, loop: PUSH 1, SWP, SUB, INC R0, JNZ loop, HLT
+    fn countdown(mem: &mut Memory, f: &Fiber, n: u64) {
+        op_imm(mem, f, Opcodes::PUSH, n);
+        op_imm(mem, f, Opcodes::PUSH, 1);
+        op(mem, f, Opcodes::SWP);
+        op(mem, f, Opcodes::SUB);
+        op_reg(mem, f, Opcodes::INC, 0);
+        op_imm(mem, f, Opcodes::JNZ, 10);
+        op(mem, f, Opcodes::HLT);
+    }
+
+    fn run(threshold: Option<u32>, program: fn(&mut Memory, &Fiber)) -> (Fiber, Memory, Result<(), MachineError>) {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.set_tier_up_threshold(threshold);
+        program(&mut mem, &f);
+        let res = f.execute(&mut mem).map_err(|fault| fault.error);
+        (f, mem, res)
+    }
+
+    fn snapshot(f: &Fiber) -> RegisterSnapshot {
+        f.registers().snapshot()
+    }
+
+    #[test]
+    fn compiles_hot_loop() {
+        let (f, mem, res) = run(Some(10), |mem, f| countdown(mem, f, 100));
+        res.unwrap();
+        assert!(f.tier().unwrap().is_compiled(10));
+        assert!(!f.tier().unwrap().is_compiled(0));
+        assert_eq!(f.get_register(&mem, Reg::R0).unwrap(), 100);
+
+        let (interpreted, _, _) = run(None, |mem, f| countdown(mem, f, 100));
+        assert!(interpreted.tier().is_none());
+        assert_eq!(snapshot(&f), snapshot(&interpreted));
+    }
+
+    #[test]
+    fn fault_inside_block() {
+        // loop: PUSH 1, ADD, JMP loop; eventually there's nothing left to add to
+        fn program(mem: &mut Memory, f: &Fiber) {
+            op_imm(mem, f, Opcodes::PUSH, 1);
+            op_imm(mem, f, Opcodes::PUSH, 1);
+            op(mem, f, Opcodes::ADD);
+            op(mem, f, Opcodes::DROP);
+            op(mem, f, Opcodes::DROP);
+            op_imm(mem, f, Opcodes::JMP, 0);
+        }
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.set_tier_up_threshold(Some(0));
+        program(&mut mem, &f);
+        let fault = f.execute(&mut mem).unwrap_err();
+        assert!(f.tier().unwrap().is_compiled(0));
+        assert!(matches!(fault.error, MachineError::StackUnderflow));
+        assert_eq!(fault.pc, Some(24));
+        assert_eq!(fault.instruction.unwrap().opcode, Opcodes::DROP);
+        assert_eq!(f.get_register(&mem, Reg::PC).unwrap(), 26);
+        assert_eq!(f.state(), FiberState::HALTED);
+    }
+
+    #[test]
+    fn caught_inside_block() {
+        // TRY handler, loop: DROP, JMP loop, handler: POP R1, HLT
+        fn program(mem: &mut Memory, f: &Fiber) {
+            op_imm(mem, f, Opcodes::PUSH, 1);
+            op_imm(mem, f, Opcodes::PUSH, 1);
+            op_imm(mem, f, Opcodes::TRY, 42);
+            op(mem, f, Opcodes::DROP);
+            op_imm(mem, f, Opcodes::JMP, 30);
+            op_reg(mem, f, Opcodes::POP, 1);
+            op(mem, f, Opcodes::HLT);
+        }
+        let (tiered, _, res) = run(Some(0), program);
+        res.unwrap();
+        let (interpreted, _, _) = run(None, program);
+        assert_eq!(snapshot(&tiered), snapshot(&interpreted));
+        assert_eq!(snapshot(&tiered).get(Reg::R1), MachineError::StackUnderflow.code());
+    }
+
+    #[test]
+    fn rewriting_drops_blocks() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.set_tier_up_threshold(Some(0));
+        // loop: INC R0, YLD, JMP loop
+        op_reg(&mut mem, &f, Opcodes::INC, 0);
+        op(&mut mem, &f, Opcodes::YLD);
+        op_imm(&mut mem, &f, Opcodes::JMP, 0);
+        for _ in 0..3 {
+            f.execute(&mut mem).unwrap();
+            assert_eq!(f.state(), FiberState::BLOCKED);
+        }
+        assert!(f.tier().unwrap().is_compiled(0));
+        assert!(f.tier().unwrap().is_compiled(5));
+        assert_eq!(f.get_register(&mem, Reg::R0).unwrap(), 3);
+
+        // blocks are dropped and compiled again from the new text
+        op(&mut mem, &f, Opcodes::HLT);
+        f.execute(&mut mem).unwrap();
+        assert_eq!(f.tier().unwrap().len(), 2);
+        assert_eq!(f.get_register(&mem, Reg::R0).unwrap(), 4);
+    }
+}
@@ -0,0 +1,101 @@
+#[cfg(test)]
+pub mod tests {
+    use std::{io::Write, sync::{Arc, Mutex}};
+
+    use machine::{fiber::fiber::FiberState, machine::machine::Machine, opcode::opcodes::Opcodes, trace::{event::{Event, EventKind, Filter}, json::JsonLines, ring::RingBuffer}};
+
+    // PUSH 65, POP R0, HLT
+    fn program() -> Vec<u64> {
+        vec![1, Opcodes::PUSH as u64, 3, 65, 1, Opcodes::POP as u64, 0, 0, 1, Opcodes::HLT as u64]
+    }
+
+    #[test]
+    fn lifecycle() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        let ring = RingBuffer::new(1024, Filter::all());
+        machine.set_tracer(Some(Box::new(ring.clone())));
+        let fid = machine.spawn().unwrap();
+        machine.write_bytecodes(fid, &program()).unwrap();
+        machine.execute().unwrap();
+
+        let events = ring.events();
+        assert!(events.iter().all(|e| e.fiber == Some(fid)));
+        let kinds: Vec<EventKind> = events.iter().map(|e| e.event.kind()).collect();
+        // stack and both sections
+        assert_eq!(kinds[..4], [EventKind::Allocate, EventKind::Allocate, EventKind::Allocate, EventKind::Spawn]);
+        assert_eq!(kinds[4..8], [EventKind::Execute, EventKind::Execute, EventKind::Execute, EventKind::State]);
+        assert_eq!(kinds[8], EventKind::Kill);
+        assert_eq!(kinds[9..].iter().filter(|k| **k == EventKind::Deallocate).count(), 3);
+
+        assert_eq!(events[4].pc, Some(0));
+        assert_eq!(events[5].pc, Some(10));
+        assert_eq!(events[5].instruction.unwrap().to_string(), "POP R0");
+        assert_eq!(events[7].event, Event::State { from: FiberState::RUNNING, to: FiberState::HALTED });
+    }
+
+    #[test]
+    fn filtered() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        let f1 = machine.spawn().unwrap();
+        let f2 = machine.spawn().unwrap();
+        machine.write_bytecodes(f1, &program()).unwrap();
+        machine.write_bytecodes(f2, &program()).unwrap();
+        let ring = RingBuffer::new(2, Filter::new(&[EventKind::Execute], Some(&[f2])));
+        machine.set_tracer(Some(Box::new(ring.clone())));
+        machine.execute().unwrap();
+
+        // only the last two instructions of f2 fit
+        let events = ring.events();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e.fiber == Some(f2) && e.event == Event::Execute));
+        assert_eq!(events[1].instruction.unwrap().opcode, Opcodes::HLT);
+    }
+
+    #[test]
+    fn faults_and_yields() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        let ring = RingBuffer::new(64, Filter::new(&[EventKind::State, EventKind::Fault], None));
+        machine.set_tracer(Some(Box::new(ring.clone())));
+        // YLD, ADD
+        let fid = machine.spawn().unwrap();
+        machine.write_bytecodes(fid, &[1, Opcodes::YLD as u64, 1, Opcodes::ADD as u64]).unwrap();
+        machine.execute().unwrap_err();
+
+        let events = ring.events();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].event, Event::State { from: FiberState::RUNNING, to: FiberState::BLOCKED });
+        assert_eq!(events[1].event, Event::State { from: FiberState::BLOCKED, to: FiberState::RUNNING });
+        assert_eq!(events[2].event, Event::State { from: FiberState::RUNNING, to: FiberState::HALTED });
+        assert_eq!(events[3].event, Event::Fault { error: "stack underflow".to_string() });
+        assert_eq!(events[3].pc, Some(2));
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut machine = Machine::new(16 * 1024 * 1024).unwrap();
+        let out = Shared::default();
+        let fid = machine.spawn().unwrap();
+        machine.write_bytecodes(fid, &program()).unwrap();
+        machine.set_tracer(Some(Box::new(JsonLines::new(out.clone(), Filter::new(&[EventKind::Execute, EventKind::Kill], None)))));
+        machine.execute().unwrap();
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], format!("{{\"event\":\"execute\",\"fiber\":{},\"pc\":0,\"instruction\":\"PUSH 65\"}}", fid));
+        assert_eq!(lines[3], format!("{{\"event\":\"kill\",\"fiber\":{}}}", fid));
+    }
+}
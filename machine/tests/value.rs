@@ -0,0 +1,129 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{execptions::MachineError, fiber::{actor::ActorId, fiber::{Fiber, Reg}, value::{Tag, Value}}, memory::memory::Memory, opcode::commands};
+
+    fn typed_fiber(mem: &mut Memory) -> Fiber {
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(mem, &mut rng).unwrap();
+        f.enable_typed_stack();
+        f
+    }
+
+    #[test]
+    fn host_values() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = typed_fiber(&mut mem);
+        let values = vec![
+            Value::Int(-5),
+            Value::Float(1.5),
+            Value::Bool(true),
+            Value::Str("hello".to_string()),
+            Value::Bytes(vec![1, 2, 3]),
+            Value::Actor(ActorId::new(0, 42)),
+            Value::Null,
+        ];
+        for value in values.clone() {
+            f.push_value(&mut mem, value).unwrap();
+        }
+        for value in values.into_iter().rev() {
+            assert_eq!(f.pop_value(&mut mem).unwrap(), value);
+        }
+        f.kill(&mut mem).unwrap();
+    }
+
+    #[test]
+    fn tags_follow_stack_ops() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = typed_fiber(&mut mem);
+        commands::pusht(&mut mem, &mut f, Tag::Bool, 1).unwrap();
+        commands::push(&mut mem, &mut f, 7).unwrap();
+        commands::swap(&mut mem, &mut f).unwrap();
+        commands::dup(&mut mem, &mut f).unwrap();
+        assert_eq!(f.pop_tagged(&mut mem).unwrap(), (Tag::Bool, 1));
+        assert_eq!(f.pop_tagged(&mut mem).unwrap(), (Tag::Bool, 1));
+        assert_eq!(f.pop_tagged(&mut mem).unwrap(), (Tag::Int, 7));
+    }
+
+    #[test]
+    fn bool_logic() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = typed_fiber(&mut mem);
+        commands::pusht(&mut mem, &mut f, Tag::Bool, 1).unwrap();
+        commands::not(&mut mem, &mut f).unwrap();
+        commands::pusht(&mut mem, &mut f, Tag::Bool, 1).unwrap();
+        commands::or(&mut mem, &mut f).unwrap();
+        assert_eq!(f.pop_value(&mut mem).unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = typed_fiber(&mut mem);
+        commands::pusht(&mut mem, &mut f, Tag::Float, 1.5f64.to_bits()).unwrap();
+        commands::push(&mut mem, &mut f, 1).unwrap();
+        assert!(matches!(commands::add(&mut mem, &mut f), Err(MachineError::TypeMismatch(_))));
+
+        commands::pusht(&mut mem, &mut f, Tag::Bool, 1).unwrap();
+        commands::push(&mut mem, &mut f, 1).unwrap();
+        assert!(matches!(commands::xor(&mut mem, &mut f), Err(MachineError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn untyped_ignores_tags() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        commands::pusht(&mut mem, &mut f, Tag::Float, 3).unwrap();
+        commands::push(&mut mem, &mut f, 4).unwrap();
+        commands::add(&mut mem, &mut f).unwrap();
+        assert_eq!(f.pop(&mut mem).unwrap(), 7);
+    }
+
+    #[test]
+    fn untyped_pops_any_tag() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut rng = Box::new(rand::rng());
+        let mut f = Fiber::new(&mut mem, &mut rng).unwrap();
+        f.push(&mut mem, 1.5f64.to_bits()).unwrap();
+        assert_eq!(f.pop_typed(&mut mem, Tag::Float).unwrap(), 1.5f64.to_bits());
+
+        let mut f = typed_fiber(&mut mem);
+        f.push(&mut mem, 1).unwrap();
+        assert!(matches!(f.pop_typed(&mut mem, Tag::Float), Err(MachineError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn references_are_not_forgeable() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = typed_fiber(&mut mem);
+        assert!(matches!(commands::pusht(&mut mem, &mut f, Tag::Ref, 0x100), Err(MachineError::TypeMismatch(_))));
+        assert!(matches!(commands::pusht(&mut mem, &mut f, Tag::Actor, 1), Err(MachineError::TypeMismatch(_))));
+
+        // arithmetic on a reference is refused and never yields one
+        f.push_value(&mut mem, Value::Ref(0x100)).unwrap();
+        commands::push(&mut mem, &mut f, 0).unwrap();
+        assert!(matches!(commands::add(&mut mem, &mut f), Err(MachineError::TypeMismatch(_))));
+        f.push_value(&mut mem, Value::Ref(0x100)).unwrap();
+        assert!(matches!(commands::not(&mut mem, &mut f), Err(MachineError::TypeMismatch(_))));
+
+        // nor can the stack pointer be moved over stale slots
+        assert!(matches!(commands::mov(&mut mem, &f, Reg::SP, 64), Err(MachineError::TypeMismatch(_))));
+        assert!(matches!(commands::inc(&mut mem, &f, Reg::SP), Err(MachineError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn typed_bytecode() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let mut f = typed_fiber(&mut mem);
+        // PUSHT Bool 1, PUSH 2, ADD, HLT
+        f.text_section().append_data(&mut mem, 0x001cu16).unwrap();
+        f.text_section().append_data(&mut mem, Tag::Bool as u8).unwrap();
+        f.text_section().append_data(&mut mem, 1u64).unwrap();
+        f.text_section().append_data(&mut mem, 0x0001u16).unwrap();
+        f.text_section().append_data(&mut mem, 2u64).unwrap();
+        f.text_section().append_data(&mut mem, 0x0004u16).unwrap();
+        f.text_section().append_data(&mut mem, 0x001au16).unwrap();
+        let fault = f.execute(&mut mem).unwrap_err();
+        assert!(matches!(fault.error, MachineError::TypeMismatch(_)));
+    }
+}
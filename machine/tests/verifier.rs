@@ -0,0 +1,155 @@
+#[cfg(test)]
+pub mod tests {
+    use machine::{execptions::MachineError, fiber::fiber::Fiber, machine::{config::MachineConfig, machine::Machine}, memory::memory::Memory, opcode::{opcodes::Opcodes, verifier::verify}};
+
+    fn fiber(mem: &mut Memory) -> Fiber {
+        let mut rng = Box::new(rand::rng());
+        Fiber::new(mem, &mut rng).unwrap()
+    }
+
+    fn op(mem: &mut Memory, f: &Fiber, opcode: Opcodes) {
+        f.text_section().append_data(mem, opcode as u16).unwrap();
+    }
+
+    fn op_imm(mem: &mut Memory, f: &Fiber, opcode: Opcodes, imm: u64) {
+        op(mem, f, opcode);
+        f.text_section().append_data(mem, imm).unwrap();
+    }
+
+    fn op_reg(mem: &mut Memory, f: &Fiber, opcode: Opcodes, reg: u8) {
+        op(mem, f, opcode);
+        f.text_section().append_data(mem, reg).unwrap();
+    }
+
+    fn rejected(mem: &Memory, f: &Fiber) -> String {
+        match verify(mem, f.text_section()) {
+            Err(MachineError::InvalidBytecode(Some(detail))) => detail,
+            other => panic!("not rejected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn straight_line() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::PUSH, 10);
+        op_imm(&mut mem, &f, Opcodes::PUSH, 20);
+        op(&mut mem, &f, Opcodes::ADD);
+        op_reg(&mut mem, &f, Opcodes::POP, 0);
+        op(&mut mem, &f, Opcodes::HLT);
+        let v = verify(&mem, f.text_section()).unwrap();
+        assert_eq!(v.depths.len(), 5);
+        assert_eq!(v.depths[&20], Some(2));
+        assert_eq!(v.depths[&25], Some(0));
+        assert_eq!(v.max_depth(), Some(2));
+        assert!(v.targets.is_empty());
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let f = fiber(&mut mem);
+        f.text_section().append_data(&mut mem, 0xffffu16).unwrap();
+        assert!(rejected(&mem, &f).contains("invalid opcode"));
+    }
+
+    #[test]
+    fn invalid_register() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let f = fiber(&mut mem);
+        op_reg(&mut mem, &f, Opcodes::INC, 9);
+        assert!(rejected(&mem, &f).contains("invalid register"));
+    }
+
+    #[test]
+    fn jump_into_operand() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let f = fiber(&mut mem);
+        // the immediate decodes as HLT when entered at #2
+        op_imm(&mut mem, &f, Opcodes::PUSH, Opcodes::HLT as u64);
+        op_imm(&mut mem, &f, Opcodes::JMP, 2);
+        assert!(rejected(&mem, &f).contains("inside another instruction"));
+    }
+
+    #[test]
+    fn jump_outside() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::JMP, 0x100);
+        assert!(rejected(&mem, &f).contains("outside of the section"));
+
+        let f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::PUSH, 1);
+        assert!(rejected(&mem, &f).contains("outside of the section"));
+    }
+
+    #[test]
+    fn underflow() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        let f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::PUSH, 1);
+        op(&mut mem, &f, Opcodes::ADD);
+        op(&mut mem, &f, Opcodes::HLT);
+        assert!(rejected(&mem, &f).starts_with("#a ADD: stack underflow"));
+
+        // the same underflow inside a TRY block is caught
+        let f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::TRY, 24);
+        op_imm(&mut mem, &f, Opcodes::PUSH, 1);
+        op(&mut mem, &f, Opcodes::ADD);
+        op(&mut mem, &f, Opcodes::HLT);
+        op_reg(&mut mem, &f, Opcodes::POP, 0);
+        op(&mut mem, &f, Opcodes::HLT);
+        let v = verify(&mem, f.text_section()).unwrap();
+        assert_eq!(v.depths[&24], Some(1));
+        assert!(v.targets.contains(&24));
+    }
+
+    #[test]
+    fn loops() {
+        let mut mem = Memory::new(1024 * 1024).unwrap();
+        // balanced loop keeps its depth
+        let f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::PUSH, 10);
+        op(&mut mem, &f, Opcodes::DUP);
+        op(&mut mem, &f, Opcodes::DROP);
+        op_reg(&mut mem, &f, Opcodes::DEC, 0);
+        op_imm(&mut mem, &f, Opcodes::JNZ, 10);
+        op(&mut mem, &f, Opcodes::HLT);
+        let v = verify(&mem, f.text_section()).unwrap();
+        assert_eq!(v.depths[&10], Some(1));
+        assert_eq!(v.max_depth(), Some(2));
+
+        // a growing loop makes the depth unknown, no underflow is claimed
+        let f = fiber(&mut mem);
+        op_imm(&mut mem, &f, Opcodes::PUSH, 1);
+        op_imm(&mut mem, &f, Opcodes::JNZ, 0);
+        op(&mut mem, &f, Opcodes::ADD);
+        op(&mut mem, &f, Opcodes::HLT);
+        let v = verify(&mem, f.text_section()).unwrap();
+        assert_eq!(v.depths[&0], None);
+        assert_eq!(v.max_depth(), None);
+    }
+
+    #[test]
+    fn machine_refuses_unverified() {
+        let mut machine = Machine::with_config(MachineConfig { verify: true, ..Default::default() }).unwrap();
+        // POP R0
+        let bad = machine.spawn().unwrap();
+        machine.write_bytecodes(bad, &[1, Opcodes::POP as u64, 0, 0]).unwrap();
+        // PUSH 1, POP R0, HLT
+        let good = machine.spawn().unwrap();
+        machine.write_bytecodes(good, &[1, Opcodes::PUSH as u64, 3, 1, 1, Opcodes::POP as u64, 0, 0, 1, Opcodes::HLT as u64]).unwrap();
+        machine.verify(good).unwrap();
+        assert!(machine.fiber(good).unwrap().is_verified());
+        // appending invalidates it
+        machine.write_bytecodes(good, &[1, Opcodes::HLT as u64]).unwrap();
+        assert!(!machine.fiber(good).unwrap().is_verified());
+
+        let fault = machine.execute().unwrap_err();
+        assert_eq!(fault.fiber, Some(bad));
+        assert!(matches!(fault.error, MachineError::InvalidBytecode(_)));
+        assert_eq!(fault.snapshot.unwrap().get(machine::fiber::fiber::Reg::SP), 0);
+        assert!(machine.take_faults().is_empty());
+    }
+}
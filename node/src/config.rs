@@ -0,0 +1,30 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::membership::{Clock, SystemClock};
+
+#[derive(Clone)]
+pub struct NodeConfig {
+    /// how often every peer gets a heartbeat
+    pub heartbeat: Duration,
+    /// silence after which a member is suspected
+    pub suspect_after: Duration,
+    /// silence after which a member is taken as gone
+    pub down_after: Duration,
+    /// what heartbeats and silences are measured with
+    pub clock: Arc<dyn Clock>,
+    /// how long a frame may wait on a peer that stopped reading before its
+    /// connection is given up
+    pub write_timeout: Duration,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat: Duration::from_secs(1),
+            suspect_after: Duration::from_secs(3),
+            down_after: Duration::from_secs(10),
+            clock: Arc::new(SystemClock::default()),
+            write_timeout: Duration::from_secs(5),
+        }
+    }
+}
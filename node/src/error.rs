@@ -0,0 +1,41 @@
+use std::{fmt, io};
+
+use machine::{execptions::MachineError, fiber::actor::NodeId};
+
+#[derive(Debug)]
+pub enum NodeError {
+    Io(io::Error),
+    /// the peer speaks another protocol or ISA version, or isn't a node at all
+    Handshake(String),
+    /// a frame that doesn't decode
+    Frame(String),
+    /// no connection to that node
+    UnknownNode(NodeId),
+    Machine(MachineError),
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "io error: {}", err),
+            Self::Handshake(detail) => write!(f, "handshake failed: {}", detail),
+            Self::Frame(detail) => write!(f, "invalid frame: {}", detail),
+            Self::UnknownNode(node) => write!(f, "not connected to node {}", node),
+            Self::Machine(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+impl From<io::Error> for NodeError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<MachineError> for NodeError {
+    fn from(err: MachineError) -> Self {
+        Self::Machine(err)
+    }
+}
@@ -0,0 +1,262 @@
+use std::{io::{Read, Write}, net::SocketAddr};
+
+use machine::{fiber::{actor::{ActorId, NodeId}, value::{Tag, Value}}, opcode::opcodes::ISA_VERSION};
+
+use crate::error::NodeError;
+
+/// first bytes of every connection, either way
+pub const MAGIC: [u8; 4] = *b"VMND";
+/// version of the framing below, bumped on any change to it
+pub const PROTOCOL_VERSION: u16 = 4;
+/// largest envelope accepted, the length prefix is checked before reading
+pub const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// What each side sends first. Everything is big-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hello {
+    pub protocol: u16,
+    pub isa: u16,
+    pub node: NodeId,
+}
+
+impl Hello {
+    pub fn new(node: NodeId) -> Self {
+        Self { protocol: PROTOCOL_VERSION, isa: ISA_VERSION, node }
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> Result<(), NodeError> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(self.protocol.to_be_bytes());
+        bytes.extend(self.isa.to_be_bytes());
+        bytes.extend(self.node.to_be_bytes());
+        out.write_all(&bytes)?;
+        Ok(out.flush()?)
+    }
+
+    pub fn read(input: &mut impl Read) -> Result<Self, NodeError> {
+        let mut bytes = [0u8; 10];
+        input.read_exact(&mut bytes)?;
+        if bytes[..4] != MAGIC {
+            return Err(NodeError::Handshake("not a node".to_string()));
+        }
+        Ok(Self {
+            protocol: u16::from_be_bytes([bytes[4], bytes[5]]),
+            isa: u16::from_be_bytes([bytes[6], bytes[7]]),
+            node: u16::from_be_bytes([bytes[8], bytes[9]]),
+        })
+    }
+
+    /// whether a node saying `self` can talk to one saying `peer`
+    pub fn check(&self, peer: &Hello) -> Result<(), NodeError> {
+        if peer.protocol != self.protocol {
+            return Err(NodeError::Handshake(format!("protocol version {}, expected {}", peer.protocol, self.protocol)));
+        }
+        if peer.isa != self.isa {
+            return Err(NodeError::Handshake(format!("ISA version {}, expected {}", peer.isa, self.isa)));
+        }
+        if peer.node == self.node {
+            return Err(NodeError::Handshake(format!("peer has our own id {}", peer.node)));
+        }
+        Ok(())
+    }
+}
+
+/// What an envelope carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// the sending fiber's id, then an encoded `Value` for the destination
+    /// fiber's mailbox
+    Message = 0x00,
+    /// a message that found no fiber, sent back to the fiber it came from:
+    /// the id it was for, the `Failure` code, then the value. Keeps the
+    /// request id of a CALLR.
+    Undeliverable = 0x01,
+    /// a message made by CALLR, laid out like `Message`
+    Request = 0x02,
+    /// a REPLY to the envelope's request, laid out like `Message`
+    Reply = 0x03,
+    /// the callee halted before replying, a string says why
+    Fault = 0x04,
+    /// a sign of life, without payload
+    Heartbeat = 0x05,
+    /// the members the source knows of, see `encode_members`
+    Members = 0x06,
+    /// the source is shutting down
+    Leave = 0x07,
+}
+
+impl Kind {
+    pub fn from_u8(val: u8) -> Result<Self, NodeError> {
+        match val {
+            0x00 => Ok(Self::Message),
+            0x01 => Ok(Self::Undeliverable),
+            0x02 => Ok(Self::Request),
+            0x03 => Ok(Self::Reply),
+            0x04 => Ok(Self::Fault),
+            0x05 => Ok(Self::Heartbeat),
+            0x06 => Ok(Self::Members),
+            0x07 => Ok(Self::Leave),
+            _ => Err(NodeError::Frame(format!("unknown message type {:#x}", val))),
+        }
+    }
+}
+
+/// One frame on the wire, after its u32 length prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// node that sent it
+    pub source: NodeId,
+    /// fiber on the receiving node it is for
+    pub fiber: u64,
+    pub kind: Kind,
+    /// ties a reply to its request, 0 when nothing is expected back
+    pub request: u64,
+    pub payload: Vec<u8>,
+}
+
+const HEADER: usize = 2 + 8 + 1 + 8;
+
+impl Envelope {
+    pub fn write(&self, out: &mut impl Write) -> Result<(), NodeError> {
+        let len = HEADER + self.payload.len();
+        if len > MAX_FRAME {
+            return Err(NodeError::Frame(format!("{} bytes, at most {} fit", len, MAX_FRAME)));
+        }
+        let mut bytes = Vec::with_capacity(4 + len);
+        bytes.extend((len as u32).to_be_bytes());
+        bytes.extend(self.source.to_be_bytes());
+        bytes.extend(self.fiber.to_be_bytes());
+        bytes.push(self.kind as u8);
+        bytes.extend(self.request.to_be_bytes());
+        bytes.extend(&self.payload);
+        out.write_all(&bytes)?;
+        Ok(out.flush()?)
+    }
+
+    pub fn read(input: &mut impl Read) -> Result<Self, NodeError> {
+        let mut prefix = [0u8; 4];
+        input.read_exact(&mut prefix)?;
+        let len = u32::from_be_bytes(prefix) as usize;
+        if !(HEADER..=MAX_FRAME).contains(&len) {
+            return Err(NodeError::Frame(format!("length {}", len)));
+        }
+        let mut bytes = vec![0u8; len];
+        input.read_exact(&mut bytes)?;
+        let mut reader = Reader { bytes: &bytes, at: 0 };
+        Ok(Self {
+            source: reader.u16()?,
+            fiber: reader.u64()?,
+            kind: Kind::from_u8(reader.u8()?)?,
+            request: reader.u64()?,
+            payload: bytes[HEADER..].to_vec(),
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], NodeError> {
+        let bytes = self.at.checked_add(len)
+            .and_then(|end| self.bytes.get(self.at..end))
+            .ok_or_else(|| NodeError::Frame(format!("ends at {}, {} more bytes expected", self.bytes.len(), len)))?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, NodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, NodeError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap_or_default()))
+    }
+
+    fn u64(&mut self) -> Result<u64, NodeError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap_or_default()))
+    }
+}
+
+/// A value as a payload: its tag, then the value. Strings and bytes are
+/// prefixed with their length, references don't leave the machine.
+pub fn encode_value(value: &Value) -> Result<Vec<u8>, NodeError> {
+    let mut bytes = vec![value.tag() as u8];
+    match value {
+        Value::Int(val) => bytes.extend(val.to_be_bytes()),
+        Value::Float(val) => bytes.extend(val.to_bits().to_be_bytes()),
+        Value::Bool(val) => bytes.push(*val as u8),
+        Value::Str(val) => {
+            bytes.extend((val.len() as u64).to_be_bytes());
+            bytes.extend(val.as_bytes());
+        },
+        Value::Bytes(val) => {
+            bytes.extend((val.len() as u64).to_be_bytes());
+            bytes.extend(val);
+        },
+        Value::Ref(_) => return Err(NodeError::Frame("references can't be sent".to_string())),
+        Value::Actor(actor) => bytes.extend(actor.to_bits().to_be_bytes()),
+        Value::Null => {},
+    }
+    Ok(bytes)
+}
+
+pub fn decode_value(bytes: &[u8]) -> Result<Value, NodeError> {
+    let mut reader = Reader { bytes, at: 0 };
+    let tag = Tag::from_u8(reader.u8()?)?;
+    let value = match tag {
+        Tag::Int => Value::Int(reader.u64()? as i64),
+        Tag::Float => Value::Float(f64::from_bits(reader.u64()?)),
+        Tag::Bool => Value::Bool(reader.u8()? != 0),
+        Tag::Str => {
+            let len = reader.u64()? as usize;
+            let text = reader.take(len)?.to_vec();
+            Value::Str(String::from_utf8(text).map_err(|err| NodeError::Frame(err.to_string()))?)
+        },
+        Tag::Bytes => {
+            let len = reader.u64()? as usize;
+            Value::Bytes(reader.take(len)?.to_vec())
+        },
+        Tag::Ref => return Err(NodeError::Frame("references can't be sent".to_string())),
+        Tag::Actor => Value::Actor(ActorId::from_bits(reader.u64()?)),
+        Tag::Null => Value::Null,
+    };
+    if reader.at != bytes.len() {
+        return Err(NodeError::Frame(format!("{} bytes left after the value", bytes.len() - reader.at)));
+    }
+    Ok(value)
+}
+
+/// A fiber id followed by whatever comes after it, see `Kind`.
+pub fn split_fiber(payload: &[u8]) -> Result<(u64, &[u8]), NodeError> {
+    let mut reader = Reader { bytes: payload, at: 0 };
+    let fiber = reader.u64()?;
+    Ok((fiber, &payload[reader.at..]))
+}
+
+/// Node ids with the addresses they listen on, each address prefixed with
+/// its length.
+pub fn encode_members(members: &[(NodeId, SocketAddr)]) -> Vec<u8> {
+    let mut bytes = (members.len() as u16).to_be_bytes().to_vec();
+    for (node, addr) in members {
+        let addr = addr.to_string();
+        bytes.extend(node.to_be_bytes());
+        bytes.push(addr.len() as u8);
+        bytes.extend(addr.as_bytes());
+    }
+    bytes
+}
+
+pub fn decode_members(bytes: &[u8]) -> Result<Vec<(NodeId, SocketAddr)>, NodeError> {
+    let mut reader = Reader { bytes, at: 0 };
+    (0..reader.u16()?).map(|_| {
+        let node = reader.u16()?;
+        let len = reader.u8()? as usize;
+        let addr = std::str::from_utf8(reader.take(len)?).ok()
+            .and_then(|addr| addr.parse().ok())
+            .ok_or_else(|| NodeError::Frame(format!("bad address of node {}", node)))?;
+        Ok((node, addr))
+    }).collect()
+}
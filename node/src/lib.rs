@@ -0,0 +1,5 @@
+pub mod config;
+pub mod error;
+pub mod frame;
+pub mod membership;
+pub mod node;
@@ -0,0 +1,182 @@
+use std::{collections::{BTreeMap, BTreeSet}, net::SocketAddr, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::{Duration, Instant}};
+
+use machine::fiber::actor::NodeId;
+
+use crate::config::NodeConfig;
+
+/// pushes how many members are alive, the node itself included
+pub const SYSCALL_MEMBERS: u64 = 0x6d00;
+/// pops a node id and pushes its `Status` code, 0 for nodes never heard of
+pub const SYSCALL_STATUS: u64 = 0x6d01;
+/// has the calling fiber told about every `Change` from then on
+pub const SYSCALL_SUBSCRIBE: u64 = 0x6d02;
+
+/// Time as the failure detector sees it, only differences matter.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Duration;
+}
+
+/// the wall clock, counting from when it was made
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self { start: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the time, so one
+/// handle can drive every node of a test.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Alive = 0x01,
+    /// quiet for longer than `NodeConfig::suspect_after`
+    Suspected = 0x02,
+    /// said goodbye, or quiet for longer than `NodeConfig::down_after`
+    Left = 0x03,
+}
+
+/// What subscribers hear. It arrives as an Int from fiber 0 of the node it
+/// is about, so RECV leaves the code under that node's actor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// first heard of, or heard from again after being suspected or left
+    Joined = 0x01,
+    Suspected = 0x02,
+    Left = 0x03,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub node: NodeId,
+    /// where it listens, once it said so
+    pub addr: Option<SocketAddr>,
+    pub status: Status,
+    /// by the node's clock
+    pub last_heard: Duration,
+}
+
+/// The nodes a node knows of, itself included, and the fibers waiting to
+/// hear about changes.
+#[derive(Debug)]
+pub struct Membership {
+    id: NodeId,
+    members: BTreeMap<NodeId, Member>,
+    subscribers: BTreeSet<u64>,
+    last_beat: Option<Duration>,
+}
+
+impl Membership {
+    pub fn new(id: NodeId, addr: SocketAddr, now: Duration) -> Self {
+        let own = Member { node: id, addr: Some(addr), status: Status::Alive, last_heard: now };
+        Self { id, members: BTreeMap::from([(id, own)]), subscribers: BTreeSet::new(), last_beat: None }
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &Member> {
+        self.members.values()
+    }
+
+    pub fn member(&self, node: NodeId) -> Option<&Member> {
+        self.members.get(&node)
+    }
+
+    pub fn alive(&self) -> usize {
+        self.members.values().filter(|member| member.status == Status::Alive).count()
+    }
+
+    /// Records a sign of life. Returns `Joined` for a node that is new or
+    /// had been given up on.
+    pub fn heard(&mut self, node: NodeId, now: Duration) -> Option<Change> {
+        let member = self.members.entry(node)
+            .or_insert(Member { node, addr: None, status: Status::Left, last_heard: now });
+        member.last_heard = now;
+        let changed = member.status != Status::Alive;
+        member.status = Status::Alive;
+        changed.then_some(Change::Joined)
+    }
+
+    /// Where a member listens, kept from the first time it is told. Returns
+    /// whether that was news.
+    pub fn learned(&mut self, node: NodeId, addr: SocketAddr) -> bool {
+        match self.members.get_mut(&node) {
+            Some(member) if member.addr.is_none() => {
+                member.addr = Some(addr);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// A member saying goodbye.
+    pub fn left(&mut self, node: NodeId) -> Option<Change> {
+        let member = self.members.get_mut(&node).filter(|member| member.node != self.id)?;
+        let changed = member.status != Status::Left;
+        member.status = Status::Left;
+        changed.then_some(Change::Left)
+    }
+
+    /// Suspects or gives up on the members that have been quiet for too long.
+    pub fn detect(&mut self, now: Duration, config: &NodeConfig) -> Vec<(NodeId, Change)> {
+        let mut changes = Vec::new();
+        for member in self.members.values_mut().filter(|member| member.node != self.id) {
+            let quiet = now.saturating_sub(member.last_heard);
+            let change = match member.status {
+                Status::Alive | Status::Suspected if quiet >= config.down_after => Change::Left,
+                Status::Alive if quiet >= config.suspect_after => Change::Suspected,
+                _ => continue,
+            };
+            member.status = match change {
+                Change::Left => Status::Left,
+                _ => Status::Suspected,
+            };
+            changes.push((member.node, change));
+        }
+        changes
+    }
+
+    /// whether the next heartbeat is due, it counts as sent once it is
+    pub fn beat(&mut self, now: Duration, interval: Duration) -> bool {
+        let due = self.last_beat.is_none_or(|last| now.saturating_sub(last) >= interval);
+        if due {
+            self.last_beat = Some(now);
+        }
+        due
+    }
+
+    pub fn subscribe(&mut self, fiber: u64) {
+        self.subscribers.insert(fiber);
+    }
+
+    pub fn unsubscribe(&mut self, fiber: u64) {
+        self.subscribers.remove(&fiber);
+    }
+
+    pub fn subscribers(&self) -> impl Iterator<Item = u64> + '_ {
+        self.subscribers.iter().copied()
+    }
+}
@@ -0,0 +1,413 @@
+use std::{collections::{HashMap, HashSet}, net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs}, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, mpsc::{self, Receiver, RecvTimeoutError, Sender}, Arc, Mutex, MutexGuard}, thread, time::{Duration, Instant}};
+
+use machine::{execptions::Fault, fiber::{actor::{ActorId, Failure, NodeId}, fiber::{FiberState, Message, Rpc}, value::{Tag, Value}}, machine::{machine::{Machine, Outgoing}, native::{Outcome, Signature, Slot}}};
+
+use crate::{config::NodeConfig, error::NodeError, frame::{decode_members, decode_value, encode_members, encode_value, split_fiber, Envelope, Hello, Kind}, membership::{Change, Member, Membership, Status, SYSCALL_MEMBERS, SYSCALL_STATUS, SYSCALL_SUBSCRIBE}};
+
+/// how long a peer gets to say hello
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// what the network threads hand to the node
+enum Event {
+    Connected(NodeId),
+    Frame(Envelope),
+    Disconnected { node: NodeId, connection: u64 },
+}
+
+/// a handshaken connection, written to by the node and read by its own thread
+struct Peer {
+    connection: u64,
+    /// locked on its own, so a slow peer only holds up frames to itself
+    writer: Arc<Mutex<TcpStream>>,
+    /// for shutting the connection down while a write may be stuck
+    stream: TcpStream,
+}
+
+/// state the node shares with its network threads
+#[derive(Default)]
+struct Shared {
+    peers: Mutex<HashMap<NodeId, Peer>>,
+    connections: AtomicU64,
+    closed: AtomicBool,
+    write_timeout: Option<Duration>,
+    /// nodes a background thread is connecting to
+    dialing: Mutex<HashSet<NodeId>>,
+}
+
+impl Shared {
+    fn peers(&self) -> MutexGuard<'_, HashMap<NodeId, Peer>> {
+        self.peers.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn dialing(&self) -> MutexGuard<'_, HashSet<NodeId>> {
+        self.dialing.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Connects to `node` from its own thread, so an address that never
+    /// answers can't hold up the caller. The connection reaches the node
+    /// as `Event::Connected` like an accepted one; a failed dial is
+    /// dropped and retried when the node is heard of again.
+    fn dial(self: &Arc<Self>, node: NodeId, addr: SocketAddr, hello: Hello, events: Sender<Event>) {
+        if !self.dialing().insert(node) {
+            return;
+        }
+        let shared = self.clone();
+        thread::spawn(move || {
+            if let Ok(stream) = TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT) {
+                let _ = shared.join(stream, hello, events);
+            }
+            shared.dialing().remove(&node);
+        });
+    }
+
+    /// Says hello both ways and starts reading frames from the peer.
+    /// Returns the peer's node id.
+    fn join(self: &Arc<Self>, mut stream: TcpStream, hello: Hello, events: Sender<Event>) -> Result<NodeId, NodeError> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        hello.write(&mut stream)?;
+        let peer = Hello::read(&mut stream)?;
+        hello.check(&peer)?;
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(self.write_timeout)?;
+
+        let connection = self.connections.fetch_add(1, Ordering::Relaxed);
+        let mut reader = stream.try_clone()?;
+        let writer = Arc::new(Mutex::new(stream.try_clone()?));
+        // a newer connection to the same node replaces the older one
+        if let Some(old) = self.peers().insert(peer.node, Peer { connection, writer, stream }) {
+            let _ = old.stream.shutdown(Shutdown::Both);
+        }
+        let _ = events.send(Event::Connected(peer.node));
+        thread::spawn(move || {
+            // frames claiming to come from another node end the connection too
+            while let Ok(envelope) = Envelope::read(&mut reader) && envelope.source == peer.node {
+                if events.send(Event::Frame(envelope)).is_err() {
+                    break;
+                }
+            }
+            let _ = reader.shutdown(Shutdown::Both);
+            let _ = events.send(Event::Disconnected { node: peer.node, connection });
+        });
+        Ok(peer.node)
+    }
+}
+
+/// A machine reachable over TCP under a node id. Network threads only
+/// decode frames, everything touching the machine happens in `step`.
+pub struct Node {
+    id: NodeId,
+    machine: Machine,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    events: Receiver<Event>,
+    sender: Sender<Event>,
+    config: NodeConfig,
+    /// shared with the membership syscalls
+    membership: Arc<Mutex<Membership>>,
+}
+
+fn lock(membership: &Mutex<Membership>) -> MutexGuard<'_, Membership> {
+    membership.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl Node {
+    /// Listens on `addr` and accepts peers in the background. Port 0 picks
+    /// a free one, see `local_addr`. The node goes by the machine's
+    /// `MachineConfig::node`, and carries the messages its fibers SEND to
+    /// other nodes from then on.
+    pub fn bind(addr: impl ToSocketAddrs, machine: Machine) -> Result<Self, NodeError> {
+        Self::bind_with(addr, machine, NodeConfig::default())
+    }
+
+    /// Like `bind`, with heartbeats and failure detection as configured.
+    /// Registers the membership syscalls on the machine.
+    pub fn bind_with(addr: impl ToSocketAddrs, mut machine: Machine, config: NodeConfig) -> Result<Self, NodeError> {
+        let id = machine.node();
+        machine.set_routing(true);
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared { write_timeout: Some(config.write_timeout), ..Default::default() });
+        let (sender, events) = mpsc::channel();
+        let (accepting, accepted) = (shared.clone(), sender.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accepting.closed.load(Ordering::Relaxed) {
+                    break;
+                }
+                // a peer failing its handshake doesn't concern anyone else
+                if let Ok(stream) = stream {
+                    let (shared, events) = (accepting.clone(), accepted.clone());
+                    thread::spawn(move || shared.join(stream, Hello::new(id), events));
+                }
+            }
+        });
+        let membership = Arc::new(Mutex::new(Membership::new(id, addr, config.clock.now())));
+        let view = membership.clone();
+        machine.register_native(SYSCALL_MEMBERS, Signature::new(&[], &[Slot::Stack(Tag::Int)]), move |_| {
+            Ok(Outcome::Return(vec![Value::Int(lock(&view).alive() as i64)]))
+        });
+        let view = membership.clone();
+        machine.register_native(SYSCALL_STATUS, Signature::new(&[Slot::Stack(Tag::Int)], &[Slot::Stack(Tag::Int)]), move |call| {
+            let status = match call.args[0] {
+                Value::Int(node) => NodeId::try_from(node).ok()
+                    .and_then(|node| lock(&view).member(node).map(|member| member.status as i64)),
+                _ => None,
+            };
+            Ok(Outcome::Return(vec![Value::Int(status.unwrap_or(0))]))
+        });
+        let view = membership.clone();
+        machine.register_native(SYSCALL_SUBSCRIBE, Signature::new(&[], &[]), move |call| {
+            lock(&view).subscribe(call.fiber);
+            Ok(Outcome::Return(vec![]))
+        });
+        Ok(Self { id, machine, addr, shared, events, sender, config, membership })
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Connects to the node listening on `addr`, returns its id.
+    pub fn connect(&mut self, addr: impl ToSocketAddrs) -> Result<NodeId, NodeError> {
+        let stream = TcpStream::connect(addr)?;
+        self.shared.join(stream, Hello::new(self.id), self.sender.clone())
+    }
+
+    /// Connects to `seed`, a node of the cluster to join, and returns its
+    /// id. Members are exchanged and connections to the rest are made over
+    /// the following steps.
+    pub fn join(&mut self, seed: impl ToSocketAddrs) -> Result<NodeId, NodeError> {
+        self.connect(seed)
+    }
+
+    /// every node this one has heard of, itself included, by id
+    pub fn members(&self) -> Vec<Member> {
+        lock(&self.membership).members().cloned().collect()
+    }
+
+    pub fn status(&self, node: NodeId) -> Option<Status> {
+        lock(&self.membership).member(node).map(|member| member.status)
+    }
+
+    /// Has the fiber told about joins, suspicions and leaves from now on,
+    /// like `SYSCALL_SUBSCRIBE` does from bytecode.
+    pub fn subscribe(&mut self, fiber: u64) {
+        lock(&self.membership).subscribe(fiber);
+    }
+
+    /// nodes with a live connection, in no particular order
+    pub fn peers(&self) -> Vec<NodeId> {
+        self.shared.peers().keys().copied().collect()
+    }
+
+    /// Writes the envelope to the node's connection. A write that fails or
+    /// times out leaves half a frame behind, so the connection is dropped.
+    pub fn send_envelope(&self, node: NodeId, envelope: &Envelope) -> Result<(), NodeError> {
+        let writer = self.shared.peers().get(&node).map(|peer| peer.writer.clone()).ok_or(NodeError::UnknownNode(node))?;
+        let mut stream = writer.lock().unwrap_or_else(|err| err.into_inner());
+        let res = envelope.write(&mut *stream);
+        if res.is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        res
+    }
+
+    /// Puts `value` into the mailbox of `fiber` on `node`, as sent by the host.
+    pub fn send(&self, node: NodeId, fiber: u64, value: &Value) -> Result<(), NodeError> {
+        self.send_outgoing(&Outgoing { from: ActorId::new(self.id, 0), to: ActorId::new(node, fiber), value: value.clone(), rpc: None })
+    }
+
+    fn send_outgoing(&self, outgoing: &Outgoing) -> Result<(), NodeError> {
+        let (kind, request) = match outgoing.rpc {
+            None => (Kind::Message, 0),
+            Some(Rpc::Request(request)) => (Kind::Request, request),
+            Some(Rpc::Reply(request)) => (Kind::Reply, request),
+            Some(Rpc::Fault(request)) => (Kind::Fault, request),
+        };
+        let mut payload = outgoing.from.local.to_be_bytes().to_vec();
+        payload.extend(encode_value(&outgoing.value)?);
+        let envelope = Envelope { source: self.id, fiber: outgoing.to.local, kind, request, payload };
+        self.send_envelope(outgoing.to.node, &envelope)
+    }
+
+    /// Carries what the fibers sent to other nodes. A node that can't be
+    /// reached bounces the message right away.
+    fn flush(&mut self) {
+        for outgoing in self.machine.take_outgoing() {
+            match self.send_outgoing(&outgoing) {
+                Ok(()) => {},
+                // references don't leave the machine, nobody could read them
+                Err(NodeError::Frame(_)) => {},
+                Err(_) => self.machine.bounce(outgoing, Failure::UnknownNode),
+            }
+        }
+    }
+
+    /// Posts the changes to every subscriber, forgetting the ones that are gone.
+    fn notify(&mut self, changes: &[(NodeId, Change)]) {
+        if changes.is_empty() {
+            return;
+        }
+        let subscribers: Vec<u64> = lock(&self.membership).subscribers().collect();
+        for fiber in subscribers {
+            for (node, change) in changes {
+                let message = Message { from: ActorId::new(*node, 0), value: Value::Int(*change as i64), failure: None, rpc: None };
+                if self.machine.deliver(fiber, message).is_err() {
+                    lock(&self.membership).unsubscribe(fiber);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn broadcast(&self, kind: Kind, payload: Vec<u8>) {
+        let envelope = Envelope { source: self.id, fiber: 0, kind, request: 0, payload };
+        for node in self.peers() {
+            // a broken connection shows up as a disconnect soon enough
+            let _ = self.send_envelope(node, &envelope);
+        }
+    }
+
+    /// tells every peer about the members that haven't left
+    fn gossip(&self) {
+        let members: Vec<_> = lock(&self.membership).members()
+            .filter(|member| member.status != Status::Left)
+            .filter_map(|member| member.addr.map(|addr| (member.node, addr)))
+            .collect();
+        self.broadcast(Kind::Members, encode_members(&members));
+    }
+
+    /// Sends the heartbeat when it is due and checks on the other members.
+    fn tick(&mut self) {
+        let now = self.config.clock.now();
+        let (beat, changes) = {
+            let mut membership = lock(&self.membership);
+            (membership.beat(now, self.config.heartbeat), membership.detect(now, &self.config))
+        };
+        if beat {
+            self.broadcast(Kind::Heartbeat, Vec::new());
+        }
+        self.notify(&changes);
+    }
+
+    fn handle(&mut self, event: Event) {
+        let now = self.config.clock.now();
+        // anything but a goodbye is a sign of life
+        if let Event::Connected(node) | Event::Frame(Envelope { source: node, .. }) = &event
+            && !matches!(&event, Event::Frame(Envelope { kind: Kind::Leave, .. })) {
+            let change = lock(&self.membership).heard(*node, now);
+            if let Some(change) = change {
+                self.notify(&[(*node, change)]);
+            }
+        }
+        match event {
+            Event::Connected(_) => self.gossip(),
+            Event::Frame(envelope) => match envelope.kind {
+                Kind::Heartbeat => {},
+                Kind::Members => if let Ok(members) = decode_members(&envelope.payload) {
+                    let peers = self.peers();
+                    let mut news = false;
+                    for (node, addr) in members {
+                        news |= lock(&self.membership).learned(node, addr);
+                        // of two nodes hearing of each other the lower one connects
+                        if node > self.id && !peers.contains(&node) {
+                            self.shared.dial(node, addr, Hello::new(self.id), self.sender.clone());
+                        }
+                    }
+                    // passed on until everyone knows where everyone listens
+                    if news {
+                        self.gossip();
+                    }
+                },
+                Kind::Leave => {
+                    let change = lock(&self.membership).left(envelope.source);
+                    if let Some(change) = change {
+                        self.notify(&[(envelope.source, change)]);
+                    }
+                },
+                Kind::Message | Kind::Request | Kind::Reply | Kind::Fault => if let Ok((from, value)) = split_fiber(&envelope.payload)
+                    && let Ok(value) = decode_value(value) {
+                    let rpc = match envelope.kind {
+                        Kind::Request => Some(Rpc::Request(envelope.request)),
+                        Kind::Reply => Some(Rpc::Reply(envelope.request)),
+                        Kind::Fault => Some(Rpc::Fault(envelope.request)),
+                        _ => None,
+                    };
+                    let message = Message { from: ActorId::new(envelope.source, from), value, failure: None, rpc };
+                    // answers to callers that are gone go nowhere
+                    if self.machine.deliver(envelope.fiber, message).is_err() && matches!(envelope.kind, Kind::Message | Kind::Request) {
+                        let mut payload = envelope.fiber.to_be_bytes().to_vec();
+                        payload.push(Failure::DeadActor as u8);
+                        payload.extend(&envelope.payload[8..]);
+                        let bounce = Envelope { source: self.id, fiber: from, kind: Kind::Undeliverable, request: envelope.request, payload };
+                        // with the sender's node gone there is nobody left to tell
+                        let _ = self.send_envelope(envelope.source, &bounce);
+                    }
+                },
+                Kind::Undeliverable => if let Ok((to, rest)) = split_fiber(&envelope.payload)
+                    && let Some((&code, value)) = rest.split_first()
+                    && let Some(failure) = Failure::from_u8(code)
+                    && let Ok(value) = decode_value(value) {
+                    let from = ActorId::new(self.id, envelope.fiber);
+                    let rpc = (envelope.request != 0).then_some(Rpc::Request(envelope.request));
+                    self.machine.bounce(Outgoing { from, to: ActorId::new(envelope.source, to), value, rpc }, failure);
+                },
+            },
+            Event::Disconnected { node, connection } => {
+                let mut peers = self.shared.peers();
+                if peers.get(&node).is_some_and(|peer| peer.connection == connection) {
+                    peers.remove(&node);
+                }
+            },
+        }
+    }
+
+    /// Hands what arrived from the network to the machine, sends the
+    /// heartbeat if it is due and checks on the other members, then gives
+    /// every fiber one slice. While no fiber can run it waits up to `timeout`
+    /// for the network first, or until the next CALLR times out. Returns
+    /// whether any fiber is left.
+    pub fn step(&mut self, timeout: Duration) -> Result<bool, Fault> {
+        let runnable = self.machine.fibers().any(|fiber| matches!(fiber.state(), FiberState::RUNNING | FiberState::BLOCKED));
+        if !runnable {
+            let timeout = self.machine.next_deadline()
+                .map_or(timeout, |deadline| timeout.min(deadline.saturating_duration_since(Instant::now())));
+            match self.events.recv_timeout(timeout) {
+                Ok(event) => self.handle(event),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => {},
+            }
+        }
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event);
+        }
+        self.tick();
+        let res = self.machine.schedule();
+        self.flush();
+        res
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        self.broadcast(Kind::Leave, Vec::new());
+        for peer in self.shared.peers().values() {
+            let _ = peer.stream.shutdown(Shutdown::Both);
+        }
+        // wakes the accepting thread so it sees the node is gone
+        let _ = TcpStream::connect(self.addr);
+    }
+}
@@ -0,0 +1,236 @@
+#[cfg(test)]
+pub mod tests {
+    use std::{io::{Cursor, Read}, net::{TcpListener, TcpStream}, thread, time::{Duration, Instant}};
+
+    use machine::{execptions::MachineError, fiber::{actor::{ActorId, Failure, NodeId}, fiber::{FiberState, Reg}, value::Value}, machine::{config::MachineConfig, device::OutputBuffer, machine::Machine}, opcode::{assembler::assemble, opcodes::ISA_VERSION}};
+    use node::{config::NodeConfig, error::NodeError, frame::{decode_value, encode_value, Envelope, Hello, Kind, MAX_FRAME, PROTOCOL_VERSION}, node::Node};
+
+    fn node(id: NodeId) -> Node {
+        let machine = Machine::with_config(MachineConfig {
+            typed_stack: true,
+            keep_halted: true,
+            node: id,
+            ..Default::default()
+        }).unwrap();
+        Node::bind("127.0.0.1:0", machine).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[&str]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    /// steps every node until `done` holds, failing after a few seconds
+    fn run_until(nodes: &mut [&mut Node], done: impl Fn(&[&mut Node]) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(nodes) {
+            assert!(Instant::now() < deadline, "timed out");
+            for node in nodes.iter_mut() {
+                node.step(Duration::from_millis(5)).unwrap();
+            }
+        }
+    }
+
+    fn halted(node: &Node, fid: u64) -> bool {
+        node.machine().fiber(fid).unwrap().state() == FiberState::HALTED
+    }
+
+    #[test]
+    fn frames() {
+        let envelope = Envelope { source: 1, fiber: 2, kind: Kind::Message, request: 3, payload: vec![4, 5] };
+        let mut wire = Vec::new();
+        envelope.write(&mut wire).unwrap();
+        assert_eq!(&wire[..4], &(19 + 2u32).to_be_bytes());
+        assert_eq!(Envelope::read(&mut Cursor::new(&wire)).unwrap(), envelope);
+
+        let mut huge = ((MAX_FRAME + 1) as u32).to_be_bytes().to_vec();
+        huge.extend([0; 32]);
+        assert!(matches!(Envelope::read(&mut Cursor::new(huge)), Err(NodeError::Frame(_))));
+
+        for value in [Value::Int(-3), Value::Float(1.5), Value::Bool(true), Value::Str("hé".to_string()),
+            Value::Bytes(vec![0, 255]), Value::Actor(ActorId::new(3, 7)), Value::Null] {
+            assert_eq!(decode_value(&encode_value(&value).unwrap()).unwrap(), value);
+        }
+        assert!(encode_value(&Value::Ref(16)).is_err());
+        assert!(decode_value(&[0x03, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn message_over_loopback() {
+        let (mut a, mut b) = (node(0xa), node(0xb));
+        let stdout = OutputBuffer::new();
+        b.machine_mut().set_stdout(stdout.clone());
+        let fid = b.machine_mut().spawn().unwrap();
+        load(b.machine_mut(), fid, &["RECV", "DROP", "OUT 1", "HLT"]);
+
+        assert_eq!(a.connect(b.local_addr()).unwrap(), 0xb);
+        a.send(0xb, fid, &Value::Str("over tcp".to_string())).unwrap();
+        run_until(&mut [&mut a, &mut b], |nodes| halted(nodes[1], fid));
+        assert_eq!(stdout.bytes(), b"over tcp");
+        assert_eq!(b.peers(), vec![0xa]);
+        assert!(matches!(a.send(0xc, fid, &Value::Null), Err(NodeError::UnknownNode(0xc))));
+    }
+
+    #[test]
+    fn three_nodes() {
+        let mut nodes = [node(1), node(2), node(3)];
+        let mut fibers = Vec::new();
+        for node in &mut nodes {
+            let fid = node.machine_mut().spawn().unwrap();
+            load(node.machine_mut(), fid, &["RECV", "DROP", "POP R0", "RECV", "DROP", "POP R1", "HLT"]);
+            fibers.push(fid);
+        }
+        let addrs: Vec<_> = nodes.iter().map(|node| node.local_addr()).collect();
+        nodes[0].connect(addrs[1]).unwrap();
+        nodes[0].connect(addrs[2]).unwrap();
+        nodes[1].connect(addrs[2]).unwrap();
+        let [a, b, c] = &mut nodes;
+        run_until(&mut [&mut *a, &mut *b, &mut *c], |nodes| nodes.iter().all(|node| node.peers().len() == 2));
+
+        // every node sends its id times ten to the other two
+        for (idx, node) in nodes.iter().enumerate() {
+            for (other, fid) in fibers.iter().enumerate().filter(|(other, _)| *other != idx) {
+                node.send(other as NodeId + 1, *fid, &Value::Int(node.id() as i64 * 10)).unwrap();
+            }
+        }
+        let [a, b, c] = &mut nodes;
+        run_until(&mut [&mut *a, &mut *b, &mut *c], |nodes| nodes.iter().zip(&fibers).all(|(node, fid)| halted(node, *fid)));
+        for (idx, node) in nodes.iter().enumerate() {
+            let regs = node.machine().fiber(fibers[idx]).unwrap().registers();
+            assert_eq!(regs.get(Reg::R0) + regs.get(Reg::R1), 60 - (idx as u64 + 1) * 10);
+        }
+    }
+
+    /// sends 5 to the actor from the host, keeps what comes back in R0 and
+    /// R1 and sets R2 when RECV says it is a failure
+    const SENDER: &[&str] = &[
+        "RECV", "DROP", "PUSH 5", "SWP", "SEND", "RECV", "JZ #26", "POP R1", "POP R0", "HLT",
+        "INC R2", "JMP #1e",
+    ];
+
+    #[test]
+    fn remote_actors() {
+        let (mut a, mut b) = (node(1), node(2));
+        // doubles whatever it gets and sends it back
+        let echo = b.machine_mut().spawn().unwrap();
+        load(b.machine_mut(), echo, &["RECV", "SWP", "DUP", "ADD", "SWP", "SEND", "JMP 0"]);
+        let dead = b.machine_mut().spawn().unwrap();
+        load(b.machine_mut(), dead, &["HLT"]);
+        run_until(&mut [&mut b], |nodes| halted(nodes[0], dead));
+
+        let mut senders = Vec::new();
+        for target in [ActorId::new(2, echo), ActorId::new(2, dead), ActorId::new(3, 1)] {
+            let fid = a.machine_mut().spawn().unwrap();
+            load(a.machine_mut(), fid, SENDER);
+            a.machine_mut().send(fid, Value::Actor(target)).unwrap();
+            senders.push(fid);
+        }
+        a.connect(b.local_addr()).unwrap();
+        run_until(&mut [&mut a, &mut b], |nodes| senders.iter().all(|fid| halted(nodes[0], *fid)));
+
+        let regs: Vec<_> = senders.iter().map(|fid| a.machine().fiber(*fid).unwrap().registers()).collect();
+        assert_eq!(regs[0].get(Reg::R0), 10);
+        assert_eq!(regs[0].get(Reg::R1), ActorId::new(2, echo).to_bits());
+        assert_eq!(regs[0].get(Reg::R2), 0);
+        assert_eq!(regs[1].get(Reg::R0), Failure::DeadActor as u64);
+        assert_eq!(regs[1].get(Reg::R1), ActorId::new(2, dead).to_bits());
+        assert_eq!(regs[1].get(Reg::R2), 1);
+        // nobody listens on node 3
+        assert_eq!(regs[2].get(Reg::R0), Failure::UnknownNode as u64);
+        assert_eq!(regs[2].get(Reg::R1), ActorId::new(3, 1).to_bits());
+        assert_eq!(regs[2].get(Reg::R2), 1);
+    }
+
+    #[test]
+    fn remote_calls() {
+        let (mut a, mut b) = (node(1), node(2));
+        let doubler = b.machine_mut().spawn().unwrap();
+        load(b.machine_mut(), doubler, &["RECV", "DROP", "DUP", "ADD", "REPLY", "JMP 0"]);
+        let thrower = b.machine_mut().spawn().unwrap();
+        load(b.machine_mut(), thrower, &["RECV", "DROP", "DROP", "THROW #7"]);
+        let silent = b.machine_mut().spawn().unwrap();
+        load(b.machine_mut(), silent, &["RECV", "DROP", "DROP", "RECV"]);
+
+        // calls the actor from the host with the number after it, keeps the
+        // reply in R1 or the fault code in R0
+        let client = ["TRY #23", "RECV", "DROP", "RECV", "DROP", "SWP", "CALLR 50", "POP R1", "HLT", "POP R0", "HLT"];
+        let calls = [(doubler, 1), (doubler, 2), (doubler, 3), (thrower, 4), (silent, 5)];
+        let mut callers = Vec::new();
+        for (server, arg) in calls {
+            let fid = a.machine_mut().spawn().unwrap();
+            load(a.machine_mut(), fid, &client);
+            a.machine_mut().send(fid, Value::Actor(ActorId::new(2, server))).unwrap();
+            a.machine_mut().send(fid, Value::Int(arg)).unwrap();
+            callers.push(fid);
+        }
+        a.connect(b.local_addr()).unwrap();
+        run_until(&mut [&mut a, &mut b], |nodes| callers.iter().all(|fid| halted(nodes[0], *fid)));
+
+        let regs: Vec<_> = callers.iter().map(|fid| a.machine().fiber(*fid).unwrap().registers()).collect();
+        for (idx, arg) in [1, 2, 3].into_iter().enumerate() {
+            assert_eq!((regs[idx].get(Reg::R0), regs[idx].get(Reg::R1)), (0, arg * 2));
+        }
+        assert_eq!(regs[3].get(Reg::R0), MachineError::RemoteFault(None).code());
+        assert_eq!(regs[4].get(Reg::R0), MachineError::CallTimeout.code());
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let b = node(0xb);
+        // a peer on another ISA gets the node's hello and is hung up on
+        let mut stream = TcpStream::connect(b.local_addr()).unwrap();
+        Hello { isa: ISA_VERSION + 1, ..Hello::new(0xa) }.write(&mut stream).unwrap();
+        assert_eq!(Hello::read(&mut stream).unwrap(), Hello::new(0xb));
+        assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+        assert!(b.peers().is_empty());
+
+        // and connecting to one fails
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            Hello { protocol: PROTOCOL_VERSION + 1, ..Hello::new(0xc) }.write(&mut stream).unwrap();
+            let _ = Hello::read(&mut stream);
+        });
+        let mut a = node(0xa);
+        match a.connect(addr) {
+            Err(NodeError::Handshake(detail)) => assert!(detail.contains("protocol version"), "{}", detail),
+            res => panic!("connected anyway: {:?}", res),
+        }
+        assert!(a.peers().is_empty());
+    }
+
+    #[test]
+    fn stalled_peer() {
+        let machine = Machine::with_config(MachineConfig { node: 0xa, ..Default::default() }).unwrap();
+        let config = NodeConfig { write_timeout: Duration::from_millis(100), ..Default::default() };
+        let mut a = Node::bind_with("127.0.0.1:0", machine, config).unwrap();
+        let mut b = node(0xb);
+        a.connect(b.local_addr()).unwrap();
+        // a peer that says hello and then never reads
+        let mut stalled = TcpStream::connect(a.local_addr()).unwrap();
+        Hello::new(0xc).write(&mut stalled).unwrap();
+        Hello::read(&mut stalled).unwrap();
+        run_until(&mut [&mut a, &mut b], |nodes| nodes[0].peers().len() == 2);
+
+        // writes to it give up once its buffers are full, and its connection goes
+        let envelope = Envelope { source: 0xa, fiber: 0, kind: Kind::Message, request: 0, payload: vec![0; 1024 * 1024] };
+        let start = Instant::now();
+        while a.send_envelope(0xc, &envelope).is_ok() {
+            assert!(start.elapsed() < Duration::from_secs(30), "never blocked");
+        }
+        run_until(&mut [&mut a, &mut b], |nodes| nodes[0].peers() == vec![0xb]);
+
+        // the other peer never noticed
+        let stdout = OutputBuffer::new();
+        b.machine_mut().set_stdout(stdout.clone());
+        let fid = b.machine_mut().spawn().unwrap();
+        load(b.machine_mut(), fid, &["RECV", "DROP", "OUT 1", "HLT"]);
+        a.send(0xb, fid, &Value::Str("still here".to_string())).unwrap();
+        run_until(&mut [&mut a, &mut b], |nodes| halted(nodes[1], fid));
+        assert_eq!(stdout.bytes(), b"still here");
+        drop(stalled);
+    }
+}
@@ -0,0 +1,153 @@
+#[cfg(test)]
+pub mod tests {
+    use std::{net::{TcpListener, TcpStream}, sync::Arc, time::{Duration, Instant}};
+
+    use machine::{fiber::{actor::NodeId, fiber::{FiberState, Reg}}, machine::{config::MachineConfig, device::OutputBuffer, machine::Machine}, opcode::assembler::assemble};
+    use node::{config::NodeConfig, frame::{encode_members, Envelope, Hello, Kind}, membership::{ManualClock, Status}, node::Node};
+
+    const HEARTBEAT: Duration = Duration::from_millis(100);
+
+    fn node(id: NodeId, clock: &ManualClock) -> Node {
+        let machine = Machine::with_config(MachineConfig {
+            typed_stack: true,
+            keep_halted: true,
+            node: id,
+            ..Default::default()
+        }).unwrap();
+        let config = NodeConfig {
+            heartbeat: HEARTBEAT,
+            suspect_after: HEARTBEAT * 3,
+            down_after: HEARTBEAT * 6,
+            clock: Arc::new(clock.clone()),
+            ..Default::default()
+        };
+        Node::bind_with("127.0.0.1:0", machine, config).unwrap()
+    }
+
+    fn load(machine: &mut Machine, fid: u64, lines: &[&str]) {
+        for line in lines {
+            let address = machine.fiber(fid).unwrap().text_section().len() as u64;
+            machine.write_bytecodes(fid, &assemble(line, address).unwrap().bytecodes()).unwrap();
+        }
+    }
+
+    /// steps every node until `done` holds, failing after a few seconds
+    fn run_until(nodes: &mut [&mut Node], done: impl Fn(&[&mut Node]) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(nodes) {
+            assert!(Instant::now() < deadline, "timed out");
+            for node in nodes.iter_mut() {
+                node.step(Duration::from_millis(5)).unwrap();
+            }
+        }
+    }
+
+    /// moves the clock on a heartbeat at a time, stepping only the given
+    /// nodes, until `done` holds
+    fn tick_until(clock: &ManualClock, nodes: &mut [&mut Node], done: impl Fn(&[&mut Node]) -> bool) {
+        for _ in 0..50 {
+            if done(nodes) {
+                return;
+            }
+            clock.advance(HEARTBEAT);
+            for _ in 0..4 {
+                for node in nodes.iter_mut() {
+                    node.step(Duration::from_millis(5)).unwrap();
+                }
+            }
+        }
+        panic!("never settled");
+    }
+
+    /// prints every change it hears of as its digit
+    fn subscriber(node: &mut Node, stdout: &OutputBuffer) -> u64 {
+        node.machine_mut().set_stdout(stdout.clone());
+        let fid = node.machine_mut().spawn().unwrap();
+        load(node.machine_mut(), fid, &["SYSCALL #6d02", "RECV", "DROP", "PUSH 48", "ADD", "OUT 1", "JMP #a"]);
+        node.step(Duration::from_millis(5)).unwrap();
+        fid
+    }
+
+    #[test]
+    fn seed_join() {
+        let clock = ManualClock::default();
+        let (mut a, mut b, mut c) = (node(1, &clock), node(2, &clock), node(3, &clock));
+        assert_eq!(b.join(a.local_addr()).unwrap(), 1);
+        assert_eq!(c.join(a.local_addr()).unwrap(), 1);
+        // b and c only know of each other through a
+        run_until(&mut [&mut a, &mut b, &mut c], |nodes| nodes.iter().all(|node| node.peers().len() == 2));
+        run_until(&mut [&mut a, &mut b, &mut c], |nodes| nodes.iter().all(|node| {
+            let members = node.members();
+            members.len() == 3 && members.iter().all(|member| member.status == Status::Alive && member.addr.is_some())
+        }));
+        assert_eq!(b.members().iter().map(|member| member.node).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(c.status(2), Some(Status::Alive));
+        assert_eq!(c.status(4), None);
+    }
+
+    #[test]
+    fn partition_and_heal() {
+        let clock = ManualClock::default();
+        let (mut a, mut b, mut c) = (node(1, &clock), node(2, &clock), node(3, &clock));
+        let stdout = OutputBuffer::new();
+        subscriber(&mut a, &stdout);
+        b.join(a.local_addr()).unwrap();
+        c.join(a.local_addr()).unwrap();
+        run_until(&mut [&mut a, &mut b, &mut c], |_| stdout.bytes() == b"11");
+        run_until(&mut [&mut a, &mut b, &mut c], |nodes| nodes.iter().all(|node| node.peers().len() == 2));
+
+        // c stops answering, a and b keep hearing from each other
+        tick_until(&clock, &mut [&mut a, &mut b], |_| stdout.bytes() == b"112");
+        assert_eq!(a.status(3), Some(Status::Suspected));
+        tick_until(&clock, &mut [&mut a, &mut b], |_| stdout.bytes() == b"1123");
+        assert_eq!(a.status(2), Some(Status::Alive));
+        assert_eq!(b.status(3), Some(Status::Left));
+
+        let fid = a.machine_mut().spawn().unwrap();
+        load(a.machine_mut(), fid, &["PUSH 3", "SYSCALL #6d01", "POP R0", "SYSCALL #6d00", "POP R1", "HLT"]);
+        run_until(&mut [&mut a], |nodes| nodes[0].machine().fiber(fid).unwrap().state() == FiberState::HALTED);
+        let regs = a.machine().fiber(fid).unwrap().registers();
+        assert_eq!(regs.get(Reg::R0), Status::Left as u64);
+        assert_eq!(regs.get(Reg::R1), 2);
+
+        // its heartbeats get through again
+        tick_until(&clock, &mut [&mut a, &mut b, &mut c], |_| stdout.bytes() == b"11231");
+        assert_eq!(a.status(3), Some(Status::Alive));
+    }
+
+    #[test]
+    fn graceful_leave() {
+        let clock = ManualClock::default();
+        let (mut a, mut b) = (node(1, &clock), node(2, &clock));
+        let stdout = OutputBuffer::new();
+        subscriber(&mut a, &stdout);
+        b.join(a.local_addr()).unwrap();
+        run_until(&mut [&mut a, &mut b], |_| stdout.bytes() == b"1");
+        drop(b);
+        // no time passes, so only the goodbye can tell
+        run_until(&mut [&mut a], |_| stdout.bytes() == b"13");
+        assert_eq!(a.status(2), Some(Status::Left));
+    }
+
+    #[test]
+    fn silent_member() {
+        let clock = ManualClock::default();
+        let mut a = node(1, &clock);
+        // takes connections but never says hello
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = TcpStream::connect(a.local_addr()).unwrap();
+        Hello::new(2).write(&mut peer).unwrap();
+        Hello::read(&mut peer).unwrap();
+        let members = encode_members(&[(3, silent.local_addr().unwrap())]);
+        Envelope { source: 2, fiber: 0, kind: Kind::Members, request: 0, payload: members }.write(&mut peer).unwrap();
+
+        // dialing it doesn't hold up the steps
+        let start = Instant::now();
+        run_until(&mut [&mut a], |nodes| nodes[0].peers() == vec![2]);
+        for _ in 0..10 {
+            a.step(Duration::from_millis(5)).unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(a.peers(), vec![2]);
+    }
+}
@@ -0,0 +1,211 @@
+use std::io::{self, BufRead, Write};
+
+use machine::{fiber::{fiber::{FiberState, Flag, Reg, RegisterSnapshot}, value::Tag}, machine::{config::MachineConfig, machine::Machine}, memory::hexdump::hexdump, opcode::assembler::assemble};
+
+const HELP: &str = "Type an instruction (`PUSH 5`, `MOV R0, 7`, `JNZ #a`) to append it to the
+current fiber and run until PC catches up with the end of the text.
+
+    :spawn               new fiber, becomes the current one
+    :fiber <n|id>        switch by index or hex id
+    :fibers              list fibers
+    :regs                registers and flags
+    :stack               stack slots, top last
+    :hexdump <at> <len>  dump machine memory, hex or decimal
+    :reset               fresh machine with one fiber
+    :help                this text
+    :quit";
+
+/// stops a backwards jump from spinning forever
+const MAX_STEPS: usize = 10_000;
+
+const FLAGS: [(Flag, &str); 4] = [(Flag::Zero, "Z"), (Flag::Overflow, "O"), (Flag::Negative, "N"), (Flag::Carry, "C")];
+const REGS: [Reg; 10] = [Reg::PC, Reg::SP, Reg::R0, Reg::R1, Reg::R2, Reg::R3, Reg::R4, Reg::R5, Reg::R6, Reg::R7];
+
+struct Repl {
+    machine: Machine,
+    current: u64,
+}
+
+/// what the diff is taken of
+struct State {
+    registers: RegisterSnapshot,
+    stack: Vec<(Tag, u64)>,
+}
+
+fn number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+impl Repl {
+    fn new() -> Result<Self, String> {
+        let mut machine = Machine::with_config(MachineConfig {
+            keep_halted: true,
+            ..Default::default()
+        }).map_err(|err| err.to_string())?;
+        let current = machine.spawn().map_err(|err| err.to_string())?;
+        Ok(Self { machine, current })
+    }
+
+    fn state(&self) -> Option<State> {
+        let fiber = self.machine.fiber(self.current)?;
+        Some(State {
+            registers: fiber.registers().snapshot(),
+            stack: fiber.stack_slots(self.machine.memory()).ok()?,
+        })
+    }
+
+    fn text_end(&self) -> u64 {
+        self.machine.fiber(self.current).map_or(0, |fiber| fiber.text_section().len() as u64)
+    }
+
+    fn pc(&self) -> u64 {
+        self.machine.fiber(self.current).map_or(0, |fiber| fiber.registers().get(Reg::PC))
+    }
+
+    fn instruction(&mut self, line: &str) -> Result<(), String> {
+        let instr = assemble(line, self.text_end()).map_err(|err| err.to_string())?;
+        let before = self.state().ok_or("no current fiber, :spawn one")?;
+        self.machine.write_bytecodes(self.current, &instr.bytecodes()).map_err(|err| err.to_string())?;
+
+        let mut steps = 0;
+        while self.pc() < self.text_end() && steps < MAX_STEPS {
+            steps += 1;
+            if let Err(fault) = self.machine.step(self.current) {
+                println!("fault: {}", fault);
+                break;
+            }
+            let state = self.machine.fiber(self.current).map(|fiber| fiber.state());
+            if state != Some(FiberState::RUNNING) {
+                println!("fiber is {:?}", state.unwrap());
+                break;
+            }
+        }
+        if steps == MAX_STEPS {
+            println!("stopped after {} steps", MAX_STEPS);
+        }
+        if let Some(after) = self.state() {
+            diff(&before, &after);
+        }
+        Ok(())
+    }
+
+    fn meta(&mut self, line: &str) -> Result<bool, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args[0] {
+            ":quit" | ":q" => return Ok(false),
+            ":help" => println!("{}", HELP),
+            ":spawn" => {
+                self.current = self.machine.spawn().map_err(|err| err.to_string())?;
+                println!("fiber {:x}", self.current);
+            },
+            ":fiber" => {
+                let arg = args.get(1).ok_or(":fiber needs an index or id")?;
+                let ids: Vec<u64> = self.machine.fibers().map(|fiber| fiber.id()).collect();
+                let id = match arg.parse::<usize>() {
+                    Ok(idx) if idx < ids.len() => Some(ids[idx]),
+                    _ => u64::from_str_radix(arg, 16).ok().filter(|id| ids.contains(id)),
+                };
+                self.current = id.ok_or_else(|| format!("no fiber {}", arg))?;
+                println!("fiber {:x}", self.current);
+            },
+            ":fibers" => {
+                for (idx, fiber) in self.machine.fibers().enumerate() {
+                    let marker = if fiber.id() == self.current { '*' } else { ' ' };
+                    println!("{} {} {:x} {:?} PC=#{:x}", marker, idx, fiber.id(), fiber.state(), fiber.registers().get(Reg::PC));
+                }
+            },
+            ":regs" => {
+                let state = self.state().ok_or("no current fiber")?;
+                print_registers(&state.registers);
+            },
+            ":stack" => {
+                let state = self.state().ok_or("no current fiber")?;
+                for (idx, (tag, val)) in state.stack.iter().enumerate() {
+                    println!("[{}] {} {:#x}", idx, tag, val);
+                }
+            },
+            ":hexdump" => {
+                let (Some(start), Some(len)) = (args.get(1).and_then(|arg| number(arg)), args.get(2).and_then(|arg| number(arg))) else {
+                    return Err(":hexdump needs an address and a length".to_string());
+                };
+                let end = start.saturating_add(len);
+                if end > self.machine.memory().size() {
+                    return Err(format!("memory ends at {:#x}", self.machine.memory().size()));
+                }
+                hexdump(self.machine.memory(), start..end);
+            },
+            ":reset" => {
+                *self = Self::new()?;
+                println!("fiber {:x}", self.current);
+            },
+            other => return Err(format!("unknown command {}, try :help", other)),
+        }
+        Ok(true)
+    }
+}
+
+fn print_registers(registers: &RegisterSnapshot) {
+    let regs: Vec<String> = REGS.iter().map(|reg| format!("{}={:#x}", reg, registers.get(*reg))).collect();
+    let flags: Vec<String> = FLAGS.iter().map(|(flag, name)| format!("{}={}", name, registers.flag(*flag) as u8)).collect();
+    println!("{}", regs.join(" "));
+    println!("{}", flags.join(" "));
+}
+
+fn diff(before: &State, after: &State) {
+    for reg in REGS {
+        let (old, new) = (before.registers.get(reg), after.registers.get(reg));
+        if old != new {
+            println!("  {}: {:#x} -> {:#x}", reg, old, new);
+        }
+    }
+    for (flag, name) in FLAGS {
+        let (old, new) = (before.registers.flag(flag), after.registers.flag(flag));
+        if old != new {
+            println!("  {}: {} -> {}", name, old as u8, new as u8);
+        }
+    }
+    for idx in 0..before.stack.len().max(after.stack.len()) {
+        match (before.stack.get(idx), after.stack.get(idx)) {
+            (Some(old), Some(new)) if old != new => println!("  [{}] {} {:#x} -> {} {:#x}", idx, old.0, old.1, new.0, new.1),
+            (None, Some(new)) => println!("  [{}] + {} {:#x}", idx, new.0, new.1),
+            (Some(old), None) => println!("  [{}] - {} {:#x}", idx, old.0, old.1),
+            _ => {},
+        }
+    }
+}
+
+fn main() {
+    let mut repl = match Repl::new() {
+        Ok(repl) => repl,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        },
+    };
+    println!("fiber {:x}, :help for commands", repl.current);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("#{:x}> ", repl.text_end());
+        let _ = io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        let line = line.trim();
+        let res = if line.is_empty() {
+            Ok(true)
+        } else if line.starts_with(':') {
+            repl.meta(line)
+        } else {
+            repl.instruction(line).map(|_| true)
+        };
+        match res {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(err) => println!("error: {}", err),
+        }
+    }
+}
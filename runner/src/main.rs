@@ -0,0 +1,135 @@
+use std::{fs::File, io::{self, BufWriter, Write}, process::ExitCode};
+
+use machine::{execptions::Fault, fiber::fiber::{Flag, Reg, RegisterSnapshot}, machine::{config::MachineConfig, device::Input, machine::Machine}, trace::{event::Filter, json::JsonLines}};
+
+const USAGE: &str = "usage: runner [options] <image>
+
+Loads a raw text section into a fresh machine and runs it to completion.
+
+options:
+    --memory <size>   memory in bytes, K/M/G suffixes allowed (default 16M)
+    --fuel <n>        fault after n instructions
+    --trace <path>    write every event as a JSON line, `-` for stderr
+    --dump            print registers and flags once the program stopped
+    --verify          run the verifier before executing
+    -h, --help        show this text";
+
+#[derive(Debug, Default)]
+struct Options {
+    image: String,
+    memory: Option<usize>,
+    fuel: Option<u64>,
+    trace: Option<String>,
+    dump: bool,
+    verify: bool,
+}
+
+/// `4096`, `64K`, `16M`, `1G`
+fn parse_size(arg: &str) -> Result<usize, String> {
+    let (digits, scale) = match arg.chars().last() {
+        Some('K' | 'k') => (&arg[..arg.len() - 1], 1024),
+        Some('M' | 'm') => (&arg[..arg.len() - 1], 1024 * 1024),
+        Some('G' | 'g') => (&arg[..arg.len() - 1], 1024 * 1024 * 1024),
+        _ => (arg, 1),
+    };
+    digits.parse::<usize>()
+        .ok()
+        .and_then(|size| size.checked_mul(scale))
+        .ok_or_else(|| format!("invalid size: {}", arg))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut image = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--memory" => options.memory = Some(parse_size(&value("--memory")?)?),
+            "--fuel" => {
+                let fuel = value("--fuel")?;
+                options.fuel = Some(fuel.parse().map_err(|_| format!("invalid fuel: {}", fuel))?);
+            },
+            "--trace" => options.trace = Some(value("--trace")?),
+            "--dump" => options.dump = true,
+            "--verify" => options.verify = true,
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option: {}", arg)),
+            _ if image.is_some() => return Err(format!("unexpected argument: {}", arg)),
+            _ => image = Some(arg),
+        }
+    }
+    options.image = image.ok_or("no image given")?;
+    Ok(options)
+}
+
+fn dump(snapshot: &RegisterSnapshot) {
+    println!("PC={:#x} SP={:#x}", snapshot.get(Reg::PC), snapshot.get(Reg::SP));
+    for (idx, val) in snapshot.registers[Reg::R0.index()..].iter().enumerate() {
+        println!("R{}={:#x}", idx, val);
+    }
+    println!("Z={} O={} N={} C={}",
+        snapshot.flag(Flag::Zero) as u8, snapshot.flag(Flag::Overflow) as u8,
+        snapshot.flag(Flag::Negative) as u8, snapshot.flag(Flag::Carry) as u8);
+}
+
+fn run(options: &Options) -> Result<Option<Fault>, String> {
+    let image = std::fs::read(&options.image).map_err(|err| format!("{}: {}", options.image, err))?;
+    let defaults = MachineConfig::default();
+    let memory = options.memory.unwrap_or(defaults.memory_size);
+    let mut machine = Machine::with_config(MachineConfig {
+        memory_size: memory,
+        max_memory_size: memory,
+        fuel: options.fuel,
+        verify: options.verify,
+        keep_halted: true,
+        ..defaults
+    }).map_err(|err| err.to_string())?;
+
+    if let Some(path) = &options.trace {
+        let out: Box<dyn Write + Send> = match path.as_str() {
+            "-" => Box::new(io::stderr()),
+            path => Box::new(BufWriter::new(File::create(path).map_err(|err| format!("{}: {}", path, err))?)),
+        };
+        machine.set_tracer(Some(Box::new(JsonLines::new(out, Filter::all()))));
+    }
+
+    machine.set_stdin(Input::from_reader(io::stdin()));
+
+    let fid = machine.spawn().map_err(|err| err.to_string())?;
+    let bytecodes: Vec<u64> = image.iter().flat_map(|byte| [0, *byte as u64]).collect();
+    machine.write_bytecodes(fid, &bytecodes).map_err(|err| err.to_string())?;
+
+    let res = machine.execute();
+    // dropping the tracer flushes the trace file
+    drop(machine.take_tracer());
+    if options.dump
+        && let Some(fiber) = machine.fiber(fid) {
+        dump(&fiber.registers().snapshot());
+    }
+    Ok(res.err())
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) if err.is_empty() => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        },
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        },
+    };
+    match run(&options) {
+        Ok(None) => ExitCode::SUCCESS,
+        Ok(Some(fault)) => {
+            eprintln!("fault: {}", fault);
+            ExitCode::from(1)
+        },
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(2)
+        },
+    }
+}